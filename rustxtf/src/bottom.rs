@@ -0,0 +1,275 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde_derive::Serialize;
+
+use crate::headers::{get_number, write_field, XTF_HEADER_SONAR, XTF_PING_HEADER};
+use crate::record::Ping;
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+// Altitude from the sidescan itself. The first strong return on each side is the seabed at
+// nadir: the samples are smoothed, and the first one above a level between the water column
+// and the bright returns is the pick. Once the bottom has been found the next ping looks near
+// the median of the last few picks first, so one bad pick doesn't pull the search after it,
+// and picks that jump away from their neighbours are replaced by the running median
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BottomTrackSettings {
+    pub threshold: f64, // 0 - 1, how far from the water column level to the bright level
+    pub blanking: f64,  // metres, ignore the transmit pulse and anything this close
+    pub max_jump: f64,  // metres, biggest believable change from the pings around it
+    pub window: usize,  // pings in the running median for outlier rejection
+}
+
+impl Default for BottomTrackSettings {
+    fn default() -> BottomTrackSettings {
+        BottomTrackSettings { threshold: 0.3, blanking: 1.0, max_jump: 2.0, window: 9 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BottomPick {
+    pub ping_number: u32,
+    pub detected: Option<f64>, // straight from the samples
+    pub altitude: Option<f64>, // after outlier rejection
+    pub logged: Option<f64>,   // SensorPrimaryAltitude as recorded
+}
+
+
+fn smoothed(samples: &[f32], half_width: usize) -> Vec<f64> {
+    let mut sums = vec![0.0f64; samples.len() + 1];
+    for (i, value) in samples.iter().enumerate() {
+        sums[i + 1] = sums[i] + if value.is_finite() { *value as f64 } else { 0.0 };
+    }
+    (0..samples.len())
+        .map(|i| {
+            let (start, end) = (i.saturating_sub(half_width), (i + half_width + 1).min(samples.len()));
+            (sums[end] - sums[start]) / (end - start) as f64
+        })
+        .collect()
+}
+
+
+// Range in metres of the first return in one channel's samples, searching from `start` to `end`
+// metres. None if nothing crosses the level
+pub fn first_return(samples: &[f32], slant_range: f64, start: f64, end: f64, threshold: f64) -> Option<f64> {
+    let n = samples.len();
+    if n < 4 || slant_range.is_nan() || slant_range <= 0.0 {
+        return None;
+    }
+    let spacing = slant_range / n as f64;
+    let smooth = smoothed(samples, 2);
+
+    // water column level from the quietest samples, bright level from the loudest
+    let mut sorted = smooth.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let quiet = sorted[n / 10];
+    let bright = sorted[n * 95 / 100];
+    if bright <= quiet {
+        return None;
+    }
+    let level = quiet + threshold * (bright - quiet);
+
+    let first = ((start / spacing).floor().max(0.0) as usize).min(n);
+    let last = ((end / spacing).ceil().max(0.0) as usize).min(n);
+    (first..last).find(|&i| smooth[i] >= level).map(|i| (i as f64 + 0.5) * spacing)
+}
+
+
+// Altitude for one ping from both sides. The nearer of the two returns is the one straight
+// down, the other side might be looking at a slope. `expected` narrows the search when the
+// previous ping found the bottom
+fn ping_altitude(xtf: &XtfFile, ping: &Ping, channels: (u16, u16), settings: &BottomTrackSettings, expected: Option<f64>) -> Option<f64> {
+    let mut picks = Vec::new();
+    for channel_number in [channels.0, channels.1] {
+        let Some(i) = ping.channels.iter().position(|channel| channel.channel_number == channel_number) else { continue };
+        let slant_range = get_number(&ping.channels[i].header, "SlantRange").unwrap_or(0.0);
+        let samples = xtf.samples(ping, i);
+
+        let tracked = expected.and_then(|expected| {
+            let start = (expected - settings.max_jump).max(settings.blanking);
+            first_return(&samples, slant_range, start, expected + settings.max_jump, settings.threshold)
+        });
+        let pick = tracked.or_else(|| first_return(&samples, slant_range, settings.blanking, slant_range, settings.threshold));
+        picks.extend(pick);
+    }
+    picks.into_iter().reduce(f64::min)
+}
+
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    Some(values[values.len() / 2])
+}
+
+
+// Picks for every ping using the given port and starboard channel numbers
+pub fn track_bottom(xtf: &XtfFile, channels: (u16, u16), settings: &BottomTrackSettings) -> Result<Vec<BottomPick>, Box<dyn Error>> {
+    let mut picks = Vec::new();
+    let mut recent: Vec<f64> = Vec::new(); // the last window detections
+
+    for entry in xtf.index.pings() {
+        let ping = xtf.ping(entry)?;
+        let expected = median(&mut recent.clone());
+        let detected = ping_altitude(xtf, &ping, channels, settings, expected);
+        if let Some(detected) = detected {
+            recent.push(detected);
+            if recent.len() > settings.window.max(1) {
+                recent.remove(0);
+            }
+        }
+        picks.push(BottomPick {
+            ping_number: entry.ping_number,
+            detected,
+            altitude: detected,
+            logged: get_number(&ping.header, "SensorPrimaryAltitude"),
+        });
+    }
+
+    // outliers against the running median of the detections around them, which also fills
+    // pings where nothing was detected
+    let half = settings.window.max(1) / 2;
+    for i in 0..picks.len() {
+        let range = i.saturating_sub(half)..(i + half + 1).min(picks.len());
+        let mut around: Vec<f64> = picks[range].iter().filter_map(|pick| pick.detected).collect();
+        let Some(median) = median(&mut around) else { continue };
+
+        picks[i].altitude = match picks[i].detected {
+            Some(detected) if (detected - median).abs() <= settings.max_jump => Some(detected),
+            _ => Some(median),
+        };
+    }
+
+    Ok(picks)
+}
+
+
+// Copies the file with SensorPrimaryAltitude set from the picks, in ping order. Pings without
+// an altitude keep what was logged. Returns records written
+pub fn write_altitudes<P: AsRef<Path>>(xtf: &XtfFile, picks: &[BottomPick], output: P) -> Result<usize, Box<dyn Error>> {
+    check_not_input(&output, &[&xtf.path])?;
+    let mut writer = XtfWriter::create(output, xtf.header_bytes())?;
+    let mut picks = picks.iter();
+
+    for entry in &xtf.index.entries {
+        let bytes = xtf.record_bytes(entry)?;
+        if entry.header_type != XTF_HEADER_SONAR {
+            writer.write_record(bytes)?;
+            continue;
+        }
+
+        match picks.next().and_then(|pick| pick.altitude) {
+            Some(altitude) => {
+                let mut bytes = bytes.to_vec();
+                write_field(XTF_PING_HEADER, &mut bytes, 0, "SensorPrimaryAltitude", altitude)?;
+                writer.write_record(&bytes)?;
+            }
+            None => writer.write_record(bytes)?,
+        }
+    }
+
+    writer.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{file_header, ping_record, set_samples, TestPing};
+
+    // 100 samples over 50 m, water column at 10 then seabed at 1000 from `bottom` metres out
+    fn returns(bottom: f64) -> Vec<f32> {
+        (0..100).map(|i| if i as f64 * 0.5 >= bottom { 1000.0 } else { 10.0 }).collect()
+    }
+
+    fn near(pick: Option<f64>, expected: f64) -> bool {
+        pick.is_some_and(|pick| (pick - expected).abs() <= 0.5)
+    }
+
+    #[test]
+    fn picks_where_the_smoothed_samples_cross_the_threshold() {
+        assert!(near(first_return(&returns(10.0), 50.0, 0.0, 50.0, 0.3), 10.0));
+
+        // on a ramp the threshold picks how far up it the pick is
+        let ramp: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        assert_eq!(first_return(&ramp, 50.0, 0.0, 50.0, 0.5), Some(26.75));
+        assert_eq!(first_return(&ramp, 50.0, 0.0, 50.0, 0.0), Some(5.25));
+
+        assert!(first_return(&[10.0; 100], 50.0, 0.0, 50.0, 0.3).is_none(), "nothing to find in flat samples");
+        assert!(first_return(&returns(10.0), 50.0, 0.0, 8.0, 0.3).is_none(), "nothing before 8 m");
+    }
+
+    #[test]
+    fn blanks_the_transmit_pulse() {
+        let mut samples = returns(10.0);
+        samples[..2].fill(1000.0);
+        assert!(near(first_return(&samples, 50.0, 0.0, 50.0, 0.3), 0.25));
+        assert!(near(first_return(&samples, 50.0, 2.0, 50.0, 0.3), 10.0));
+    }
+
+    // A ping whose port and starboard samples are returns(port) and returns(starboard)
+    fn ping_bytes(n: u32, port: f64, starboard: f64) -> Vec<u8> {
+        let mut bytes = ping_record(2, 100, &TestPing::numbered(n));
+        for (channel, bottom) in [port, starboard].into_iter().enumerate() {
+            let samples: Vec<u16> = returns(bottom).iter().map(|&value| value as u16).collect();
+            set_samples(&mut bytes, 100, channel, &samples);
+        }
+        bytes
+    }
+
+    fn track(bottoms: &[(f64, f64)]) -> Vec<BottomPick> {
+        let mut bytes = file_header(2);
+        for (n, &(port, starboard)) in bottoms.iter().enumerate() {
+            bytes.extend(ping_bytes(n as u32, port, starboard));
+        }
+        let xtf = XtfFile::from_bytes(bytes).unwrap();
+        track_bottom(&xtf, (0, 1), &BottomTrackSettings::default()).unwrap()
+    }
+
+    #[test]
+    fn takes_the_nearer_side_as_nadir() {
+        let picks = track(&[(10.0, 14.0), (16.0, 12.0)]);
+        assert!(near(picks[0].detected, 10.0) && near(picks[1].detected, 12.0), "{:?}", picks);
+        assert_eq!(picks[0].logged, Some(10.0));
+    }
+
+    #[test]
+    fn rejects_an_outlier_without_following_it() {
+        // the bottom return drops out on ping 7 and the first thing found is at 25 m
+        let mut bottoms = vec![(10.0, 10.0); 15];
+        bottoms[7] = (25.0, 25.0);
+        let picks = track(&bottoms);
+
+        assert!(near(picks[7].detected, 25.0), "{:?}", picks[7]);
+        assert!(near(picks[7].altitude, 10.0), "{:?}", picks[7]);
+        // the seabed is bright out past 25 m on ping 8 too, searching around the outlier would
+        // have found that
+        for pick in &picks[8..] {
+            assert!(near(pick.detected, 10.0), "{:?}", pick);
+        }
+    }
+
+    #[test]
+    fn follows_the_bottom_past_something_shallower() {
+        // a bright target at 4 - 6 m on one ping, the tracked search starts past it
+        let mut bytes = file_header(2);
+        for n in 0..5 {
+            let mut ping = ping_bytes(n, 10.0, 10.0);
+            if n == 3 {
+                let mut samples: Vec<u16> = returns(10.0).iter().map(|&value| value as u16).collect();
+                samples[8..12].fill(1000);
+                set_samples(&mut ping, 100, 0, &samples);
+                set_samples(&mut ping, 100, 1, &samples);
+            }
+            bytes.extend(ping);
+        }
+        let xtf = XtfFile::from_bytes(bytes).unwrap();
+        let picks = track_bottom(&xtf, (0, 1), &BottomTrackSettings::default()).unwrap();
+        assert!(picks.iter().all(|pick| near(pick.detected, 10.0)), "{:?}", picks);
+    }
+}
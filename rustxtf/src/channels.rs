@@ -0,0 +1,153 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::headers::{
+    write_field, CHAN_INFO_LENGTH, FILE_HEADER_LENGTH, PING_CHAN_HEADER_LENGTH, PING_HEADER_LENGTH,
+    XTF_FILE_HEADER, XTF_PING_CHAN_HEADER, XTF_PING_HEADER,
+};
+use crate::index::IndexEntry;
+use crate::record::{Ping, Record};
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::{file_header_length, XtfFile};
+
+
+// File header and chan info block for a file that only has the given channels (chan info
+// indexes, in the order they should appear). Sonar and bathymetry counts are rewritten
+pub fn subset_header_bytes(xtf: &XtfFile, channels: &[u16]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let sonar_channels = xtf.number_of_sonar_channels();
+
+    for &channel in channels {
+        if channel as usize >= xtf.channel_infos.len() {
+            return Err(format!("Channel {} not in file, it has {} channels", channel, xtf.channel_infos.len()).into());
+        }
+    }
+
+    let mut header = xtf.data[..FILE_HEADER_LENGTH].to_vec();
+    let sonar = channels.iter().filter(|&&c| (c as usize) < sonar_channels).count();
+    write_field(XTF_FILE_HEADER, &mut header, 0, "NumberOfSonarChannels", sonar as f64)?;
+    write_field(XTF_FILE_HEADER, &mut header, 0, "NumberOfBathymetryChannels", (channels.len() - sonar) as f64)?;
+
+    for &channel in channels {
+        let start = FILE_HEADER_LENGTH + channel as usize * CHAN_INFO_LENGTH;
+        header.extend_from_slice(&xtf.data[start..start + CHAN_INFO_LENGTH]);
+    }
+
+    header.resize(file_header_length(channels.len()), 0);
+    Ok(header)
+}
+
+
+// Rebuilds a ping record with only the wanted channels, renumbering ChannelNumber to the
+// channel's position in channels so it still points at the right chan info
+pub fn subset_ping(ping: &Ping, channels: &[u16]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = ping.bytes[..PING_HEADER_LENGTH].to_vec();
+    let mut kept = 0;
+
+    for (new_number, &wanted) in channels.iter().enumerate() {
+        if let Some(channel) = ping.channels.iter().find(|c| c.channel_number == wanted) {
+            let start = bytes.len();
+            let header_start = channel.data_offset - PING_CHAN_HEADER_LENGTH;
+            let end = channel.data_offset + channel.num_samples * channel.bytes_per_sample;
+
+            bytes.extend_from_slice(&ping.bytes[header_start..end]);
+            write_field(XTF_PING_CHAN_HEADER, &mut bytes, start, "ChannelNumber", new_number as f64)?;
+            kept += 1;
+        }
+    }
+
+    // keep any padding the logger put after the last channel
+    if let Some(last) = ping.channels.last() {
+        let end = last.data_offset + last.num_samples * last.bytes_per_sample;
+        bytes.extend_from_slice(&ping.bytes[end..]);
+    }
+
+    let length = bytes.len();
+    write_field(XTF_PING_HEADER, &mut bytes, 0, "NumChansToFollow", kept as f64)?;
+    write_field(XTF_PING_HEADER, &mut bytes, 0, "NumBytesThisRecord", length as f64)?;
+    Ok(bytes)
+}
+
+
+// Writes a copy of xtf holding only the given channels, other record types pass through untouched
+pub fn select_channels<P: AsRef<Path>>(xtf: &XtfFile, channels: &[u16], output: P) -> Result<usize, Box<dyn Error>> {
+    let entries: Vec<&IndexEntry> = xtf.index.entries.iter().collect();
+    write_channels(xtf, &entries, channels, output)
+}
+
+
+// Same as select_channels but only for the given records, so it can follow a range extraction.
+// Channels keep their original order so sonar chan infos still come before bathymetry ones
+pub fn write_channels<P: AsRef<Path>>(xtf: &XtfFile, entries: &[&IndexEntry], channels: &[u16], output: P) -> Result<usize, Box<dyn Error>> {
+    let mut channels = channels.to_vec();
+    channels.sort_unstable();
+    channels.dedup();
+
+    if channels.is_empty() {
+        return Err("No channels selected".into());
+    }
+
+    check_not_input(&output, &[&xtf.path])?;
+    let mut writer = XtfWriter::create(output, &subset_header_bytes(xtf, &channels)?)?;
+
+    for entry in entries {
+        match xtf.record(entry)? {
+            Record::Sonar(ping) => writer.write_record(&subset_ping(&ping, &channels)?)?,
+            Record::Unknown { bytes, .. } => writer.write_record(&bytes)?,
+        }
+    }
+
+    writer.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::get_number;
+    use crate::record::parse_ping;
+    use crate::test_data::{assert_consistent, other_record, ping_record, xtf_bytes, TempPath, TestPing};
+
+    #[test]
+    fn subset_ping_rewrites_counts_and_lengths() {
+        let ping = parse_ping(ping_record(4, 20, &TestPing::numbered(1)), &[2; 4]).unwrap();
+        let subset = parse_ping(subset_ping(&ping, &[1, 3]).unwrap(), &[2; 2]).unwrap();
+
+        assert_eq!(get_number(&subset.header, "NumChansToFollow"), Some(2.0));
+        assert_eq!(get_number(&subset.header, "NumBytesThisRecord"), Some(subset.bytes.len() as f64));
+        assert_eq!(subset.bytes.len(), PING_HEADER_LENGTH + 2 * (PING_CHAN_HEADER_LENGTH + 20 * 2));
+        assert_eq!(subset.channels.iter().map(|c| c.channel_number).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(subset.raw_samples(0), ping.raw_samples(1));
+        assert_eq!(subset.raw_samples(1), ping.raw_samples(3));
+    }
+
+    #[test]
+    fn select_channels_writes_a_file_with_only_those_channels() {
+        let mut bytes = xtf_bytes(4, 20, &(0..5).map(TestPing::numbered).collect::<Vec<_>>());
+        bytes.extend(other_record(107, 64));
+        let source = XtfFile::from_bytes(bytes).unwrap();
+        let output = TempPath::new("channels-select.xtf");
+
+        assert_eq!(select_channels(&source, &[2, 3], &output.0).unwrap(), 6);
+        let xtf = XtfFile::open(&output.0).unwrap();
+        assert_consistent(&xtf);
+
+        assert_eq!(xtf.number_of_sonar_channels(), 2);
+        assert_eq!(xtf.channel_infos.len(), 2);
+        // chan info 2 was a port channel, 3 starboard
+        assert_eq!(get_number(&xtf.channel_infos[0], "TypeOfChannel"), Some(1.0));
+        assert_eq!(get_number(&xtf.channel_infos[1], "TypeOfChannel"), Some(2.0));
+        for entry in xtf.index.pings() {
+            let ping = xtf.ping(entry).unwrap();
+            let original = source.ping_by_number(entry.ping_number).unwrap().unwrap();
+            assert_eq!(ping.channels.len(), 2);
+            assert_eq!(ping.raw_samples(0), original.raw_samples(2));
+        }
+        assert_eq!(xtf.index.entries.last().unwrap().header_type, 107);
+    }
+
+    #[test]
+    fn rejects_channels_the_file_hasnt_got() {
+        let source = XtfFile::from_bytes(xtf_bytes(2, 20, &[TestPing::numbered(0)])).unwrap();
+        assert!(subset_header_bytes(&source, &[0, 5]).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::str::FromStr;
+
+use rustxtf::bottom::BottomTrackSettings;
+use rustxtf::coords::CoordinateSystem;
+use rustxtf::dump::SampleOutput;
+use rustxtf::gain::Tvg;
+use rustxtf::lines::LineSettings;
+use rustxtf::processing::Processing;
+use rustxtf::waterfall::default_pair;
+use rustxtf::XtfFile;
+
+use super::USAGE;
+
+
+// Positional arguments plus --name value options. Flags are the options that don't take a value
+pub struct Args {
+    pub positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: HashSet<String>,
+}
+
+impl Args {
+    pub fn parse(args: &[String], flags: &[&str]) -> Result<Args, Box<dyn Error>> {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            flags: HashSet::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if flags.contains(&name) => {
+                    parsed.flags.insert(name.to_string());
+                }
+                Some(name) => {
+                    let value = args.next().ok_or(format!("--{} needs a value", name))?;
+                    parsed.options.insert(name.to_string(), value.clone());
+                }
+                None => parsed.positional.push(arg.clone()),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn positional(&self, index: usize, what: &str) -> Result<&str, Box<dyn Error>> {
+        self.positional
+            .get(index)
+            .map(|arg| arg.as_str())
+            .ok_or_else(|| format!("missing {}\n{}", what, USAGE).into())
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    pub fn parsed<T: FromStr>(&self, name: &str) -> Result<Option<T>, Box<dyn Error>> {
+        match self.option(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("can't parse --{} {}", name, value).into()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    pub fn samples(&self) -> Result<SampleOutput, Box<dyn Error>> {
+        match self.option("samples") {
+            None => Ok(SampleOutput::None),
+            Some("array") => Ok(SampleOutput::Array),
+            Some("base64") => Ok(SampleOutput::Base64),
+            Some(other) => Err(format!("--samples should be array or base64, not {}", other).into()),
+        }
+    }
+
+    // What the file's positions are in, from NavUnits and --zone for files in metres
+    pub fn coordinate_system(&self, xtf: &XtfFile) -> Result<CoordinateSystem, Box<dyn Error>> {
+        Ok(CoordinateSystem::of_file(&xtf.file_header, self.parsed("zone")?))
+    }
+
+    pub fn line_settings(&self) -> Result<LineSettings, Box<dyn Error>> {
+        let defaults = LineSettings::default();
+        Ok(LineSettings {
+            max_turn_rate: self.parsed("turn-rate")?.unwrap_or(defaults.max_turn_rate),
+            window: self.parsed("window")?.unwrap_or(defaults.window),
+            min_length: self.parsed("min-length")?.unwrap_or(defaults.min_length),
+        })
+    }
+
+    pub fn bottom_settings(&self) -> Result<BottomTrackSettings, Box<dyn Error>> {
+        let defaults = BottomTrackSettings::default();
+        Ok(BottomTrackSettings {
+            threshold: self.parsed("threshold")?.unwrap_or(defaults.threshold),
+            blanking: self.parsed("blanking")?.unwrap_or(defaults.blanking),
+            max_jump: self.parsed("max-jump")?.unwrap_or(defaults.max_jump),
+            window: self.parsed("window")?.unwrap_or(defaults.window),
+        })
+    }
+
+    // Port and starboard channel numbers, from --port/--starboard or the chan infos
+    pub fn channel_pair(&self, xtf: &XtfFile) -> Result<(u16, u16), Box<dyn Error>> {
+        let (port, starboard) = default_pair(xtf);
+        Ok((self.parsed("port")?.unwrap_or(port), self.parsed("starboard")?.unwrap_or(starboard)))
+    }
+
+    pub fn processing(&self) -> Result<Processing, Box<dyn Error>> {
+        let tvg = match self.option("tvg") {
+            Some(value) => {
+                let bad = || format!("--tvg should be <spreading dB/decade>,<absorption dB/km>, not {}", value);
+                let (spreading, absorption) = value.split_once(',').ok_or_else(bad)?;
+                Some(Tvg {
+                    spreading: spreading.trim().parse().map_err(|_| bad())?,
+                    absorption: absorption.trim().parse().map_err(|_| bad())?,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Processing {
+            tvg,
+            ground_resolution: self.parsed("ground-range")?,
+            normalise_window: self.parsed("normalise")?,
+        })
+    }
+
+    pub fn channels(&self) -> Result<Option<Vec<u16>>, Box<dyn Error>> {
+        match self.option("channels") {
+            Some(list) => Ok(Some(
+                list.split(',')
+                    .map(|channel| channel.trim().parse::<u16>())
+                    .collect::<Result<Vec<u16>, _>>()?,
+            )),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::error::Error;
+
+use serde_json::json;
+
+use rustxtf::bottom::{track_bottom, write_altitudes};
+
+use super::args::Args;
+use super::open_xtf;
+
+
+// Prints the picks, and writes them into SensorPrimaryAltitude when there's an output file
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+    let channels = args.channel_pair(&xtf)?;
+
+    let settings = args.bottom_settings()?;
+    let picks = track_bottom(&xtf, channels, &settings)?;
+
+    let text = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{:.2}", value));
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&json!({ "settings": settings, "picks": picks }))?);
+    } else {
+        println!("ping        detected  altitude  logged");
+        for pick in &picks {
+            println!("{:<10}  {:>8}  {:>8}  {:>6}", pick.ping_number, text(pick.detected), text(pick.altitude), text(pick.logged));
+        }
+    }
+
+    if let Some(output) = args.positional.get(1) {
+        let written = write_altitudes(&xtf, &picks, output)?;
+        eprintln!("Wrote {} records to {}", written, output);
+    }
+    Ok(())
+}
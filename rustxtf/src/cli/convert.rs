@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use serde_json::json;
+
+use rustxtf::channels::{select_channels, write_channels};
+use rustxtf::coords::{projected_for, CoordinateSystem, UtmZone};
+use rustxtf::dump::write_ndjson;
+use rustxtf::filters::{apply_filters, Filter};
+use rustxtf::image::{write_pgm16, write_png, write_tiff16};
+use rustxtf::track::{convert_track, read_track, write_track, TrackFormat, TrackSource};
+use rustxtf::waterfall::{build_waterfall, Stretch};
+
+use super::args::Args;
+use super::open_xtf;
+
+
+// Output format comes from the output file's extension
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let input = args.positional(0, "input file")?;
+    let output = args.positional(1, "output file")?;
+    let extension = extension(output);
+
+    let xtf = open_xtf(input)?;
+
+    let written = match extension.as_str() {
+        "xtf" => match args.channels()? {
+            Some(channels) => select_channels(&xtf, &channels, output)?,
+            None => {
+                let entries: Vec<_> = xtf.index.entries.iter().collect();
+                write_channels(&xtf, &entries, &(0..xtf.channel_infos.len() as u16).collect::<Vec<_>>(), output)?
+            }
+        },
+        "ndjson" | "jsonl" => {
+            let lines = write_ndjson(&xtf, &mut BufWriter::new(File::create(output)?), args.samples()?)?;
+            println!("Wrote {} json lines to {}", lines, output);
+            return Ok(());
+        }
+        "csv" | "geojson" | "kml" | "gpx" => {
+            let mut format = TrackFormat::from_extension(&extension).ok_or("unknown track format")?;
+            if let TrackFormat::GeoJson { .. } = format {
+                format = TrackFormat::GeoJson { points: args.flag("points") };
+            }
+            let source = if args.flag("ship") { TrackSource::Ship } else { TrackSource::Sensor };
+
+            // the map formats are always longitude, latitude, csv can have them projected
+            let from = args.coordinate_system(&xtf)?;
+            let points = read_track(&xtf, args.parsed("every")?.unwrap_or(1));
+            let to = match (format, args.option("utm")) {
+                (TrackFormat::Csv, Some(utm)) => {
+                    let zone = if utm == "auto" { None } else { Some(utm.parse::<UtmZone>()?) };
+                    match points.first() {
+                        Some(point) => {
+                            let (x, y) = point.position(source);
+                            projected_for(from, x, y, zone)
+                        }
+                        None => from,
+                    }
+                }
+                (TrackFormat::Csv, None) => from,
+                _ => CoordinateSystem::Geographic,
+            };
+            let points = convert_track(&points, from, to)?;
+            write_track(&points, format, source, &mut BufWriter::new(File::create(output)?))?;
+            println!("Wrote {} track points to {}", points.len(), output);
+            return Ok(());
+        }
+        "png" | "pgm" | "tif" | "tiff" => {
+            let (port, starboard) = args.channel_pair(&xtf)?;
+            let stretch = stretch(args)?;
+
+            let processing = args.processing()?;
+
+            let every = args.parsed("every")?.unwrap_or(1);
+            let filters = filters(args)?;
+            let waterfall = apply_filters(build_waterfall(&xtf, port, starboard, every, &processing)?, &filters);
+            let (black, white) = stretch.limits(&waterfall.pixels);
+            let stretch = Stretch::Manual(black, white);
+
+            // how the image was made, so it can be reproduced
+            let description = json!({
+                "source": input,
+                "port": port,
+                "starboard": starboard,
+                "every": every,
+                "processing": processing,
+                "filters": filters,
+                "black": black,
+                "white": white,
+            })
+            .to_string();
+            let description = Some(description.as_str());
+
+            match extension.as_str() {
+                "png" => write_png(output, waterfall.width, waterfall.height, &waterfall.to_u8(stretch), description)?,
+                "pgm" => write_pgm16(output, waterfall.width, waterfall.height, &waterfall.to_u16(stretch), description)?,
+                _ => write_tiff16(output, waterfall.width, waterfall.height, &waterfall.to_u16(stretch), description)?,
+            }
+            println!("Wrote {}x{} waterfall of channels {} and {} to {}", waterfall.width, waterfall.height, port, starboard, output);
+            return Ok(());
+        }
+        _ => {
+            return Err(format!(
+                "don't know how to convert to .{} (supported: xtf, ndjson, csv, geojson, kml, gpx, png, pgm, tif)",
+                extension
+            )
+            .into())
+        }
+    };
+
+    println!("Wrote {} records to {}", written, output);
+    Ok(())
+}
+
+
+fn stretch(args: &Args) -> Result<Stretch, Box<dyn Error>> {
+    let value = args.option("stretch").unwrap_or("percentile:1,99");
+    let bad = || format!("--stretch should be minmax, percentile:<low>,<high> or manual:<black>,<white>, not {}", value);
+    if value == "minmax" {
+        return Ok(Stretch::MinMax);
+    }
+
+    let (kind, limits) = value.split_once(':').ok_or_else(bad)?;
+    let (low, high) = limits.split_once(',').ok_or_else(bad)?;
+    let (low, high): (f64, f64) = (low.trim().parse().map_err(|_| bad())?, high.trim().parse().map_err(|_| bad())?);
+    match kind {
+        "percentile" => Ok(Stretch::Percentile(low, high)),
+        "manual" => Ok(Stretch::Manual(low, high)),
+        _ => Err(bad().into()),
+    }
+}
+
+
+// Comma separated, applied in order
+fn filters(args: &Args) -> Result<Vec<Filter>, Box<dyn Error>> {
+    match args.option("filter") {
+        Some(list) => Ok(list.split(',').map(|filter| filter.parse::<Filter>()).collect::<Result<_, _>>()?),
+        None => Ok(Vec::new()),
+    }
+}
+
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
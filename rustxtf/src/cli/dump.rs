@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::io::{self, BufWriter};
+
+use rustxtf::dump::write_ndjson;
+use rustxtf::headers::{FieldTable, HeaderMap, XTF_CHAN_INFO, XTF_FILE_HEADER, XTF_PING_CHAN_HEADER, XTF_PING_HEADER};
+use rustxtf::Record;
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+
+    if args.flag("json") {
+        write_ndjson(&xtf, &mut BufWriter::new(io::stdout().lock()), args.samples()?)?;
+        return Ok(());
+    }
+
+    print_headers(&xtf.file_header, XTF_FILE_HEADER);
+    for (i, channel_info) in xtf.channel_infos.iter().enumerate() {
+        println!("\nChannel {}", i);
+        print_headers(channel_info, XTF_CHAN_INFO);
+    }
+
+    for (entry, record) in xtf.index.entries.iter().zip(xtf.records()) {
+        match record? {
+            Record::Sonar(ping) => {
+                println!("\nPing at offset {}", entry.offset);
+                print_headers(&ping.header, XTF_PING_HEADER);
+
+                for (i, channel) in ping.channels.iter().enumerate() {
+                    println!("\nPing channel {}", i);
+                    print_headers(&channel.header, XTF_PING_CHAN_HEADER);
+                }
+            }
+            Record::Unknown { header_type, bytes } => {
+                println!("\nUnknown record type {} at offset {} ({} bytes)", header_type, entry.offset, bytes.len());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+// Prints in table order rather than hash map order so related fields stay together
+fn print_headers(headers: &HeaderMap, table: &FieldTable) {
+    for (key, _, _) in table {
+        match headers.get(*key) {
+            Some(Some(val)) => println!("Key: {}, Value: {:?}", key, val),
+            _ => println!("Key: {}, Value: None", key),
+        }
+    }
+}
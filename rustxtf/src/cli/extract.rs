@@ -0,0 +1,38 @@
+use std::error::Error;
+
+use rustxtf::channels::{select_channels, write_channels};
+use rustxtf::extract::{extract, select_records, ExtractRange};
+use rustxtf::time::parse_time;
+
+use super::args::Args;
+use super::open_xtf;
+use super::USAGE;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let input = args.positional(0, "input file")?;
+    let output = args.positional(1, "output file")?;
+
+    let range = match (args.option("start"), args.option("end"), args.parsed("first-ping")?, args.parsed("last-ping")?) {
+        (Some(start), Some(end), None, None) => {
+            let start = parse_time(start).ok_or(format!("can't parse start time {}", start))?;
+            let end = parse_time(end).ok_or(format!("can't parse end time {}", end))?;
+            Some(ExtractRange::Time(start, end))
+        }
+        (None, None, Some(first), Some(last)) => Some(ExtractRange::PingNumber(first, last)),
+        (None, None, None, None) => None,
+        _ => return Err(format!("extract needs both ends of either --start/--end or --first-ping/--last-ping\n{}", USAGE).into()),
+    };
+    let channels = args.channels()?;
+
+    let xtf = open_xtf(input)?;
+    let written = match (range, channels) {
+        (Some(range), None) => extract(&xtf, range, output)?,
+        (Some(range), Some(channels)) => write_channels(&xtf, &select_records(&xtf, range), &channels, output)?,
+        (None, Some(channels)) => select_channels(&xtf, &channels, output)?,
+        (None, None) => return Err(format!("extract needs a range and/or --channels\n{}", USAGE).into()),
+    };
+
+    println!("Wrote {} records to {}", written, output);
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::error::Error;
+
+use serde_json::json;
+
+use rustxtf::coords::CoordinateSystem;
+use rustxtf::georef::Georeferencer;
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+    let ping_number = args.parsed("ping")?.ok_or("georef needs --ping")?;
+    let channel_number: u16 = args.parsed("channel")?.ok_or("georef needs --channel")?;
+    let sample = args.parsed("sample")?.ok_or("georef needs --sample")?;
+
+    let georeferencer = Georeferencer::for_file(&xtf, args.parsed("zone")?, args.parsed("utm")?);
+
+    let ping = xtf.ping_by_number(ping_number)?.ok_or(format!("no ping {}", ping_number))?;
+    let channel = ping
+        .channels
+        .iter()
+        .position(|channel| channel.channel_number == channel_number)
+        .ok_or(format!("ping {} has no channel {}", ping_number, channel_number))?;
+    let position = georeferencer
+        .sample_position(&xtf, &ping, channel, sample)
+        .ok_or("can't place that sample, it needs a position, heading, slant range and a port or starboard channel")?;
+
+    let system = match georeferencer.target {
+        CoordinateSystem::Utm(zone) => format!("UTM {}", zone),
+        CoordinateSystem::Geographic => "longitude/latitude".to_string(),
+        CoordinateSystem::UnknownGrid => "file grid".to_string(),
+    };
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&json!({ "system": system, "position": position }))?);
+    } else {
+        println!(
+            "Ping {} channel {} sample {}: {:.2}, {:.2} ({}), slant range {:.2} m, ground range {:.2} m",
+            ping_number, channel_number, sample, position.x, position.y, system, position.slant_range, position.ground_range
+        );
+    }
+    Ok(())
+}
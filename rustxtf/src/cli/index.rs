@@ -0,0 +1,26 @@
+use std::error::Error;
+
+use serde_json::json;
+
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+    let path = xtf.write_index()?;
+    let pings = xtf.index.pings().count();
+
+    if args.flag("json") {
+        let report = json!({
+            "index": path,
+            "records": xtf.index.entries.len(),
+            "pings": pings,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("Indexed {} records ({} pings) into {}", xtf.index.entries.len(), pings, path.display());
+    }
+    Ok(())
+}
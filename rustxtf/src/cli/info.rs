@@ -0,0 +1,18 @@
+use std::error::Error;
+
+use rustxtf::info::summarize;
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let summary = summarize(&open_xtf(args.positional(0, "input file")?)?);
+
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        print!("{}", summary);
+    }
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use std::error::Error;
+
+use rustxtf::layback::{write_layback, LaybackModel};
+
+use super::args::Args;
+use super::open_xtf;
+
+
+// Summary, then a line per ping that got a towfish position, or all of it as json
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let input = args.positional(0, "input file")?;
+    let output = args.positional(1, "output file")?;
+    let model = if args.flag("catenary") { LaybackModel::Catenary } else { LaybackModel::Straight };
+
+    let xtf = open_xtf(input)?;
+    let report = write_layback(&xtf, model, args.flag("force"), output)?;
+
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{} of {} pings given towfish positions", report.changed.len(), xtf.index.pings().count());
+        if !report.kept.is_empty() {
+            println!("{} pings kept their own sensor positions, --force replaces them", report.kept.len());
+        }
+        for fish in &report.changed {
+            println!("ping {:<10} layback {:>7.1} m  {:.8}, {:.8}", fish.ping_number, fish.layback, fish.x, fish.y);
+        }
+    }
+    eprintln!("Wrote {} records to {}", report.records, output);
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+
+use serde_json::json;
+
+use rustxtf::lines::{find_lines, SegmentKind};
+use rustxtf::time::format_time;
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+    let settings = args.line_settings()?;
+    let segments = find_lines(&xtf, &settings);
+
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&json!({ "settings": settings, "segments": segments }))?);
+        return Ok(());
+    }
+
+    let lines: Vec<_> = segments.iter().filter(|segment| segment.kind == SegmentKind::Line).collect();
+    println!("{} lines, {:.0} m in total", lines.len(), lines.iter().fold(0.0, |total, line| total + line.length));
+    println!("kind  first ping  last ping   pings  start                    heading  turn     length");
+    let degrees = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{:.1}", value));
+    for segment in &segments {
+        println!(
+            "{:<4}  {:<10}  {:<10}  {:>5}  {:<23}  {:>7}  {:>6}  {:>7.1}",
+            if segment.kind == SegmentKind::Line { "line" } else { "turn" },
+            segment.first_ping,
+            segment.last_ping,
+            segment.pings,
+            format_time(segment.start_time),
+            degrees(segment.heading),
+            degrees(segment.turn),
+            segment.length
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use std::error::Error;
+
+use rustxtf::merge::merge;
+
+use super::args::Args;
+use super::USAGE;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let output = args.positional(0, "output file")?;
+    let inputs = &args.positional[1..];
+    if inputs.is_empty() {
+        return Err(format!("merge needs at least one input file\n{}", USAGE).into());
+    }
+
+    let written = merge(inputs, output, args.flag("renumber"))?;
+    println!("Wrote {} records to {}", written, output);
+    Ok(())
+}
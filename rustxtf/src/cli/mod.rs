@@ -0,0 +1,125 @@
+use std::error::Error;
+
+use rustxtf::XtfFile;
+
+use self::args::Args;
+
+// One module per command, each with a run function taking the command's arguments
+mod args;
+mod bottom;
+mod convert;
+mod dump;
+mod extract;
+mod georef;
+mod index;
+mod info;
+mod layback;
+mod lines;
+mod merge;
+mod mosaic;
+mod nav_qc;
+mod retime;
+mod split;
+mod timing;
+mod validate;
+mod water_column;
+
+pub const USAGE: &str = "usage: rustxtf <command> [options]
+
+commands:
+    info <file.xtf> [--json]
+    dump <file.xtf> [--json [--samples array|base64]]
+    validate <file.xtf> [--json]
+    timing <file.xtf> [--gap-factor <n>] [--json]
+    retime <input.xtf> <output.xtf> --from navfix|clock|position|interval [--date <YYYY-MM-DD>] [--start <time>]
+            [--interval <s>] [--json]
+    extract <input.xtf> <output.xtf> [--start <time> --end <time> | --first-ping <n> --last-ping <n>] [--channels <n,n,...>]
+    convert <input.xtf> <output.xtf|.ndjson> [--channels <n,n,...>] [--samples array|base64]
+    convert <input.xtf> <output.csv|.geojson|.kml|.gpx> [--every <n>] [--points] [--ship] [--zone <zone>] [--utm auto|<zone>]
+    convert <input.xtf> <output.png|.pgm|.tif> [--port <n> --starboard <n>] [--every <n>] [--stretch <stretch>]
+            [--ground-range <m>] [--tvg <spreading>,<absorption>] [--normalise <pings>] [--filter <filter>,...]
+    index <file.xtf> [--json]
+    merge <output.xtf> <input.xtf>... [--renumber]
+    split <input.xtf> <output-prefix> (--max-bytes <n> | --max-seconds <s> | --turns [degrees] [--settle <s>]
+            | --lines [line options])
+    lines <file.xtf> [--turn-rate <deg/s>] [--min-length <m>] [--window <pings>] [--json]
+    layback <input.xtf> <output.xtf> [--catenary] [--force] [--json]
+    mosaic <output.tif> <input.xtf>... --resolution <m> [--overlap last|nadir:<m>] [--zone <zone>] [--utm <zone>]
+            [--tvg <spreading>,<absorption>] [--normalise <pings>] [--keep-nav-outliers] [--max-gap <m>]
+    bottom <input.xtf> [<output.xtf>] [--port <n> --starboard <n>] [--threshold <0-1>] [--blanking <m>] [--max-jump <m>]
+            [--window <pings>] [--json]
+    watercolumn <input.xtf> <output.xtf> [--track [--port <n> --starboard <n>] [bottom options]]
+    navqc <input.xtf> [<output.xtf>] [--ship] [--zone <zone>] [--max-speed <m/s>] [--max-acceleration <m/s2>]
+            [--process-noise <m/s2>] [--fix-noise <m>] [--json]
+    georef <file.xtf> --ping <n> --channel <n> --sample <n> [--zone <zone>] [--utm <zone>] [--json]
+
+times are seconds since the epoch or YYYY-MM-DDTHH:MM:SS[.ss]
+--json prints machine readable output on stdout, for dump that is one json object per record
+retime rebuilds ping times from the nav fix time, the computer clock, raw navigation records or the
+    ping interval. Nav fix and clock times go on --date, default the first good ping date. Raw
+    navigation records carry their own dates so --from position ignores --date. Interval times
+    count from --start, default lined up with the first good ping time
+timing reports gaps over --gap-factor (default 2) times SecondsPerPing, or the median interval without it
+--zone is the UTM zone (e.g. 31N, 17S) of positions in metres, --utm projects csv output
+    and picks the zone for georef and mosaic (default the zone the data is in)
+--filter is any of median:<size>, lee:<size>[:<looks>], frost:<size>[:<damping>], along:<pings>,
+    stripes:<pings>[:<factor>], applied in order
+split --turns starts a part when the heading moves more than [degrees] (default 30) off the line for
+    5 pings, and takes the next line once the heading has held steady for --settle (default 30 s)
+lines and split --lines treat pings turning faster than --turn-rate (default 1 deg/s over 9 pings) as
+    turning and lines shorter than --min-length (default 50 m) as part of the turn. Nav jumps look
+    like turns, clean them with navqc first
+layback only fills in sensor positions that are empty or the same as the ship's, --force replaces
+    them all
+navqc checks sensor positions, or ship positions with --ship, and writes the smoothed ones to <output.xtf>
+mosaic leaves out pings whose sensor positions navqc flags as speed or acceleration outliers,
+    unless --keep-nav-outliers
+mosaic fills in between pings up to --max-gap (default 10 m) apart, raise it for fast or slow pinging surveys
+--overlap last puts the latest ping on top, nadir:<m> keeps samples within <m> of nadir underneath
+--stretch is minmax, percentile:<low>,<high> (default percentile:1,99) or manual:<black>,<white>
+--ground-range corrects slant range to ground range at that many metres per pixel
+--tvg adds spreading (dB per decade of range) and absorption (dB/km) gain, --normalise evens out
+    across track gain using the mean of each sample bin over that many pings";
+
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Err(USAGE.into()),
+    };
+
+    match command {
+        "info" => info::run(&Args::parse(rest, &["json"])?),
+        "dump" => dump::run(&Args::parse(rest, &["json"])?),
+        "validate" => validate::run(&Args::parse(rest, &["json"])?),
+        "timing" => timing::run(&Args::parse(rest, &["json"])?),
+        "retime" => retime::run(&Args::parse(rest, &["json"])?),
+        "extract" => extract::run(&Args::parse(rest, &[])?),
+        "convert" => convert::run(&Args::parse(rest, &["points", "ship"])?),
+        "index" => index::run(&Args::parse(rest, &["json"])?),
+        "merge" => merge::run(&Args::parse(rest, &["renumber"])?),
+        "split" => split::run(rest),
+        "lines" => lines::run(&Args::parse(rest, &["json"])?),
+        "layback" => layback::run(&Args::parse(rest, &["catenary", "force", "json"])?),
+        "georef" => georef::run(&Args::parse(rest, &["json"])?),
+        "bottom" => bottom::run(&Args::parse(rest, &["json"])?),
+        "watercolumn" => water_column::run(&Args::parse(rest, &["track"])?),
+        "mosaic" => mosaic::run(&Args::parse(rest, &["keep-nav-outliers"])?),
+        "navqc" => nav_qc::run(&Args::parse(rest, &["ship", "json"])?),
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(format!("unknown command {}\n{}", other, USAGE).into()),
+    }
+}
+
+
+// Opens an input file, saying so when its sidecar index couldn't be used
+fn open_xtf(path: &str) -> Result<XtfFile, Box<dyn Error>> {
+    let xtf = XtfFile::open(path)?;
+    if let Some(error) = &xtf.index_error {
+        eprintln!("Ignoring unreadable index for {}: {}", path, error);
+    }
+    Ok(xtf)
+}
@@ -0,0 +1,38 @@
+use std::error::Error;
+
+use rustxtf::mosaic::{build_mosaic, MosaicSettings, Overlap};
+use rustxtf::navigation::NavQcSettings;
+
+use super::args::Args;
+use super::open_xtf;
+use super::USAGE;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let output = args.positional(0, "output file")?;
+    let inputs = &args.positional[1..];
+    if inputs.is_empty() {
+        return Err(format!("mosaic needs at least one input file\n{}", USAGE).into());
+    }
+
+    let overlap = match args.option("overlap") {
+        None | Some("last") => Overlap::LastOnTop,
+        Some(value) => match value.strip_prefix("nadir:").and_then(|distance| distance.parse().ok()) {
+            Some(distance) => Overlap::NadirAvoid(distance),
+            None => return Err(format!("--overlap should be last or nadir:<metres>, not {}", value).into()),
+        },
+    };
+    let settings = MosaicSettings {
+        resolution: args.parsed("resolution")?.ok_or("mosaic needs --resolution")?,
+        overlap,
+        processing: args.processing()?,
+        navigation: if args.flag("keep-nav-outliers") { None } else { Some(NavQcSettings::default()) },
+        max_ping_gap: args.parsed("max-gap")?.unwrap_or(10.0),
+    };
+
+    let files = inputs.iter().map(|input| open_xtf(input)).collect::<Result<Vec<_>, _>>()?;
+    let mosaic = build_mosaic(&files, args.parsed("zone")?, args.parsed("utm")?, &settings)?;
+    mosaic.write_geotiff(output, &settings)?;
+    println!("Wrote {}x{} mosaic at {} m to {}", mosaic.width, mosaic.height, mosaic.resolution, output);
+    Ok(())
+}
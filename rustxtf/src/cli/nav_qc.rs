@@ -0,0 +1,53 @@
+use std::error::Error;
+
+use serde_json::json;
+
+use rustxtf::navigation::{check_navigation, write_smoothed, NavFlag, NavQcSettings};
+use rustxtf::track::TrackSource;
+
+use super::args::Args;
+use super::open_xtf;
+
+
+// Prints the flagged fixes, or every ping with --json, and writes the smoothed positions when
+// there's an output file
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+    let source = if args.flag("ship") { TrackSource::Ship } else { TrackSource::Sensor };
+
+    let defaults = NavQcSettings::default();
+    let settings = NavQcSettings {
+        max_speed: args.parsed("max-speed")?.unwrap_or(defaults.max_speed),
+        max_acceleration: args.parsed("max-acceleration")?.unwrap_or(defaults.max_acceleration),
+        process_noise: args.parsed("process-noise")?.unwrap_or(defaults.process_noise),
+        fix_noise: args.parsed("fix-noise")?.unwrap_or(defaults.fix_noise),
+    };
+    let points = check_navigation(&xtf, args.coordinate_system(&xtf)?, source, &settings);
+
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&json!({ "settings": settings, "points": points }))?);
+    } else {
+        let count = |flag: NavFlag| points.iter().filter(|point| point.flag == flag).count();
+        println!(
+            "{} pings: {} good, {} without a position, {} duplicate fixes, {} speed outliers, {} acceleration outliers",
+            points.len(),
+            count(NavFlag::Good),
+            count(NavFlag::NoPosition),
+            count(NavFlag::Duplicate),
+            count(NavFlag::SpeedOutlier),
+            count(NavFlag::AccelerationOutlier)
+        );
+        for point in points.iter().filter(|point| point.flag != NavFlag::Good) {
+            println!(
+                "ping {:<10} {:?}: {:.8}, {:.8} smoothed to {:.8}, {:.8}",
+                point.ping_number, point.flag, point.x, point.y, point.smoothed_x, point.smoothed_y
+            );
+        }
+    }
+
+    if let Some(output) = args.positional.get(1) {
+        let written = write_smoothed(&xtf, &points, source, output)?;
+        eprintln!("Wrote {} records to {}", written, output);
+    }
+    Ok(())
+}
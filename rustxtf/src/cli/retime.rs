@@ -0,0 +1,49 @@
+use std::error::Error;
+
+use rustxtf::time::{format_time, parse_time};
+use rustxtf::time_repair::{rebuild_times, repair_report, write_times, RepairSettings};
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+    let output = args.positional(1, "output file")?;
+
+    let date = match args.option("date") {
+        Some(date) => Some(parse_time(&format!("{}T00:00:00", date)).ok_or(format!("can't parse --date {}, expected YYYY-MM-DD", date))?),
+        None => None,
+    };
+    let start = match args.option("start") {
+        Some(start) => Some(parse_time(start).ok_or(format!("can't parse --start {}", start))?),
+        None => None,
+    };
+    let settings = RepairSettings {
+        source: args.parsed("from")?.ok_or("retime needs --from navfix, clock, position or interval")?,
+        date,
+        start,
+        interval: args.parsed("interval")?,
+    };
+
+    let times = rebuild_times(&xtf, &settings)?;
+    let report = repair_report(&xtf, &times, &settings);
+    let written = write_times(&xtf, &times, output)?;
+
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "{} of {} pings retimed from {:?}, {} had no time to give and were left alone",
+            report.changed, report.pings, settings.source, report.unresolved
+        );
+        for change in &report.changes {
+            match change.old {
+                Some(old) => println!("ping {:<10} {} -> {} ({:+.2} s)", change.ping_number, format_time(old), format_time(change.new), change.new - old),
+                None => println!("ping {:<10} {:<23} -> {}", change.ping_number, "-", format_time(change.new)),
+            }
+        }
+    }
+    eprintln!("Wrote {} records to {}", written, output);
+    Ok(())
+}
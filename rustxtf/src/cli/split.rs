@@ -0,0 +1,43 @@
+use std::error::Error;
+
+use rustxtf::split::{split, SplitRule};
+
+use super::args::Args;
+use super::open_xtf;
+use super::USAGE;
+
+
+pub fn run(rest: &[String]) -> Result<(), Box<dyn Error>> {
+    // --turns takes an optional value so it can't go through the generic flag handling
+    let (turns, rest): (Option<f64>, Vec<String>) = match rest.iter().position(|arg| arg == "--turns") {
+        Some(i) => {
+            let degrees = rest.get(i + 1).and_then(|value| value.parse::<f64>().ok());
+            let skip = if degrees.is_some() { 2 } else { 1 };
+            let remaining = rest[..i].iter().chain(&rest[i + skip..]).cloned().collect();
+            (Some(degrees.unwrap_or(30.0)), remaining)
+        }
+        None => (None, rest.to_vec()),
+    };
+    let args = Args::parse(&rest, &["lines"])?;
+
+    let input = args.positional(0, "input file")?;
+    let prefix = args.positional(1, "output prefix")?;
+
+    let rule = if let Some(bytes) = args.parsed("max-bytes")? {
+        SplitRule::MaxBytes(bytes)
+    } else if let Some(seconds) = args.parsed("max-seconds")? {
+        SplitRule::MaxDuration(seconds)
+    } else if let Some(max_change) = turns {
+        SplitRule::Turns { max_change, window: 5, settle_seconds: args.parsed("settle")?.unwrap_or(30.0) }
+    } else if args.flag("lines") {
+        SplitRule::Lines(args.line_settings()?)
+    } else {
+        return Err(format!("split needs --max-bytes, --max-seconds, --turns or --lines\n{}", USAGE).into());
+    };
+
+    let xtf = open_xtf(input)?;
+    for path in split(&xtf, rule, prefix)? {
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
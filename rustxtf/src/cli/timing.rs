@@ -0,0 +1,47 @@
+use std::error::Error;
+
+use serde_json::json;
+
+use rustxtf::time::format_time;
+use rustxtf::timing::check_timing;
+
+use super::args::Args;
+use super::open_xtf;
+
+
+// Summary first, then a line per issue
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let path = args.positional(0, "input file")?;
+    let report = check_timing(&open_xtf(path)?, args.parsed("gap-factor")?.unwrap_or(2.0));
+
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&json!({ "file": path, "report": report }))?);
+        return Ok(());
+    }
+
+    let time = |time: Option<f64>| time.map_or("-".to_string(), format_time);
+    println!("{} pings from {} to {}", report.pings, time(report.start_time), time(report.end_time));
+    match report.median_interval {
+        Some(interval) => println!("Median ping interval {:.3} s ({:.2} pings/s)", interval, 1.0 / interval),
+        None => println!("No ping interval, fewer than two pings have times"),
+    }
+    println!("{} dropped pings, {} issues", report.dropped_pings, report.issues.len());
+
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for issue in &report.issues {
+        let kind = issue.issue.name();
+        match counts.iter_mut().find(|(name, _)| *name == kind) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((kind, 1)),
+        }
+    }
+    for (kind, count) in &counts {
+        println!("    {:<22} {}", kind, count);
+    }
+
+    for issue in &report.issues {
+        let time = if issue.time.is_finite() { format_time(issue.time) } else { "-".to_string() };
+        println!("ping {:<10} {:<23}  {}", issue.ping_number, time, issue.issue);
+    }
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use std::error::Error;
+
+use serde_json::json;
+
+use rustxtf::validate::validate;
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let path = args.positional(0, "input file")?;
+    let issues = validate(&open_xtf(path)?);
+
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&json!({ "file": path, "issues": issues }))?);
+    } else {
+        for issue in &issues {
+            println!("offset {}: {}", issue.offset, issue.message);
+        }
+    }
+
+    if issues.is_empty() {
+        if !args.flag("json") {
+            println!("{} looks valid", path);
+        }
+        Ok(())
+    } else {
+        Err(format!("{} problems found in {}", issues.len(), path).into())
+    }
+}
@@ -0,0 +1,24 @@
+use std::error::Error;
+
+use rustxtf::water_column::{ping_altitudes, write_blanked, AltitudeSource};
+
+use super::args::Args;
+use super::open_xtf;
+
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let xtf = open_xtf(args.positional(0, "input file")?)?;
+    let output = args.positional(1, "output file")?;
+
+    let source = if args.flag("track") {
+        AltitudeSource::Tracked { channels: args.channel_pair(&xtf)?, settings: args.bottom_settings()? }
+    } else {
+        AltitudeSource::Logged
+    };
+    let altitudes = ping_altitudes(&xtf, source)?;
+    let missing = altitudes.iter().filter(|altitude| altitude.is_none()).count();
+
+    let written = write_blanked(&xtf, &altitudes, output)?;
+    println!("Wrote {} records to {}, {} pings had no altitude and were left alone", written, output, missing);
+    Ok(())
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_derive::Serialize;
+
+use crate::headers::{get_number, HeaderMap};
+
+// Positions in the ping headers are plain numbers, NavUnits in the file header says what they
+// are: 0 metres in some projection, 3 longitude (x) and latitude (y) in degrees. Everything that
+// exports or places data goes through here rather than guessing. UTM is WGS84 and uses the
+// Snyder transverse Mercator series (USGS Professional Paper 1395), which loses accuracy
+// away from the central meridian but stays far inside sonar positioning error within a zone
+
+pub const NAV_UNITS_METRES: u16 = 0;
+pub const NAV_UNITS_LAT_LON: u16 = 3;
+
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+const UTM_K0: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+const EARTH_RADIUS: f64 = 6_371_008.8; // mean radius, metres
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct UtmZone {
+    pub zone: u8, // 1 - 60
+    pub north: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CoordinateSystem {
+    // longitude, latitude in degrees, WGS84
+    Geographic,
+    Utm(UtmZone),
+    // metres in a projection we haven't been told, only usable as it is
+    UnknownGrid,
+}
+
+
+impl UtmZone {
+    // Standard zone for a position, including the Norway and Svalbard exceptions
+    pub fn for_position(longitude: f64, latitude: f64) -> UtmZone {
+        let longitude = (longitude + 180.0).rem_euclid(360.0) - 180.0;
+        let mut zone = (((longitude + 180.0) / 6.0).floor() as i32 + 1).clamp(1, 60) as u8;
+
+        if (56.0..64.0).contains(&latitude) && (3.0..12.0).contains(&longitude) {
+            zone = 32;
+        }
+        if (72.0..84.0).contains(&latitude) {
+            zone = match longitude {
+                l if (0.0..9.0).contains(&l) => 31,
+                l if (9.0..21.0).contains(&l) => 33,
+                l if (21.0..33.0).contains(&l) => 35,
+                l if (33.0..42.0).contains(&l) => 37,
+                _ => zone,
+            };
+        }
+
+        UtmZone { zone, north: latitude >= 0.0 }
+    }
+
+    pub fn central_meridian(&self) -> f64 {
+        self.zone as f64 * 6.0 - 183.0
+    }
+
+    // WGS84 / UTM EPSG code, 326xx north and 327xx south
+    pub fn epsg(&self) -> u16 {
+        if self.north {
+            32600 + self.zone as u16
+        } else {
+            32700 + self.zone as u16
+        }
+    }
+}
+
+impl fmt::Display for UtmZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.zone, if self.north { "N" } else { "S" })
+    }
+}
+
+// "31N", "31S" or just "31" for the northern hemisphere. The letter is the hemisphere, not
+// the MGRS latitude band
+impl FromStr for UtmZone {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<UtmZone, String> {
+        let text = text.trim().to_uppercase();
+        let (digits, north) = match text.strip_suffix('N') {
+            Some(digits) => (digits, true),
+            None => match text.strip_suffix('S') {
+                Some(digits) => (digits, false),
+                None => (text.as_str(), true),
+            },
+        };
+
+        match digits.parse::<u8>() {
+            Ok(zone) if (1..=60).contains(&zone) => Ok(UtmZone { zone, north }),
+            _ => Err(format!("{} isn't a UTM zone, expected something like 31N or 17S", text)),
+        }
+    }
+}
+
+
+impl CoordinateSystem {
+    // What the file's positions are in. Metres are taken to be UTM when the zone is given,
+    // there's nothing in the file to say which projection they're in otherwise
+    pub fn of_file(file_header: &HeaderMap, zone: Option<UtmZone>) -> CoordinateSystem {
+        match get_number(file_header, "NavUnits").map(|units| units as u16) {
+            Some(NAV_UNITS_LAT_LON) => CoordinateSystem::Geographic,
+            _ => match zone {
+                Some(zone) => CoordinateSystem::Utm(zone),
+                None => CoordinateSystem::UnknownGrid,
+            },
+        }
+    }
+
+    pub fn is_geographic(&self) -> bool {
+        *self == CoordinateSystem::Geographic
+    }
+
+    // x, y from this system into `to`. None when either end is an unknown grid (unless both
+    // are) or the result isn't finite
+    pub fn convert(&self, x: f64, y: f64, to: CoordinateSystem) -> Option<(f64, f64)> {
+        if *self == to {
+            return Some((x, y));
+        }
+
+        let (longitude, latitude) = match *self {
+            CoordinateSystem::Geographic => (x, y),
+            CoordinateSystem::Utm(zone) => utm_to_geographic(x, y, zone),
+            CoordinateSystem::UnknownGrid => return None,
+        };
+        let converted = match to {
+            CoordinateSystem::Geographic => (longitude, latitude),
+            CoordinateSystem::Utm(zone) => geographic_to_utm(longitude, latitude, zone),
+            CoordinateSystem::UnknownGrid => return None,
+        };
+
+        if converted.0.is_finite() && converted.1.is_finite() {
+            Some(converted)
+        } else {
+            None
+        }
+    }
+
+    // Moves x, y `distance` metres towards `bearing` (degrees from north). Geographic positions
+    // use a spherical earth, plenty for layback and swath distances. Grid north is taken as north
+    pub fn offset(&self, x: f64, y: f64, distance: f64, bearing: f64) -> (f64, f64) {
+        let (east, north) = (distance * bearing.to_radians().sin(), distance * bearing.to_radians().cos());
+        if self.is_geographic() {
+            let latitude = (north / EARTH_RADIUS).to_degrees();
+            let longitude = (east / (EARTH_RADIUS * y.to_radians().cos())).to_degrees();
+            (x + longitude, y + latitude)
+        } else {
+            (x + east, y + north)
+        }
+    }
+}
+
+
+// A projected system to work in for data at this position: the given zone, or the standard
+// zone for the position when there isn't one. Files already in metres stay as they are
+pub fn projected_for(source: CoordinateSystem, x: f64, y: f64, zone: Option<UtmZone>) -> CoordinateSystem {
+    match (source, zone) {
+        (_, Some(zone)) => CoordinateSystem::Utm(zone),
+        (CoordinateSystem::Geographic, None) => CoordinateSystem::Utm(UtmZone::for_position(x, y)),
+        (source, None) => source,
+    }
+}
+
+
+// All zero means the logger had no fix rather than a position off west Africa
+pub fn is_position(x: f64, y: f64) -> bool {
+    x.is_finite() && y.is_finite() && (x != 0.0 || y != 0.0)
+}
+
+
+fn eccentricity_squared() -> f64 {
+    WGS84_F * (2.0 - WGS84_F)
+}
+
+
+// Distance along the meridian from the equator to latitude phi (radians)
+fn meridian_arc(phi: f64) -> f64 {
+    let e2 = eccentricity_squared();
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+    WGS84_A
+        * ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * phi
+            - (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0) * (2.0 * phi).sin()
+            + (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * phi).sin()
+            - (35.0 * e6 / 3072.0) * (6.0 * phi).sin())
+}
+
+
+// Longitude, latitude in degrees to easting, northing in metres. Works outside the zone
+// too, just less accurately the further out it goes
+pub fn geographic_to_utm(longitude: f64, latitude: f64, zone: UtmZone) -> (f64, f64) {
+    let e2 = eccentricity_squared();
+    let ep2 = e2 / (1.0 - e2);
+    let phi = latitude.to_radians();
+    let lambda = (longitude - zone.central_meridian()).to_radians();
+
+    let n = WGS84_A / (1.0 - e2 * phi.sin().powi(2)).sqrt();
+    let t = phi.tan().powi(2);
+    let c = ep2 * phi.cos().powi(2);
+    let a = phi.cos() * lambda;
+
+    let easting = UTM_K0
+        * n
+        * (a + (1.0 - t + c) * a.powi(3) / 6.0 + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0)
+        + UTM_FALSE_EASTING;
+    let mut northing = UTM_K0
+        * (meridian_arc(phi)
+            + n * phi.tan()
+                * (a * a / 2.0
+                    + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0
+                    + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0));
+    if !zone.north {
+        northing += UTM_FALSE_NORTHING_SOUTH;
+    }
+
+    (easting, northing)
+}
+
+
+// Easting, northing in metres back to longitude, latitude in degrees
+pub fn utm_to_geographic(easting: f64, northing: f64, zone: UtmZone) -> (f64, f64) {
+    let e2 = eccentricity_squared();
+    let ep2 = e2 / (1.0 - e2);
+    let x = easting - UTM_FALSE_EASTING;
+    let y = if zone.north { northing } else { northing - UTM_FALSE_NORTHING_SOUTH };
+
+    // footpoint latitude
+    let mu = y / UTM_K0 / (WGS84_A * (1.0 - e2 / 4.0 - 3.0 * e2 * e2 / 64.0 - 5.0 * e2.powi(3) / 256.0));
+    let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+    let phi1 = mu
+        + (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+        + (21.0 * e1 * e1 / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+        + (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+        + (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+    let c1 = ep2 * phi1.cos().powi(2);
+    let t1 = phi1.tan().powi(2);
+    let n1 = WGS84_A / (1.0 - e2 * phi1.sin().powi(2)).sqrt();
+    let r1 = WGS84_A * (1.0 - e2) / (1.0 - e2 * phi1.sin().powi(2)).powf(1.5);
+    let d = x / (n1 * UTM_K0);
+
+    let phi = phi1
+        - (n1 * phi1.tan() / r1)
+            * (d * d / 2.0 - (5.0 + 3.0 * t1 + 10.0 * c1 - 4.0 * c1 * c1 - 9.0 * ep2) * d.powi(4) / 24.0
+                + (61.0 + 90.0 * t1 + 298.0 * c1 + 45.0 * t1 * t1 - 252.0 * ep2 - 3.0 * c1 * c1) * d.powi(6) / 720.0);
+    let lambda = (d - (1.0 + 2.0 * t1 + c1) * d.powi(3) / 6.0
+        + (5.0 - 2.0 * c1 + 28.0 * t1 - 3.0 * c1 * c1 + 8.0 * ep2 + 24.0 * t1 * t1) * d.powi(5) / 120.0)
+        / phi1.cos();
+
+    (zone.central_meridian() + lambda.to_degrees(), phi.to_degrees())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Baghdad in UTM 38N (EPSG:32638), the worked example in GeographicLib's GeoConvert
+    // documentation: 33.3N 44.4E is 444140.54 E 3684706.36 N
+    #[test]
+    fn projects_a_known_point() {
+        let zone = UtmZone::for_position(44.4, 33.3);
+        assert_eq!(zone, UtmZone { zone: 38, north: true });
+        assert_eq!(zone.epsg(), 32638);
+
+        let (easting, northing) = geographic_to_utm(44.4, 33.3, zone);
+        assert!((easting - 444_140.54).abs() < 0.01, "easting {}", easting);
+        assert!((northing - 3_684_706.36).abs() < 0.01, "northing {}", northing);
+
+        let (longitude, latitude) = utm_to_geographic(444_140.54, 3_684_706.36, zone);
+        assert!((longitude - 44.4).abs() < 1e-7 && (latitude - 33.3).abs() < 1e-7, "{} {}", longitude, latitude);
+    }
+
+    #[test]
+    fn round_trips_across_a_zone() {
+        for zone in [UtmZone { zone: 31, north: true }, UtmZone { zone: 19, north: false }] {
+            let centre = zone.central_meridian();
+            let latitude = if zone.north { 54.0 } else { -40.0 };
+            for longitude in [centre - 2.9, centre, centre + 2.9] {
+                let (easting, northing) = geographic_to_utm(longitude, latitude, zone);
+                let (back_longitude, back_latitude) = utm_to_geographic(easting, northing, zone);
+                assert!((back_longitude - longitude).abs() < 1e-8, "{:?} {} came back as {}", zone, longitude, back_longitude);
+                assert!((back_latitude - latitude).abs() < 1e-8, "{:?} {} came back as {}", zone, latitude, back_latitude);
+            }
+        }
+    }
+
+    #[test]
+    fn converts_through_coordinate_systems() {
+        let zone = UtmZone { zone: 38, north: true };
+        let (easting, northing) = CoordinateSystem::Geographic.convert(44.4, 33.3, CoordinateSystem::Utm(zone)).unwrap();
+        assert!((easting - 444_140.54).abs() < 0.01 && (northing - 3_684_706.36).abs() < 0.01);
+        assert!(CoordinateSystem::UnknownGrid.convert(1.0, 2.0, CoordinateSystem::Geographic).is_none());
+    }
+}
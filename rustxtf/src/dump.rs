@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::io::Write;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Map, Value};
+
+use crate::headers::{header_type_name, FieldTable, HeaderMap, XTF_CHAN_INFO, XTF_FILE_HEADER, XTF_PING_CHAN_HEADER, XTF_PING_HEADER};
+use crate::record::Record;
+use crate::time::{format_time, ping_time};
+use crate::xtf_file::XtfFile;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleOutput {
+    // headers only
+    None,
+    // decoded sample values as a json array
+    Array,
+    // raw little endian sample bytes, base64 encoded, much smaller than Array
+    Base64,
+}
+
+
+// Header fields in table order, so related fields stay together and every run gives the same
+// output
+fn header_json(headers: &HeaderMap, table: &FieldTable) -> Value {
+    let mut fields = Map::new();
+    for (key, _, _) in table {
+        if let Some(value) = headers.get(*key) {
+            fields.insert(key.to_string(), json!(value));
+        }
+    }
+    Value::Object(fields)
+}
+
+
+// Writes one json object per line: the file header, each chan info, then every record in
+// file order. The lines are written as they're made but the file itself is already in memory.
+// Returns the number of lines written
+pub fn write_ndjson<W: Write>(xtf: &XtfFile, out: &mut W, samples: SampleOutput) -> Result<usize, Box<dyn Error>> {
+    let mut lines = 0;
+    let mut write_line = |out: &mut W, value: Value| -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(&mut *out, &value)?;
+        out.write_all(b"\n")?;
+        lines += 1;
+        Ok(())
+    };
+
+    write_line(out, json!({ "record": "file_header", "header": header_json(&xtf.file_header, XTF_FILE_HEADER) }))?;
+
+    for (channel, channel_info) in xtf.channel_infos.iter().enumerate() {
+        write_line(out, json!({ "record": "channel_info", "channel": channel, "header": header_json(channel_info, XTF_CHAN_INFO) }))?;
+    }
+
+    for entry in &xtf.index.entries {
+        let line = match xtf.record(entry)? {
+            Record::Sonar(ping) => {
+                let channels: Vec<Value> = ping
+                    .channels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, channel)| {
+                        let mut value = json!({ "header": header_json(&channel.header, XTF_PING_CHAN_HEADER) });
+                        match samples {
+                            SampleOutput::None => {}
+                            SampleOutput::Array => value["samples"] = json!(xtf.samples(&ping, i)),
+                            SampleOutput::Base64 => value["samples_base64"] = json!(BASE64.encode(ping.raw_samples(i))),
+                        }
+                        value
+                    })
+                    .collect();
+
+                json!({
+                    "record": "ping",
+                    "offset": entry.offset,
+                    "time": ping_time(&ping.header).map(format_time),
+                    "header": header_json(&ping.header, XTF_PING_HEADER),
+                    "channels": channels,
+                })
+            }
+            Record::Unknown { header_type, bytes } => json!({
+                "record": "other",
+                "offset": entry.offset,
+                "header_type": header_type,
+                "name": header_type_name(header_type),
+                "length": bytes.len(),
+            }),
+        };
+
+        write_line(out, line)?;
+    }
+
+    out.flush()?;
+    Ok(lines)
+}
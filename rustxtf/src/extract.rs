@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::headers::XTF_HEADER_SONAR;
+use crate::index::IndexEntry;
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtractRange {
+    // seconds since epoch, inclusive
+    Time(f64, f64),
+    // PingNumber, inclusive
+    PingNumber(u32, u32),
+}
+
+impl ExtractRange {
+    fn contains(&self, entry: &IndexEntry) -> bool {
+        match *self {
+            ExtractRange::Time(start, end) => entry.time >= start && entry.time <= end,
+            ExtractRange::PingNumber(first, last) => entry.ping_number >= first && entry.ping_number <= last,
+        }
+    }
+}
+
+
+// Index entries to copy for range: every ping inside it, plus any non sonar records that
+// sit between the first and last of those pings in the file
+pub fn select_records(xtf: &XtfFile, range: ExtractRange) -> Vec<&IndexEntry> {
+    let entries = &xtf.index.entries;
+    let is_selected_ping = |entry: &IndexEntry| entry.header_type == XTF_HEADER_SONAR && range.contains(entry);
+
+    let first = match entries.iter().position(is_selected_ping) {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let last = entries.iter().rposition(is_selected_ping).unwrap_or(first);
+
+    entries[first..=last]
+        .iter()
+        .filter(|entry| entry.header_type != XTF_HEADER_SONAR || range.contains(entry))
+        .collect()
+}
+
+
+// Copies the file header, chan infos and the records in range into a new file, returns records written
+pub fn extract<P: AsRef<Path>>(xtf: &XtfFile, range: ExtractRange, output: P) -> Result<usize, Box<dyn Error>> {
+    let selected = select_records(xtf, range);
+    if selected.is_empty() {
+        return Err(format!("No pings found in {:?}", range).into());
+    }
+
+    check_not_input(&output, &[&xtf.path])?;
+    let mut writer = XtfWriter::create(output, xtf.header_bytes())?;
+    for entry in selected {
+        writer.write_record(xtf.record_bytes(entry)?)?;
+    }
+
+    writer.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{assert_consistent, file_header, other_record, ping_record, TempPath, TestPing};
+
+    // Pings 0 to 9 with a navigation record after ping 4 and another before ping 0
+    fn source() -> XtfFile {
+        let mut bytes = file_header(2);
+        bytes.extend(other_record(107, 64));
+        for ping_number in 0..10 {
+            bytes.extend(ping_record(2, 32, &TestPing::numbered(ping_number)));
+            if ping_number == 4 {
+                bytes.extend(other_record(107, 64));
+            }
+        }
+        XtfFile::from_bytes(bytes).unwrap()
+    }
+
+    fn extracted(range: ExtractRange, name: &str) -> XtfFile {
+        let output = TempPath::new(name);
+        extract(&source(), range, &output.0).unwrap();
+        XtfFile::open(&output.0).unwrap()
+    }
+
+    #[test]
+    fn extracts_a_ping_range_with_the_records_inside_it() {
+        let xtf = extracted(ExtractRange::PingNumber(3, 6), "extract-pings.xtf");
+        assert_consistent(&xtf);
+
+        let types: Vec<(u8, u32)> = xtf.index.entries.iter().map(|entry| (entry.header_type, entry.ping_number)).collect();
+        assert_eq!(types, vec![(0, 3), (0, 4), (107, 0), (0, 5), (0, 6)]);
+        assert_eq!(xtf.number_of_sonar_channels(), 2);
+        assert!(xtf.index.pings().all(|entry| xtf.ping(entry).unwrap().channels.len() == 2));
+    }
+
+    #[test]
+    fn extracts_a_time_range() {
+        let start = TestPing::numbered(7).time;
+        let xtf = extracted(ExtractRange::Time(start - 0.1, start + 10.0), "extract-time.xtf");
+        assert_consistent(&xtf);
+        assert_eq!(xtf.index.pings().map(|entry| entry.ping_number).collect::<Vec<_>>(), vec![7, 8, 9]);
+    }
+
+    #[test]
+    fn refuses_an_empty_range() {
+        let output = TempPath::new("extract-empty.xtf");
+        assert!(extract(&source(), ExtractRange::PingNumber(20, 30), &output.0).is_err());
+        assert!(!output.0.exists());
+    }
+}
@@ -0,0 +1,322 @@
+use std::str::FromStr;
+
+use serde_derive::Serialize;
+
+use crate::waterfall::Waterfall;
+
+// Clean up filters over a waterfall, rows being pings and columns samples. NaNs (padding,
+// blanked water column) are left out of every window and stay NaN. Window sizes are in pixels
+// and rounded up to odd so the window is centred
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Filter {
+    // median of a size x size window, knocks out isolated spikes
+    Median(usize),
+    // Lee speckle filter: smooths flat areas, keeps edges. looks is the speckle's equivalent
+    // number of looks, 1 for single look sonar
+    Lee { size: usize, looks: f64 },
+    // Frost speckle filter, exponentially weighted by distance, more so in flat areas
+    Frost { size: usize, damping: f64 },
+    // mean over this many pings in each column
+    AlongTrack(usize),
+    // rescales pings whose overall level is off from their neighbours by more than `factor`
+    // times, the bright or dark lines periodic interference leaves across the waterfall
+    Stripes { window: usize, factor: f64 },
+}
+
+
+// "median:5", "lee:5", "lee:5:2" (looks), "frost:5", "frost:5:1.5" (damping), "along:5",
+// "stripes:15" or "stripes:15:1.5" (factor)
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Filter, String> {
+        let bad = || format!("can't understand filter {}, expected e.g. median:5, lee:5, frost:5, along:5 or stripes:15", text);
+        let mut parts = text.trim().split(':');
+        let name = parts.next().ok_or_else(bad)?.to_lowercase();
+        let numbers: Vec<f64> = parts.map(|part| part.parse::<f64>().map_err(|_| bad())).collect::<Result<_, _>>()?;
+        let size = *numbers.first().ok_or_else(bad)? as usize;
+        let extra = |default: f64| numbers.get(1).copied().unwrap_or(default);
+
+        match name.as_str() {
+            "median" => Ok(Filter::Median(size)),
+            "lee" => Ok(Filter::Lee { size, looks: extra(1.0) }),
+            "frost" => Ok(Filter::Frost { size, damping: extra(1.0) }),
+            "along" => Ok(Filter::AlongTrack(size)),
+            "stripes" => Ok(Filter::Stripes { window: size, factor: extra(1.5) }),
+            _ => Err(bad()),
+        }
+    }
+}
+
+
+impl Filter {
+    pub fn apply(&self, waterfall: &Waterfall) -> Waterfall {
+        let pixels = match *self {
+            Filter::Median(size) => map_windows(waterfall, size, |_, window| median(window)),
+            Filter::Lee { size, looks } => map_windows(waterfall, size, |value, window| lee(value, window, looks)),
+            Filter::Frost { size, damping } => frost(waterfall, size, damping),
+            Filter::AlongTrack(size) => along_track(waterfall, size),
+            Filter::Stripes { window, factor } => remove_stripes(waterfall, window, factor),
+        };
+        Waterfall { pixels, ..*waterfall }
+    }
+}
+
+
+pub fn apply_filters(waterfall: Waterfall, filters: &[Filter]) -> Waterfall {
+    filters.iter().fold(waterfall, |waterfall, filter| filter.apply(&waterfall))
+}
+
+
+fn half_width(size: usize) -> usize {
+    size.max(1) / 2
+}
+
+
+// Calls f with each finite pixel and the finite values of the size x size window around it
+fn map_windows<F: Fn(f32, &mut Vec<f32>) -> f32>(waterfall: &Waterfall, size: usize, f: F) -> Vec<f32> {
+    let (width, height) = (waterfall.width, waterfall.height);
+    let half = half_width(size);
+    let mut window = Vec::with_capacity((2 * half + 1) * (2 * half + 1));
+
+    (0..width * height)
+        .map(|i| {
+            let value = waterfall.pixels[i];
+            if !value.is_finite() {
+                return value;
+            }
+            let (row, column) = (i / width, i % width);
+            window.clear();
+            for r in row.saturating_sub(half)..(row + half + 1).min(height) {
+                for c in column.saturating_sub(half)..(column + half + 1).min(width) {
+                    let v = waterfall.pixels[r * width + c];
+                    if v.is_finite() {
+                        window.push(v);
+                    }
+                }
+            }
+            f(value, &mut window)
+        })
+        .collect()
+}
+
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    values[values.len() / 2]
+}
+
+
+fn mean_and_variance(values: &[f32]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().map(|v| *v as f64).sum::<f64>() / n;
+    let variance = values.iter().map(|v| (*v as f64 - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance)
+}
+
+
+// Speckle is multiplicative with coefficient of variation 1 / sqrt(looks). Where the window
+// varies no more than that it's speckle and gets the mean, where it varies more there's
+// structure and the pixel is kept
+fn lee(value: f32, window: &[f32], looks: f64) -> f32 {
+    let (mean, variance) = mean_and_variance(window);
+    if mean <= 0.0 {
+        return value;
+    }
+    let noise = 1.0 / looks.max(f64::EPSILON);
+    let local = variance / (mean * mean);
+    let weight = if local > 0.0 { (1.0 - noise / local).clamp(0.0, 1.0) } else { 0.0 };
+    (mean + weight * (value as f64 - mean)) as f32
+}
+
+
+fn frost(waterfall: &Waterfall, size: usize, damping: f64) -> Vec<f32> {
+    let (width, height) = (waterfall.width, waterfall.height);
+    let half = half_width(size) as isize;
+    let mut window = Vec::new();
+
+    (0..width * height)
+        .map(|i| {
+            let value = waterfall.pixels[i];
+            if !value.is_finite() {
+                return value;
+            }
+            let (row, column) = ((i / width) as isize, (i % width) as isize);
+
+            window.clear();
+            for r in (row - half).max(0)..(row + half + 1).min(height as isize) {
+                for c in (column - half).max(0)..(column + half + 1).min(width as isize) {
+                    let v = waterfall.pixels[r as usize * width + c as usize];
+                    if v.is_finite() {
+                        window.push((v, ((r - row) as f64).hypot((c - column) as f64)));
+                    }
+                }
+            }
+
+            let values: Vec<f32> = window.iter().map(|(v, _)| *v).collect();
+            let (mean, variance) = mean_and_variance(&values);
+            let k = if mean > 0.0 { damping * variance / (mean * mean) } else { 0.0 };
+            let (sum, weights) = window.iter().fold((0.0, 0.0), |(sum, weights), (v, distance)| {
+                let weight = (-k * distance).exp();
+                (sum + weight * *v as f64, weights + weight)
+            });
+            (sum / weights) as f32
+        })
+        .collect()
+}
+
+
+fn along_track(waterfall: &Waterfall, size: usize) -> Vec<f32> {
+    let (width, height) = (waterfall.width, waterfall.height);
+    let half = half_width(size);
+
+    (0..width * height)
+        .map(|i| {
+            let value = waterfall.pixels[i];
+            if !value.is_finite() {
+                return value;
+            }
+            let (row, column) = (i / width, i % width);
+            let (sum, count) = (row.saturating_sub(half)..(row + half + 1).min(height))
+                .map(|r| waterfall.pixels[r * width + column])
+                .filter(|v| v.is_finite())
+                .fold((0.0, 0), |(sum, count), v| (sum + v as f64, count + 1));
+            (sum / count as f64) as f32
+        })
+        .collect()
+}
+
+
+fn remove_stripes(waterfall: &Waterfall, window: usize, factor: f64) -> Vec<f32> {
+    let (width, height) = (waterfall.width, waterfall.height);
+    let half = half_width(window);
+
+    // each ping's level is the median of its samples, so a few bright targets don't count
+    let levels: Vec<Option<f64>> = (0..height)
+        .map(|row| {
+            let mut values: Vec<f32> = waterfall.pixels[row * width..(row + 1) * width].iter().copied().filter(|v| v.is_finite()).collect();
+            if values.is_empty() {
+                None
+            } else {
+                Some(median(&mut values) as f64)
+            }
+        })
+        .collect();
+
+    let mut pixels = waterfall.pixels.clone();
+    for row in 0..height {
+        let Some(level) = levels[row].filter(|level| *level > 0.0) else { continue };
+        let mut around: Vec<f32> = levels[row.saturating_sub(half)..(row + half + 1).min(height)].iter().flatten().map(|l| *l as f32).collect();
+        let expected = median(&mut around) as f64;
+        if expected <= 0.0 {
+            continue;
+        }
+
+        let ratio = level / expected;
+        if ratio > factor || ratio < 1.0 / factor {
+            for value in &mut pixels[row * width..(row + 1) * width] {
+                *value = (*value as f64 / ratio) as f32;
+            }
+        }
+    }
+    pixels
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waterfall(width: usize, pixels: &[f32]) -> Waterfall {
+        Waterfall { width, height: pixels.len() / width, pixels: pixels.to_vec() }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!("median:5".parse(), Ok(Filter::Median(5)));
+        assert_eq!("Lee:3".parse(), Ok(Filter::Lee { size: 3, looks: 1.0 }));
+        assert_eq!("frost:5:1.5".parse(), Ok(Filter::Frost { size: 5, damping: 1.5 }));
+        assert_eq!("along:7".parse(), Ok(Filter::AlongTrack(7)));
+        assert_eq!("stripes:15".parse(), Ok(Filter::Stripes { window: 15, factor: 1.5 }));
+        assert!("median".parse::<Filter>().is_err());
+        assert!("blur:3".parse::<Filter>().is_err());
+        assert!("lee:3:x".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn median_removes_a_spike_and_keeps_nans() {
+        let input = waterfall(3, &[1.0, 2.0, 3.0, 4.0, 100.0, 6.0, 7.0, 8.0, f32::NAN]);
+        let output = Filter::Median(3).apply(&input);
+
+        assert_eq!(output.pixels[4], 6.0, "median of the finite 8 around the spike");
+        assert_eq!(output.pixels[0], 4.0);
+        assert!(output.pixels[8].is_nan());
+        assert_eq!((output.width, output.height), (3, 3));
+    }
+
+    #[test]
+    fn lee_smooths_speckle_and_keeps_structure() {
+        // varies much less than single look speckle would, so it's all speckle
+        let speckle = waterfall(3, &[10.0, 12.0, 10.0, 12.0, 10.0, 12.0, 10.0, 12.0, 10.0]);
+        assert!(close(Filter::Lee { size: 3, looks: 1.0 }.apply(&speckle).pixels[4], 98.0 / 9.0));
+
+        // with many looks the same variation is structure and the pixel is nearly kept
+        let kept = Filter::Lee { size: 3, looks: 10_000.0 }.apply(&speckle).pixels[4];
+        assert!((kept - 10.0).abs() < 0.1, "{}", kept);
+
+        let flat = waterfall(2, &[5.0; 4]);
+        assert_eq!(Filter::Lee { size: 3, looks: 1.0 }.apply(&flat).pixels, [5.0; 4]);
+    }
+
+    #[test]
+    fn frost_weights_by_distance() {
+        let input = waterfall(3, &[1.0, 2.0, 3.0, 4.0, 20.0, 6.0, 7.0, 8.0, 9.0]);
+
+        // no damping is a plain mean of the window
+        assert!(close(Filter::Frost { size: 3, damping: 0.0 }.apply(&input).pixels[4], 60.0 / 9.0));
+        // damping pulls the result towards the centre pixel
+        let damped = Filter::Frost { size: 3, damping: 5.0 }.apply(&input).pixels[4];
+        assert!(damped > 60.0 / 9.0 && damped < 20.0, "{}", damped);
+
+        let flat = waterfall(3, &[5.0, 5.0, f32::NAN, 5.0, 5.0, 5.0]);
+        let output = Filter::Frost { size: 3, damping: 1.0 }.apply(&flat);
+        assert!(output.pixels[2].is_nan());
+        assert!(output.pixels.iter().filter(|v| v.is_finite()).all(|&v| close(v, 5.0)));
+    }
+
+    #[test]
+    fn along_track_averages_each_column() {
+        let input = waterfall(2, &[1.0, 10.0, 2.0, f32::NAN, 6.0, 30.0]);
+        let output = Filter::AlongTrack(3).apply(&input);
+        assert_eq!(output.pixels[..3], [1.5, 10.0, 3.0]);
+        assert!(output.pixels[3].is_nan());
+        assert_eq!(output.pixels[4..], [4.0, 30.0]);
+    }
+
+    #[test]
+    fn rescales_a_striped_ping() {
+        let mut pixels = vec![10.0; 15];
+        pixels[6..9].copy_from_slice(&[40.0, 50.0, 60.0]);
+        pixels[12..15].copy_from_slice(&[12.0, 12.0, 12.0]);
+        let output = Filter::Stripes { window: 5, factor: 1.5 }.apply(&waterfall(3, &pixels));
+
+        // the middle ping is 5 times its neighbours' level, the last only 1.2 times
+        assert_eq!(output.pixels[6..9], [8.0, 10.0, 12.0]);
+        assert_eq!(output.pixels[12..15], [12.0, 12.0, 12.0]);
+        assert_eq!(output.pixels[..6], [10.0; 6]);
+    }
+
+    #[test]
+    fn applies_filters_in_order() {
+        let input = waterfall(3, &[1.0, 2.0, 3.0, 4.0, 100.0, 6.0, 7.0, 8.0, 9.0]);
+        let output = apply_filters(input.clone(), &[Filter::Median(3), Filter::AlongTrack(1)]);
+        assert_eq!(output.pixels, Filter::Median(3).apply(&input).pixels);
+        assert_eq!(apply_filters(input.clone(), &[]).pixels, input.pixels);
+    }
+}
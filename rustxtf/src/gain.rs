@@ -0,0 +1,161 @@
+use serde_derive::Serialize;
+
+use crate::headers::get_number;
+use crate::record::Ping;
+
+
+// Time varying gain: spreading * log10(r) + 2 * absorption * r / 1000 dB at range r metres
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Tvg {
+    pub spreading: f64,  // dB per decade of range, 20 - 40 typically
+    pub absorption: f64, // dB per km one way, depends on frequency
+}
+
+impl Tvg {
+    pub fn gain_db(&self, range: f64) -> f64 {
+        // closer than a metre would turn into attenuation, hold it at zero spreading gain
+        let range = range.max(1.0);
+        self.spreading * range.log10() + 2.0 * self.absorption * range / 1000.0
+    }
+}
+
+
+// Range of the far edge of a channel's samples. From the two way time and sound velocity when
+// both are set, otherwise the logged SlantRange
+pub fn channel_range(ping: &Ping, channel_index: usize) -> f64 {
+    let channel = &ping.channels[channel_index].header;
+    let duration = get_number(channel, "TimeDuration").unwrap_or(0.0);
+    let sound_velocity = get_number(&ping.header, "SoundVelocity").unwrap_or(0.0);
+
+    if duration > 0.0 && sound_velocity > 0.0 {
+        duration * sound_velocity / 2.0
+    } else {
+        get_number(channel, "SlantRange").unwrap_or(0.0)
+    }
+}
+
+
+// Applies the gain in place, sample i being at range (i + 0.5) * max_range / n
+pub fn apply_tvg(samples: &mut [f32], max_range: f64, tvg: Tvg) {
+    let spacing = max_range / samples.len().max(1) as f64;
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let gain = 10f64.powf(tvg.gain_db((i as f64 + 0.5) * spacing) / 20.0);
+        *sample = (*sample as f64 * gain) as f32;
+    }
+}
+
+
+// Empirical across track normalisation. Each sample is divided by the mean of its sample bin
+// over the `window` pings centred on it, then scaled by the mean of the whole set so levels stay
+// in the original units. Rows can be different lengths, NaNs are left out of the means
+pub fn normalise_across_track(rows: &mut [Vec<f32>], window: usize) {
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if rows.is_empty() || width == 0 {
+        return;
+    }
+    let half = window.max(1) / 2;
+
+    let (mut total, mut count) = (0.0, 0usize);
+    for value in rows.iter().flatten().filter(|v| v.is_finite()) {
+        total += *value as f64;
+        count += 1;
+    }
+    if count == 0 {
+        return;
+    }
+    let overall_mean = total / count as f64;
+
+    // running per bin sums over the window, rows are added and dropped as it slides
+    let mut sums = vec![0.0f64; width];
+    let mut counts = vec![0.0f64; width];
+    let update = |row: &[f32], sign: f64, sums: &mut [f64], counts: &mut [f64]| {
+        for (bin, value) in row.iter().enumerate().filter(|(_, v)| v.is_finite()) {
+            sums[bin] += sign * *value as f64;
+            counts[bin] += sign;
+        }
+    };
+
+    let originals: Vec<Vec<f32>> = rows.to_vec();
+    let (mut window_start, mut window_end) = (0, 0); // rows in the window, end exclusive
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        let (start, end) = (i.saturating_sub(half), (i + half + 1).min(originals.len()));
+        while window_end < end {
+            update(&originals[window_end], 1.0, &mut sums, &mut counts);
+            window_end += 1;
+        }
+        while window_start < start {
+            update(&originals[window_start], -1.0, &mut sums, &mut counts);
+            window_start += 1;
+        }
+
+        for (bin, value) in row.iter_mut().enumerate() {
+            let mean = if counts[bin] > 0.5 { sums[bin] / counts[bin] } else { 0.0 };
+            if value.is_finite() && mean > 0.0 {
+                *value = (*value as f64 / mean * overall_mean) as f32;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{write_field, PING_HEADER_LENGTH, XTF_PING_CHAN_HEADER, XTF_PING_HEADER};
+    use crate::record::parse_ping;
+    use crate::test_data::{ping_record, TestPing};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn works_out_tvg_gain() {
+        let tvg = Tvg { spreading: 30.0, absorption: 10.0 };
+        assert!((tvg.gain_db(100.0) - 62.0).abs() < 1e-9);
+        // no spreading loss to make up for inside a metre
+        assert!((tvg.gain_db(0.5) - 0.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn applies_tvg_at_each_sample_range() {
+        // samples at 5 m and 15 m, 20 log r is a gain of r
+        let mut samples = [1.0, 2.0];
+        apply_tvg(&mut samples, 20.0, Tvg { spreading: 20.0, absorption: 0.0 });
+        assert!(close(samples[0], 5.0) && close(samples[1], 30.0), "{:?}", samples);
+    }
+
+    #[test]
+    fn takes_range_from_two_way_time_when_it_can() {
+        let mut bytes = ping_record(1, 8, &TestPing::numbered(0));
+        let ping = parse_ping(bytes.clone(), &[2]).unwrap();
+        assert_eq!(channel_range(&ping, 0), 50.0);
+
+        write_field(XTF_PING_HEADER, &mut bytes, 0, "SoundVelocity", 1500.0).unwrap();
+        write_field(XTF_PING_CHAN_HEADER, &mut bytes, PING_HEADER_LENGTH, "TimeDuration", 0.1).unwrap();
+        let ping = parse_ping(bytes, &[2]).unwrap();
+        assert!((channel_range(&ping, 0) - 75.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn normalises_each_bin_by_its_mean() {
+        let mut rows = vec![vec![1.0, 2.0], vec![3.0, 4.0]];
+        normalise_across_track(&mut rows, 3);
+        // bin means 2 and 3, overall mean 2.5
+        let expected = [[1.25, 5.0 / 3.0], [3.75, 10.0 / 3.0]];
+        for (row, expected) in rows.iter().zip(expected) {
+            assert!(row.iter().zip(expected).all(|(&a, b)| close(a, b)), "{:?}", rows);
+        }
+    }
+
+    #[test]
+    fn normalises_over_a_sliding_window() {
+        // a window of one ping flattens everything to the overall mean
+        let mut rows = vec![vec![1.0, 2.0, f32::NAN], vec![3.0], vec![4.0, 6.0]];
+        normalise_across_track(&mut rows, 1);
+        assert!(rows.iter().flatten().filter(|v| v.is_finite()).all(|&v| close(v, 3.2)), "{:?}", rows);
+        assert!(rows[0][2].is_nan());
+        assert_eq!(rows[1].len(), 1);
+    }
+}
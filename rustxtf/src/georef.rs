@@ -0,0 +1,222 @@
+use serde_derive::Serialize;
+
+use crate::coords::{is_position, projected_for, CoordinateSystem, UtmZone};
+use crate::heading::ping_heading;
+use crate::headers::get_number;
+use crate::record::Ping;
+use crate::xtf_file::XtfFile;
+
+// Puts sidescan samples on the ground. A sample is laid out from the sensor position
+// perpendicular to the heading, port or starboard by TypeOfChannel, at its ground range with
+// the channel's OffsetX (starboard) and OffsetY (forward) mounting offsets added. Flat seabed
+// at the sensor's altitude
+
+pub const CHANNEL_TYPE_PORT: u8 = 1;
+pub const CHANNEL_TYPE_STARBOARD: u8 = 2;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SamplePosition {
+    pub x: f64, // easting or longitude, in the georeferencer's target system
+    pub y: f64,
+    pub slant_range: f64,
+    pub ground_range: f64, // 0 for samples still in the water column
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Georeferencer {
+    pub source: CoordinateSystem, // what the ping headers are in
+    pub target: CoordinateSystem, // what positions come out in
+}
+
+
+impl Georeferencer {
+    // Source from NavUnits (input_zone for files in metres), target the given UTM zone or the
+    // standard zone for the first ping with a position
+    pub fn for_file(xtf: &XtfFile, input_zone: Option<UtmZone>, output_zone: Option<UtmZone>) -> Georeferencer {
+        let source = CoordinateSystem::of_file(&xtf.file_header, input_zone);
+        let first = xtf.index.pings().find_map(|entry| {
+            let field = |name: &str| xtf.ping_field(entry, name).unwrap_or(f64::NAN);
+            let position = sensor_position_of(field("SensorXcoordinate"), field("SensorYcoordinate"), field("ShipXcoordinate"), field("ShipYcoordinate"));
+            position.and_then(|(x, y)| source.convert(x, y, CoordinateSystem::Geographic))
+        });
+
+        let target = match first {
+            Some((longitude, latitude)) => projected_for(CoordinateSystem::Geographic, longitude, latitude, output_zone),
+            None => projected_for(source, 0.0, 0.0, output_zone),
+        };
+        Georeferencer { source, target }
+    }
+
+    fn source_sensor_position(&self, ping: &Ping) -> Option<(f64, f64)> {
+        let field = |name: &str| get_number(&ping.header, name).unwrap_or(f64::NAN);
+        sensor_position_of(field("SensorXcoordinate"), field("SensorYcoordinate"), field("ShipXcoordinate"), field("ShipYcoordinate"))
+    }
+
+    // Sensor position in the target system
+    pub fn sensor_position(&self, ping: &Ping) -> Option<(f64, f64)> {
+        let (x, y) = self.source_sensor_position(ping)?;
+        self.source.convert(x, y, self.target)
+    }
+
+    // Position of whatever is `ground_range` metres out to the side of one of the ping's
+    // channels. None when the ping has no position or heading or the channel isn't port or
+    // starboard
+    pub fn ground_position(&self, xtf: &XtfFile, ping: &Ping, channel_index: usize, ground_range: f64) -> Option<(f64, f64)> {
+        let channel_number = ping.channels.get(channel_index)?.channel_number as usize;
+        let info = xtf.channel_infos.get(channel_number)?;
+        let side = match get_number(info, "TypeOfChannel").map(|t| t as u8) {
+            Some(CHANNEL_TYPE_PORT) => -1.0,
+            Some(CHANNEL_TYPE_STARBOARD) => 1.0,
+            _ => return None,
+        };
+
+        let (x, y) = self.source_sensor_position(ping)?;
+        let heading = ping_heading(&ping.header)?;
+        let starboard = get_number(info, "OffsetX").unwrap_or(0.0) + side * ground_range;
+        let forward = get_number(info, "OffsetY").unwrap_or(0.0);
+
+        // offset in the source system, where north is true north for geographic positions,
+        // rather than in UTM where grid north is off by the meridian convergence
+        let distance = starboard.hypot(forward);
+        let bearing = heading + starboard.atan2(forward).to_degrees();
+        let (x, y) = self.source.offset(x, y, distance, bearing);
+        self.source.convert(x, y, self.target)
+    }
+
+    // Position of one sample, sample i being at slant range (i + 0.5) * SlantRange / NumSamples
+    pub fn sample_position(&self, xtf: &XtfFile, ping: &Ping, channel_index: usize, sample_index: usize) -> Option<SamplePosition> {
+        let channel = ping.channels.get(channel_index)?;
+        if sample_index >= channel.num_samples {
+            return None;
+        }
+
+        let full_range = get_number(&channel.header, "SlantRange").filter(|range| *range > 0.0)?;
+        let slant_range = (sample_index as f64 + 0.5) * full_range / channel.num_samples as f64;
+        let altitude = get_number(&ping.header, "SensorPrimaryAltitude").filter(|a| a.is_finite()).unwrap_or(0.0).max(0.0);
+        let ground_range = if slant_range > altitude { (slant_range * slant_range - altitude * altitude).sqrt() } else { 0.0 };
+
+        let (x, y) = self.ground_position(xtf, ping, channel_index, ground_range)?;
+        Some(SamplePosition { x, y, slant_range, ground_range })
+    }
+}
+
+
+// Towfish position when the logger filled it in, otherwise the ship's
+fn sensor_position_of(sensor_x: f64, sensor_y: f64, ship_x: f64, ship_y: f64) -> Option<(f64, f64)> {
+    if is_position(sensor_x, sensor_y) {
+        Some((sensor_x, sensor_y))
+    } else if is_position(ship_x, ship_y) {
+        Some((ship_x, ship_y))
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{write_field, CHAN_INFO_LENGTH, FILE_HEADER_LENGTH, XTF_CHAN_INFO, XTF_FILE_HEADER, XTF_PING_HEADER};
+    use crate::test_data::{file_header, ping_record, xtf_bytes, TestPing};
+
+    const X: f64 = 500_000.0;
+    const Y: f64 = 6_000_000.0;
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+    }
+
+    // A ping in metres with port channel 0 and starboard channel 1, 10 samples over 50 m and
+    // 10 m altitude. Both channels get the same OffsetX and OffsetY
+    fn ping_in_metres(headings: &[(&str, f64)], offset_x: f64, offset_y: f64) -> (XtfFile, Ping) {
+        let mut bytes = file_header(2);
+        write_field(XTF_FILE_HEADER, &mut bytes, 0, "NavUnits", 0.0).unwrap();
+        for channel in 0..2 {
+            write_field(XTF_CHAN_INFO, &mut bytes, FILE_HEADER_LENGTH + channel * CHAN_INFO_LENGTH, "OffsetX", offset_x).unwrap();
+            write_field(XTF_CHAN_INFO, &mut bytes, FILE_HEADER_LENGTH + channel * CHAN_INFO_LENGTH, "OffsetY", offset_y).unwrap();
+        }
+
+        let mut ping = ping_record(2, 10, &TestPing { x: X, y: Y, ..TestPing::numbered(0) });
+        for &(name, value) in headings {
+            write_field(XTF_PING_HEADER, &mut ping, 0, name, value).unwrap();
+        }
+        bytes.extend(ping);
+
+        let xtf = XtfFile::from_bytes(bytes).unwrap();
+        let ping = xtf.ping(&xtf.index.entries[0]).unwrap();
+        (xtf, ping)
+    }
+
+    const GRID: Georeferencer = Georeferencer { source: CoordinateSystem::UnknownGrid, target: CoordinateSystem::UnknownGrid };
+
+    // sample 3 is at 17.5 m slant range
+    fn ground_range() -> f64 {
+        (17.5f64 * 17.5 - 10.0 * 10.0).sqrt()
+    }
+
+    #[test]
+    fn puts_port_and_starboard_either_side_of_the_heading() {
+        let (xtf, ping) = ping_in_metres(&[("SensorHeading", 90.0)], 0.0, 0.0);
+
+        let starboard = GRID.sample_position(&xtf, &ping, 1, 3).unwrap();
+        assert!((starboard.slant_range - 17.5).abs() < 1e-9);
+        assert!((starboard.ground_range - ground_range()).abs() < 1e-9);
+        // heading east starboard is south and port north
+        assert!(close((starboard.x, starboard.y), (X, Y - ground_range())), "{:?}", starboard);
+        let port = GRID.sample_position(&xtf, &ping, 0, 3).unwrap();
+        assert!(close((port.x, port.y), (X, Y + ground_range())), "{:?}", port);
+    }
+
+    #[test]
+    fn takes_the_gyro_when_there_is_no_sensor_heading() {
+        // the sensor heading wins when it's set
+        let (xtf, ping) = ping_in_metres(&[("SensorHeading", 90.0), ("ShipGyro", 180.0)], 0.0, 0.0);
+        let starboard = GRID.sample_position(&xtf, &ping, 1, 3).unwrap();
+        assert!(close((starboard.x, starboard.y), (X, Y - ground_range())));
+
+        // heading south starboard is west
+        let (xtf, ping) = ping_in_metres(&[("SensorHeading", 0.0), ("ShipGyro", 180.0)], 0.0, 0.0);
+        let starboard = GRID.sample_position(&xtf, &ping, 1, 3).unwrap();
+        assert!(close((starboard.x, starboard.y), (X - ground_range(), Y)), "{:?}", starboard);
+    }
+
+    #[test]
+    fn adds_mounting_offsets_in_the_heading_frame() {
+        // 1 m to starboard and 2 m forward of the sensor, heading east
+        let (xtf, ping) = ping_in_metres(&[("SensorHeading", 90.0)], 1.0, 2.0);
+
+        let starboard = GRID.sample_position(&xtf, &ping, 1, 3).unwrap();
+        assert!(close((starboard.x, starboard.y), (X + 2.0, Y - 1.0 - ground_range())), "{:?}", starboard);
+        let port = GRID.sample_position(&xtf, &ping, 0, 3).unwrap();
+        assert!(close((port.x, port.y), (X + 2.0, Y - 1.0 + ground_range())), "{:?}", port);
+    }
+
+    #[test]
+    fn puts_water_column_samples_under_the_sensor() {
+        let (xtf, ping) = ping_in_metres(&[("SensorHeading", 90.0)], 0.0, 0.0);
+        let nadir = GRID.sample_position(&xtf, &ping, 1, 0).unwrap();
+        assert_eq!(nadir.ground_range, 0.0);
+        assert!(close((nadir.x, nadir.y), (X, Y)));
+
+        assert!(GRID.sample_position(&xtf, &ping, 1, 10).is_none());
+        assert!(GRID.sample_position(&xtf, &ping, 2, 0).is_none());
+    }
+
+    #[test]
+    fn projects_geographic_files_to_their_utm_zone() {
+        let pings = [TestPing { heading: 90.0, ..TestPing::numbered(0) }];
+        let xtf = XtfFile::from_bytes(xtf_bytes(2, 10, &pings)).unwrap();
+        let georeferencer = Georeferencer::for_file(&xtf, None, None);
+        assert_eq!(georeferencer.target, CoordinateSystem::Utm(UtmZone { zone: 31, north: true }));
+
+        let ping = xtf.ping(&xtf.index.entries[0]).unwrap();
+        let sensor = georeferencer.sensor_position(&ping).unwrap();
+        let starboard = georeferencer.sample_position(&xtf, &ping, 1, 3).unwrap();
+        let (east, north) = (starboard.x - sensor.0, starboard.y - sensor.1);
+
+        // south of the sensor give or take scale factor and grid convergence
+        assert!((east.hypot(north) / ground_range() - 1.0).abs() < 0.001, "{} {}", east, north);
+        assert!(north < 0.0 && east.abs() < 0.05 * ground_range(), "{} {}", east, north);
+    }
+}
@@ -0,0 +1,591 @@
+use std::fmt;
+use std::error::Error;
+use std::sync::OnceLock;
+use regex::Regex;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+// header starts and lengths
+pub const FILE_HEADER_LENGTH: usize = 256;
+pub const CHAN_INFO_LENGTH: usize = 128;
+pub const FILE_HEADER_BLOCK_LENGTH: usize = 1024; // file header + chan infos are padded to multiples of this
+pub const PING_HEADER_LENGTH: usize = 256;
+pub const PING_CHAN_HEADER_LENGTH: usize = 64;
+
+pub const MAGIC_NUMBER: u16 = 64206; // 0xFACE
+
+// Header types we know how to interpret, everything else is passed through as raw bytes
+pub const XTF_HEADER_SONAR: u8 = 0;
+pub const XTF_HEADER_POS_RAW_NAVIGATION: u8 = 107;
+
+// Names from the XTF spec, for reporting
+pub fn header_type_name(header_type: u8) -> &'static str {
+    match header_type {
+        0 => "SONAR",
+        1 => "NOTES",
+        2 => "BATHY",
+        3 => "ATTITUDE",
+        4 => "FORWARD",
+        5 => "ELAC",
+        6 => "RAW_SERIAL",
+        7 => "EMBED_HEAD",
+        8 => "HIDDEN_SONAR",
+        9 => "SEAVIEW_PROCESSED_BATHY",
+        10 => "SEAVIEW_DEPTHS",
+        11 => "RSVD_HIGHSPEED_SENSOR",
+        12 => "ECHOSTRENGTH",
+        13 => "GEOREC",
+        14 => "KLEIN_RAW_BATHY",
+        15 => "HIGHSPEED_SENSOR2",
+        16 => "ELAC_XSE",
+        17 => "BATHY_XYZA",
+        18 => "K5000_BATHY_IQ",
+        19 => "BATHY_SNIPPET",
+        20 => "GPS",
+        21 => "STAT",
+        22 => "SINGLEBEAM",
+        23 => "GYRO",
+        24 => "TRACKPOINT",
+        25 => "MULTIBEAM",
+        26 => "Q_SINGLEBEAM",
+        27 => "Q_MULTITARGET",
+        28 => "Q_MULTIBEAM",
+        50 => "TIME",
+        107 => "POS_RAW_NAVIGATION",
+        _ => "UNKNOWN",
+    }
+}
+
+// (name, format, offset) where format follows python struct codes, z is zeroed padding
+pub type FieldTable = [(&'static str, &'static str, usize)];
+
+pub type HeaderMap = HashMap<String, Option<HeaderValue>>;
+
+pub const XTF_FILE_HEADER: &FieldTable = &[
+    ("FileFormat", "b", 0), // bytes as num
+    ("SystemType", "b", 1), // byte as num
+    ("RecordingProgramName", "8s", 2),
+    ("RecordingProgramVersion", "8s", 10),
+    ("SonarName", "16s", 18),
+    ("SensorsType", "H", 34),
+    ("NoteString", "64s", 36),
+    ("ThisFileName", "64s", 100),
+    ("NavUnits", "H", 164),
+    ("NumberOfSonarChannels", "H", 166),
+    ("NumberOfBathymetryChannels", "H", 168),
+    ("NumberOfSnippetChannels", "b", 170),
+    ("NumberOfForwardLookArrays", "b", 171),
+    ("NumberOfEchoStrengthChannels", "H", 172),
+    ("NumberOfInterferometryChannels", "b", 174),
+    ("Reserved1", "b", 175),
+    ("Reserved2", "b", 176),
+    ("ReferencePointHeight", "b", 178),
+    ("ProjectionType", "12z", 182),  // Not currently used set to zero
+    ("SpheriodType", "10z", 194),   // Not currently used set to zero
+    ("NavigationLatency", "H", 204), //was 2H
+    ("OriginY", "f", 208),
+    ("OriginX", "f", 212),
+    ("NavOffsetY", "f", 216),
+    ("NavOffsetX", "f", 220),
+    ("NavOffsetZ", "f", 224),
+    ("NavOffsetYaw", "f", 228),
+    ("MRUOffsetY", "f", 232),
+    ("MRUOffsetX", "f", 236),
+    ("MRUOffsetZ", "f", 240),
+    ("MRUOffsetYaw", "f", 244),
+    ("MRUOffsetPitch", "f", 248),
+    ("MRUOffsetRoll", "f", 252),
+];
+
+pub const XTF_CHAN_INFO: &FieldTable = &[
+    ("TypeOfChannel", "b", 0),
+    ("SubChannelNumber", "b", 1),
+    ("CorrectionFlags", "H", 2),
+    ("UniPolar", "H", 4),
+    ("BytesPerSample", "H", 6),
+    ("Reserved", "H", 8), // was i
+    ("ChannelName", "16s", 12),
+    ("VoltScale", "f", 28),
+    ("Frequency", "f", 32),
+    ("HorizBeamAngle", "f", 36),
+    ("TiltAngle", "f", 40),
+    ("BeamWidth", "f", 44),
+    ("OffsetX", "f", 48),
+    ("OffsetY", "f", 52),
+    ("OffsetZ", "f", 56),
+    ("OffsetYaw", "f", 60),
+    ("OffsetPitch", "f", 64),
+    ("OffsetRoll", "f", 68),
+    ("BeamsPerArray", "H", 72),
+    ("SampleFormat", "b", 74),
+    ("ReservedArea2", "53z", 75), // Not currently used set to zero
+];
+
+pub const XTF_PING_HEADER: &FieldTable = &[
+    ("MagicNumber", "H", 0),
+    ("HeaderType", "b", 2),
+    ("SubChannelNumber", "b", 3),
+    ("NumChansToFollow", "H", 4), // determines the number of XTFPINGCHANHEADERs to follow
+    ("Reserved1", "2H", 6), // should be H
+    ("NumBytesThisRecord", "2H", 10), // was H, records with lots of samples overflow a u16
+    ("Year", "H", 14),
+    ("Month", "b", 16),
+    ("Day", "b", 17),
+    ("Hour", "b", 18),
+    ("Minute", "b", 19),
+    ("Second", "b", 20),
+    ("HSeconds", "b", 21),
+    ("JulianDay", "H", 22),
+    ("EventNumber", "H", 24),
+    ("PingNumber", "2H", 28), // was H
+    ("SoundVelocity", "f", 32),
+    ("OceanTide", "f", 36),
+    ("Reserved2", "2H", 40),
+    ("ConductivityFreq", "f", 44),
+    ("TemperatureFreq", "f", 48),
+    ("PressureFreq", "f", 52),
+    ("PressureTemp", "f", 56),
+    ("Conductivity", "f", 60),
+    ("WaterTemperature", "f", 64),
+    ("Pressure", "f", 68),
+    ("ComputedSoundVelocity", "f", 72),
+    ("MagX", "f", 76),
+    ("MagY", "f", 80),
+    ("MagZ", "f", 84),
+    ("AuxVal1", "f", 88),
+    ("AuxVal2", "f", 92),
+    ("AuxVal3", "f", 96),
+    ("Reserved3", "f", 100),
+    ("Reserved4", "f", 104),
+    ("Reserved5", "f", 108),
+    ("SpeedLog", "f", 112),
+    ("Turbidity", "f", 116),
+    ("ShipSpeed", "f", 120),
+    ("ShipGyro", "f", 124),
+    ("ShipYcoordinate", "d", 128),
+    ("ShipXcoordinate", "d", 136),
+    ("ShipAltitude", "H", 144),
+    ("ShipDepth", "H", 146),
+    ("FixTimeHour", "b", 148),
+    ("FixTimeMinute", "b", 149),
+    ("FixTimeSecond", "b", 150),
+    ("FixTimeHsecond", "b", 151),
+    ("SensorSpeed", "f", 152),
+    ("KP", "f", 156),
+    ("SensorYcoordinate", "d", 160),
+    ("SensorXcoordinate", "d", 168),
+    ("SonarStatus", "H", 176),
+    ("RangeToFish", "H", 178),
+    ("BearingToFish", "H", 180),
+    ("CableOut", "H", 182),
+    ("Layback", "f", 184),
+    ("CableTension", "f", 188),
+    ("SensorDepth", "f", 192),
+    ("SensorPrimaryAltitude", "f", 196),
+    ("SensorAuxAltitude", "f", 200),
+    ("SensorPitch", "f", 204),
+    ("SensorRoll", "f", 208),
+    ("SensorHeading", "f", 212),
+    ("Heave", "f", 216),
+    ("Yaw", "f", 220),
+    ("AttitudeTimeTag", "2H", 224),
+    ("DOT", "f", 228),
+    ("NavFixMilliseconds", "2H", 232),
+    ("ComputerClockHour", "b", 236),
+    ("ComputerClockMinute", "b", 237),
+    ("ComputerClockSecond", "b", 238),
+    ("ComputerClockHsec", "b", 239),
+    ("FishPositionDeltaX", "H", 240), // was h
+    ("FishPositionDeltaY", "H", 242), // was h
+    ("FishPositionErrorCode", "b", 244),
+    ("OptionalOffset", "2H", 245),
+    ("CableOutHundredths", "b", 249),
+    ("ReservedSpace2", "6z", 250), // Not current used set to zero
+];
+
+pub const XTF_PING_CHAN_HEADER: &FieldTable = &[
+    ("ChannelNumber", "H", 0),
+    ("DownsampleMethod", "H", 2),
+    ("SlantRange", "f", 4),
+    ("GroundRange", "f", 8),
+    ("TimeDelay", "f", 12),
+    ("TimeDuration", "f", 16),
+    ("SecondsPerPing", "f", 20),
+    ("ProcessingFlags", "H", 24),
+    ("Frequency", "H", 26),
+    ("InitialGainCode", "H", 28),
+    ("GainCode", "H", 30),
+    ("BandWidth", "H", 32),
+    ("ContactNumber", "2H", 34),
+    ("ContactClassification", "H", 38),
+    ("ContactSubNumber", "b", 40),
+    ("ContactType", "b", 41),
+    ("NumSamples", "2H", 42),  // Number of samples in the data, was H
+    ("MillivoltScale", "H", 46),
+    ("ContactTimeOffTrack", "f", 48),
+    ("ContactCloseNumber", "b", 52),
+    ("Reserved2", "b", 53),
+    ("FixedVSOP", "f", 54),
+    ("Weight", "H", 58), //was h
+    ("ReservedSpace", "4z", 60), // Not currently used set to zero
+];
+
+
+// XTFPOSRAWNAVIGATION, position as it came off the navigation system with its own time
+pub const XTF_POS_RAW_NAVIGATION: &FieldTable = &[
+    ("MagicNumber", "H", 0),
+    ("HeaderType", "b", 2),
+    ("Reserved", "7z", 3),
+    ("NumBytesThisRecord", "2H", 10),
+    ("Year", "H", 14),
+    ("Month", "b", 16),
+    ("Day", "b", 17),
+    ("Hour", "b", 18),
+    ("Minute", "b", 19),
+    ("Second", "b", 20),
+    ("MicroSeconds", "H", 21), // 0 - 9999, tenths of a millisecond despite the name
+    ("RawYcoordinate", "d", 23),
+    ("RawXcoordinate", "d", 31),
+    ("RawAltitude", "d", 39),
+    ("Pitch", "f", 47),
+    ("Roll", "f", 51),
+    ("Heave", "f", 55),
+    ("Heading", "f", 59),
+    ("Reserved2", "b", 63),
+];
+
+#[derive(Debug, Clone, Serialize)] // so can print with {:?} and allow cloning values
+#[serde(untagged)] // plain numbers and strings in json
+pub enum HeaderValue {
+    Byte(u8),
+    Float(f32),
+    String(String),
+    Short(u16),
+    Int(i32),
+    UInt(u32), // 2H fields, unsigned on disk and past i32 for big records and ping numbers
+    Double(f64), // positions, f32 only gets lat/lon to about a metre
+}
+
+impl fmt::Display for HeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderValue::Byte(val) => write!(f, "Byte: {}", val),
+            HeaderValue::Float(val) => write!(f, "Float: {}", val),
+            HeaderValue::String(val) => write!(f, "String: {}", val),
+            HeaderValue::Short(val) => write!(f, "Short: {}", val),
+            HeaderValue::Int(val) => write!(f, "Int: {}", val),
+            HeaderValue::UInt(val) => write!(f, "UInt: {}", val),
+            HeaderValue::Double(val) => write!(f, "Double: {}", val),
+        }
+    }
+}
+
+impl HeaderValue {
+    // Numeric view of any non string value
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            HeaderValue::Byte(val) => Some(*val as f64),
+            HeaderValue::Float(val) => Some(*val as f64),
+            HeaderValue::String(_) => None,
+            HeaderValue::Short(val) => Some(*val as f64),
+            HeaderValue::Int(val) => Some(*val as f64),
+            HeaderValue::UInt(val) => Some(*val as f64),
+            HeaderValue::Double(val) => Some(*val),
+        }
+    }
+}
+
+
+// Saves repeating the if let Some(Some(HeaderValue::Short(val))) dance for every lookup
+pub fn get_number(map: &HeaderMap, key: &str) -> Option<f64> {
+    match map.get(key) {
+        Some(Some(value)) => value.as_f64(),
+        _ => None,
+    }
+}
+
+
+pub fn get_string(map: &HeaderMap, key: &str) -> Option<String> {
+    match map.get(key) {
+        Some(Some(HeaderValue::String(val))) => Some(val.clone()),
+        _ => None,
+    }
+}
+
+
+pub fn read_headers(
+    file_header: &FieldTable,
+    data: &[u8],
+    base_offset: usize,
+) -> (HeaderMap, usize) {
+
+    let mut final_byte = base_offset;
+    let mut result_map: HeaderMap = HashMap::new();
+
+    for (name, fmt, offset) in file_header {
+        let mut in_loop_fmt = fmt.to_string();
+        let offset_plus_base = base_offset + offset;
+
+        let mut number = 0;
+
+        if contains_number_and_z_or_s(fmt) {
+            let (parsed_number, char_type) = match parse_size_and_type(fmt) {
+                Ok((number, char_type)) => {
+                    (number, char_type)
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return (result_map, final_byte);
+                }
+            };
+
+            number = parsed_number;
+            in_loop_fmt = char_type.to_string();
+        }
+
+        let result = read_value(&in_loop_fmt, data, offset_plus_base, number);
+
+        result_map.insert(name.to_string(), result);
+
+        final_byte = offset_plus_base + format_size(&in_loop_fmt, number);
+    }
+
+    (result_map, final_byte)
+}
+
+
+// Decodes one field, fmt has already had any size prefix split off into number
+fn read_value(fmt: &str, data: &[u8], offset: usize, number: usize) -> Option<HeaderValue> {
+    match fmt {
+        "b" => {
+            match read_and_decode_byte_as_number_u8(data, offset) {
+                Ok(byte_value) => Some(HeaderValue::Byte(byte_value)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    None
+                }
+            }
+        },
+
+        "f" => {
+            match read_float_from_binary_at_offset(data, offset) {
+                Ok(float_value) => Some(HeaderValue::Float(float_value)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    None
+                }
+            }
+        },
+
+        "s" => {
+            match read_and_decode_bytes_as_string(data, offset, number) {
+                Ok(string_value) => Some(HeaderValue::String(string_value)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    None
+                }
+            }
+        },
+
+        "H" => {
+            match read_unsigned_short(data, offset) {
+                Ok(short_value) => Some(HeaderValue::Short(short_value)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    None
+                }
+            }
+        },
+
+        "2H" => {
+            match read_unsigned_long(data, offset) {
+                Ok(long_value) => Some(HeaderValue::UInt(long_value)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    None
+                }
+            }
+        }
+
+        "d" => {
+            match read_double(data, offset) {
+                Ok(double_value) => Some(HeaderValue::Double(double_value)), // was Float, lost precision
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    None
+                }
+            }
+        },
+
+        "z" => {
+            let x = 0;
+            Some(HeaderValue::Int(x))
+        },
+
+        _ => {
+            println!("Unknown value type: {}", fmt);
+            None
+        },
+    }
+}
+
+
+// Reads a single named field without building the whole map, for hot loops like indexing
+pub fn read_field(table: &FieldTable, data: &[u8], base_offset: usize, name: &str) -> Option<HeaderValue> {
+    let (_, fmt, offset) = table.iter().find(|(field, _, _)| *field == name)?;
+
+    if contains_number_and_z_or_s(fmt) {
+        let (number, char_type) = parse_size_and_type(fmt).ok()?;
+        return read_value(&char_type.to_string(), data, base_offset + offset, number);
+    }
+
+    read_value(fmt, data, base_offset + offset, 0)
+}
+
+
+// Overwrites a single numeric field in place, the inverse of read_field
+pub fn write_field(table: &FieldTable, data: &mut [u8], base_offset: usize, name: &str, value: f64) -> Result<(), Box<dyn Error>> {
+    let (_, fmt, offset) = table
+        .iter()
+        .find(|(field, _, _)| *field == name)
+        .ok_or_else(|| format!("No field called {}", name))?;
+
+    let offset = base_offset + offset;
+    let bytes: Vec<u8> = match *fmt {
+        "b" => vec![value as u8],
+        "H" => (value as u16).to_le_bytes().to_vec(),
+        "2H" => (value as u32).to_le_bytes().to_vec(),
+        "f" => (value as f32).to_le_bytes().to_vec(),
+        "d" => value.to_le_bytes().to_vec(),
+        _ => return Err(format!("Can't write {} field {} as a number", fmt, name).into()),
+    };
+
+    if offset + bytes.len() > data.len() {
+        return Err(format!("Insufficient data to write {}", name).into());
+    }
+
+    data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    Ok(())
+}
+
+
+// Number of bytes a single (already split) format code takes up
+fn format_size(fmt: &str, number: usize) -> usize {
+    match fmt {
+        "b" => 1,
+        "f" => 4,
+        "s" => number,
+        "H" => 2,
+        "2H" => 4,
+        "d" => 8,
+        "z" => number,
+        _ => {
+            println!("Unknown value type: {}", fmt);
+            0  // Default size in case of unknown type
+        }
+    }
+}
+
+
+fn contains_number_and_z_or_s(s: &str) -> bool {
+    // compiled once, this gets called for every field of every ping
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^\d{1,2}[zs]$").unwrap()).is_match(s)
+}
+
+
+pub fn read_float_from_binary_at_offset(data: &[u8], offset: usize) -> Result<f32, Box<dyn Error>> {
+    if offset + 4 > data.len() {
+        return Err("Insufficient data to read f32".into());
+    }
+
+    let bytes: [u8; 4] = data[offset..offset + 4].try_into()?; // Try converting slice to array
+    Ok(f32::from_le_bytes(bytes))
+}
+
+
+pub fn read_and_decode_byte_as_number_u8(data: &[u8], offset: usize) -> Result<u8, Box<dyn Error>> {
+    data.get(offset)
+        .copied()
+        .ok_or_else(|| "Offset exceeds data length".into())
+}
+
+
+pub fn read_and_decode_bytes_as_string(data: &[u8], offset: usize, num_bytes: usize) -> Result<String, Box<dyn Error>> {
+    if offset + num_bytes > data.len() {
+        return Err("Offset and number of bytes exceed data length".into());
+    }
+
+    let mut buffer = data[offset..offset + num_bytes].to_vec();
+    buffer.retain(|&b| b != 0x00); // Remove null padding
+
+    Ok(String::from_utf8(buffer)?)
+}
+
+
+pub fn read_unsigned_short(data: &[u8], offset: usize) -> Result<u16, Box<dyn Error>> {
+    if offset + 2 > data.len() {
+        return Err("Insufficient data to read u16".into());
+    }
+
+    let bytes: [u8; 2] = data[offset..offset + 2].try_into()?; // Try converting slice to array
+    Ok(u16::from_le_bytes(bytes))
+}
+
+
+pub fn read_unsigned_long(data: &[u8], offset: usize) -> Result<u32, Box<dyn Error>> {
+    // Used for reading 2H which is an unsigned short with twice the required bytes
+    if offset + 4 > data.len() {
+        return Err("Insufficient data to read u32".into());
+    }
+
+    let bytes: [u8; 4] = data[offset..offset + 4].try_into()?; // Try converting slice to array
+    Ok(u32::from_le_bytes(bytes))
+}
+
+
+pub fn read_double(data: &[u8], offset: usize) -> Result<f64, Box<dyn Error>> {
+    if offset + 8 > data.len() {
+        return Err("Insufficient data to read f64".into());
+    }
+
+    let bytes: [u8; 8] = data[offset..offset + 8].try_into()?; // Try converting slice to array
+    Ok(f64::from_le_bytes(bytes))
+}
+
+
+fn parse_size_and_type(input: &str) -> Result<(usize, char), Box<dyn Error>> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"^(\d+)([a-zA-Z])$").unwrap());
+
+    re.captures(input)
+        .ok_or_else(|| "Invalid format".into())
+        .and_then(|caps| {
+            let number = caps.get(1).unwrap().as_str().parse::<usize>()?;
+            let char_type = caps.get(2).unwrap().as_str().chars().next().unwrap();
+            Ok((number, char_type))
+        })
+}
+
+
+pub fn find_byte_offset_for_value(data: &[u8], base_offset: usize, target_value: u16) -> Option<usize> {
+    let mut offset = base_offset;
+
+    while offset + 1 < data.len() {
+        // Try to read the next two bytes as an unsigned short
+        match read_unsigned_short(data, offset) {
+            Ok(value) => {
+                if value == target_value {
+                    return Some(offset); // Return the offset if the value matches
+                }
+            }
+            Err(e) => {
+                eprintln!("Error reading unsigned short at offset {}: {}", offset, e);
+                break;
+            }
+        }
+        offset += 1; // Move to the next byte
+    }
+
+    None // Return None if the value is not found
+}
@@ -0,0 +1,59 @@
+use crate::headers::{get_number, read_field, HeaderMap, XTF_PING_HEADER};
+
+// Headings are degrees clockwise from north in [0, 360)
+
+
+// Sensor heading when the logger filled it in, otherwise the ship's gyro
+pub fn ping_heading(header: &HeaderMap) -> Option<f64> {
+    choose_heading(get_number(header, "SensorHeading"), get_number(header, "ShipGyro"))
+}
+
+
+// Same as ping_heading but straight from the record bytes
+pub fn record_heading(data: &[u8], offset: usize) -> Option<f64> {
+    let field = |name: &str| read_field(XTF_PING_HEADER, data, offset, name).and_then(|v| v.as_f64());
+    choose_heading(field("SensorHeading"), field("ShipGyro"))
+}
+
+
+// A zero SensorHeading nearly always means "not recorded" rather than due north
+fn choose_heading(sensor: Option<f64>, gyro: Option<f64>) -> Option<f64> {
+    match (sensor, gyro) {
+        (Some(sensor), _) if sensor != 0.0 && sensor.is_finite() => Some(normalise_heading(sensor)),
+        (_, Some(gyro)) if gyro.is_finite() => Some(normalise_heading(gyro)),
+        _ => None,
+    }
+}
+
+
+pub fn normalise_heading(heading: f64) -> f64 {
+    heading.rem_euclid(360.0)
+}
+
+
+// Signed smallest turn from `from` to `to`, in (-180, 180]
+pub fn heading_difference(from: f64, to: f64) -> f64 {
+    let difference = (to - from).rem_euclid(360.0);
+    if difference > 180.0 {
+        difference - 360.0
+    } else {
+        difference
+    }
+}
+
+
+// Circular mean so 359 and 1 average to 0 rather than 180
+pub fn mean_heading(headings: &[f64]) -> Option<f64> {
+    if headings.is_empty() {
+        return None;
+    }
+
+    let (sin, cos) = headings
+        .iter()
+        .fold((0.0, 0.0), |(sin, cos), h| (sin + h.to_radians().sin(), cos + h.to_radians().cos()));
+
+    if sin == 0.0 && cos == 0.0 {
+        return None;
+    }
+    Some(normalise_heading(sin.atan2(cos).to_degrees()))
+}
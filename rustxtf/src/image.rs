@@ -0,0 +1,207 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Greyscale image writers. PNG goes through the png crate, PGM and TIFF are simple enough to
+// write by hand. The description ends up in a PNG text chunk, a PGM comment or the TIFF
+// ImageDescription tag
+
+const TIFF_ASCII: u16 = 2;
+const TIFF_SHORT: u16 = 3;
+const TIFF_LONG: u16 = 4;
+const TIFF_RATIONAL: u16 = 5;
+const TIFF_DOUBLE: u16 = 12;
+
+
+pub fn write_png<P: AsRef<Path>>(path: P, width: usize, height: usize, pixels: &[u8], description: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    if let Some(description) = description {
+        encoder.add_itxt_chunk("Description".to_string(), description.to_string())?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+
+// Binary 16 bit PGM (P5), samples are big endian
+pub fn write_pgm16<P: AsRef<Path>>(path: P, width: usize, height: usize, pixels: &[u16], description: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "P5")?;
+    for line in description.into_iter().flat_map(|description| description.lines()) {
+        writeln!(out, "# {}", line)?;
+    }
+    write!(out, "{} {}\n65535\n", width, height)?;
+    for pixel in pixels {
+        out.write_all(&pixel.to_be_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+
+// One IFD entry, values already encoded little endian
+pub struct TiffTag {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u32,
+    pub data: Vec<u8>,
+}
+
+impl TiffTag {
+    pub fn short(tag: u16, value: u16) -> TiffTag {
+        TiffTag { tag, field_type: TIFF_SHORT, count: 1, data: value.to_le_bytes().to_vec() }
+    }
+
+    pub fn long(tag: u16, value: u32) -> TiffTag {
+        TiffTag { tag, field_type: TIFF_LONG, count: 1, data: value.to_le_bytes().to_vec() }
+    }
+
+    pub fn rational(tag: u16, numerator: u32, denominator: u32) -> TiffTag {
+        let data = [numerator.to_le_bytes(), denominator.to_le_bytes()].concat();
+        TiffTag { tag, field_type: TIFF_RATIONAL, count: 1, data }
+    }
+
+    pub fn shorts(tag: u16, values: &[u16]) -> TiffTag {
+        let data = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        TiffTag { tag, field_type: TIFF_SHORT, count: values.len() as u32, data }
+    }
+
+    pub fn doubles(tag: u16, values: &[f64]) -> TiffTag {
+        let data = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        TiffTag { tag, field_type: TIFF_DOUBLE, count: values.len() as u32, data }
+    }
+
+    pub fn ascii(tag: u16, value: &str) -> TiffTag {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        TiffTag { tag, field_type: TIFF_ASCII, count: data.len() as u32, data }
+    }
+}
+
+
+// Baseline little endian TIFF with the whole image in one strip. pixel_bytes is already
+// encoded, the caller says how wide a sample is and what SampleFormat (1 uint, 3 float) it is
+pub fn write_tiff<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    bits_per_sample: u16,
+    sample_format: u16,
+    pixel_bytes: &[u8],
+    extra_tags: Vec<TiffTag>,
+) -> Result<(), Box<dyn Error>> {
+    let image_offset = 8u32;
+    let mut tags = vec![
+        TiffTag::long(256, width as u32),              // ImageWidth
+        TiffTag::long(257, height as u32),             // ImageLength
+        TiffTag::short(258, bits_per_sample),          // BitsPerSample
+        TiffTag::short(259, 1),                        // Compression none
+        TiffTag::short(262, 1),                        // PhotometricInterpretation BlackIsZero
+        TiffTag::long(273, image_offset),              // StripOffsets
+        TiffTag::short(277, 1),                        // SamplesPerPixel
+        TiffTag::long(278, height as u32),             // RowsPerStrip
+        TiffTag::long(279, pixel_bytes.len() as u32),  // StripByteCounts
+        TiffTag::rational(282, 1, 1),                  // XResolution, required but means nothing here
+        TiffTag::rational(283, 1, 1),                  // YResolution
+        TiffTag::short(284, 1),                        // PlanarConfiguration
+        TiffTag::short(296, 1),                        // ResolutionUnit none
+        TiffTag::short(339, sample_format),            // SampleFormat
+    ];
+    tags.extend(extra_tags);
+    tags.sort_by_key(|tag| tag.tag);
+
+    // values that don't fit in the 4 byte entry go after the image, then the IFD itself
+    let mut overflow: Vec<u8> = Vec::new();
+    let overflow_start = image_offset as usize + pixel_bytes.len();
+    let mut entries: Vec<u8> = Vec::new();
+
+    for tag in &tags {
+        entries.extend_from_slice(&tag.tag.to_le_bytes());
+        entries.extend_from_slice(&tag.field_type.to_le_bytes());
+        entries.extend_from_slice(&tag.count.to_le_bytes());
+
+        if tag.data.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..tag.data.len()].copy_from_slice(&tag.data);
+            entries.extend_from_slice(&inline);
+        } else {
+            let offset = (overflow_start + overflow.len()) as u32;
+            entries.extend_from_slice(&offset.to_le_bytes());
+            overflow.extend_from_slice(&tag.data);
+            if overflow.len() % 2 == 1 {
+                overflow.push(0); // keep word alignment
+            }
+        }
+    }
+
+    let mut ifd_offset = overflow_start + overflow.len();
+    let padding = ifd_offset % 2;
+    ifd_offset += padding;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(b"II")?;
+    out.write_all(&42u16.to_le_bytes())?;
+    out.write_all(&(ifd_offset as u32).to_le_bytes())?;
+    out.write_all(pixel_bytes)?;
+    out.write_all(&overflow)?;
+    out.write_all(&vec![0u8; padding])?;
+    out.write_all(&(tags.len() as u16).to_le_bytes())?;
+    out.write_all(&entries)?;
+    out.write_all(&0u32.to_le_bytes())?; // no more IFDs
+    out.flush()?;
+    Ok(())
+}
+
+
+pub fn write_tiff16<P: AsRef<Path>>(path: P, width: usize, height: usize, pixels: &[u16], description: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let bytes: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+    let tags = description.map(|description| TiffTag::ascii(270, description)).into_iter().collect(); // ImageDescription
+    write_tiff(path, width, height, 16, 1, &bytes, tags)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_data::{tiff_tags, TempPath};
+
+    #[test]
+    fn writes_a_baseline_tiff() {
+        let path = TempPath::new("image.tif");
+        let pixels = [0u16, 1, 256, 65535, 7, 8];
+        write_tiff16(&path.0, 3, 2, &pixels, Some("a waterfall")).unwrap();
+
+        let bytes = fs::read(&path.0).unwrap();
+        let tags = tiff_tags(&bytes);
+        let long = |tag: u16| u32::from_le_bytes(tags[&tag].2[..4].try_into().unwrap());
+        let short = |tag: u16| u16::from_le_bytes(tags[&tag].2[..2].try_into().unwrap());
+
+        assert_eq!((long(256), long(257), short(258), short(339)), (3, 2, 16, 1));
+        for tag in [282, 283] {
+            assert_eq!(tags[&tag].0, TIFF_RATIONAL);
+            assert_eq!(tags[&tag].2, [1, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        assert_eq!(short(296), 1);
+        assert_eq!(tags[&270].2, b"a waterfall\0");
+
+        let strip = long(273) as usize;
+        assert_eq!(long(279), 12);
+        let read: Vec<u16> = bytes[strip..strip + 12].chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(read, pixels);
+    }
+
+    #[test]
+    fn writes_a_16_bit_pgm() {
+        let path = TempPath::new("image.pgm");
+        write_pgm16(&path.0, 2, 1, &[1, 65534], Some("two\nlines")).unwrap();
+        assert_eq!(fs::read(&path.0).unwrap(), b"P5\n# two\n# lines\n2 1\n65535\n\x00\x01\xff\xfe");
+    }
+}
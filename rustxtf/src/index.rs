@@ -0,0 +1,404 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::headers::{read_field, XTF_HEADER_SONAR, XTF_PING_HEADER};
+use crate::record::{next_record_offset, read_record_prefix};
+use crate::time::record_time;
+
+// Sidecar layout (all little endian):
+//   8 byte magic/version, u64 xtf length, u64 xtf mtime secs, u32 xtf mtime nanos, u64 entry count
+//   then per record: u64 offset, u8 header type, u32 ping number, f64 time (NaN when unknown)
+const INDEX_MAGIC: &[u8; 8] = b"XTFIDX01";
+const INDEX_EXTENSION: &str = "idx";
+const INDEX_HEADER_LENGTH: u64 = 36;
+const INDEX_ENTRY_LENGTH: u64 = 21;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexEntry {
+    pub offset: u64,
+    pub header_type: u8,
+    pub ping_number: u32, // only meaningful for sonar records
+    pub time: f64,        // seconds since epoch, NaN if the record has no usable time
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RecordIndex {
+    pub entries: Vec<IndexEntry>,
+    // Worked out once from the entries, which aren't changed after the index is built or loaded
+    pings: Vec<usize>,    // positions of the sonar records in entries
+    times_sorted: bool,   // no ping time is missing or earlier than the one before it
+    numbers_sorted: bool, // every ping number is bigger than the one before it
+}
+
+// What the index was built from, so a stale sidecar can be spotted
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SourceStamp {
+    length: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+}
+
+
+impl RecordIndex {
+    pub fn new(entries: Vec<IndexEntry>) -> RecordIndex {
+        let pings: Vec<usize> = (0..entries.len()).filter(|&i| entries[i].header_type == XTF_HEADER_SONAR).collect();
+        let ping_pairs = || pings.windows(2).map(|pair| (&entries[pair[0]], &entries[pair[1]]));
+        let times_sorted = pings.iter().all(|&i| !entries[i].time.is_nan()) && ping_pairs().all(|(a, b)| a.time <= b.time);
+        let numbers_sorted = ping_pairs().all(|(a, b)| a.ping_number < b.ping_number);
+
+        RecordIndex { entries, pings, times_sorted, numbers_sorted }
+    }
+
+    // Walks the records from first_record using their lengths, only reading the fields we index
+    pub fn build(data: &[u8], first_record: usize) -> RecordIndex {
+        let mut entries = Vec::new();
+        let mut offset = first_record;
+
+        while let Some(record_offset) = next_record_offset(data, offset) {
+            let (header_type, length) = match read_record_prefix(data, record_offset) {
+                Ok(prefix) => prefix,
+                Err(_) => break,
+            };
+
+            let (ping_number, time) = if header_type == XTF_HEADER_SONAR {
+                let ping_number = read_field(XTF_PING_HEADER, data, record_offset, "PingNumber")
+                    .and_then(|v| v.as_f64())
+                    .unwrap_or(0.0) as u32;
+                (ping_number, record_time(data, record_offset).unwrap_or(f64::NAN))
+            } else {
+                (0, f64::NAN)
+            };
+
+            entries.push(IndexEntry {
+                offset: record_offset as u64,
+                header_type,
+                ping_number,
+                time,
+            });
+
+            offset = record_offset + length;
+        }
+
+        RecordIndex::new(entries)
+    }
+
+    // Sonar records only, in file order
+    pub fn pings(&self) -> impl Iterator<Item = &IndexEntry> {
+        self.pings.iter().map(|&i| &self.entries[i])
+    }
+
+    // n'th sonar record
+    fn ping(&self, n: usize) -> &IndexEntry {
+        &self.entries[self.pings[n]]
+    }
+
+    // First ping with this number. Binary search when the numbers go up through the file,
+    // which they normally do, otherwise a scan because a restarted or wrapped counter can
+    // put the number anywhere
+    pub fn ping_by_number(&self, ping_number: u32) -> Option<&IndexEntry> {
+        if self.numbers_sorted {
+            let n = self.pings.partition_point(|&i| self.entries[i].ping_number < ping_number);
+            return self.pings.get(n).map(|&i| &self.entries[i]).filter(|entry| entry.ping_number == ping_number);
+        }
+        self.pings().find(|entry| entry.ping_number == ping_number)
+    }
+
+    // Ping whose time is closest to time, ties go to the earlier ping. Pings without a time
+    // are never picked. Binary search when the times are in order, otherwise a scan
+    pub fn ping_at_time(&self, time: f64) -> Option<&IndexEntry> {
+        if time.is_nan() {
+            return None;
+        }
+
+        if self.times_sorted {
+            let after = self.first_ping_from(time);
+            let nearest = match (after.checked_sub(1), after < self.pings.len()) {
+                (Some(before), true) if time - self.ping(before).time <= self.ping(after).time - time => before,
+                (_, true) => after,
+                (Some(before), false) => before,
+                (None, false) => return None,
+            };
+            // the earliest of any pings sharing the nearest time
+            return Some(self.ping(self.first_ping_from(self.ping(nearest).time)));
+        }
+
+        self.pings()
+            .filter(|entry| !entry.time.is_nan())
+            .fold(None, |best: Option<&IndexEntry>, entry| match best {
+                Some(best) if (best.time - time).abs() <= (entry.time - time).abs() => Some(best),
+                _ => Some(entry),
+            })
+    }
+
+    // Pings with start <= time <= end, in file order, none if start is after end
+    pub fn pings_between(&self, start: f64, end: f64) -> Vec<&IndexEntry> {
+        if self.times_sorted {
+            let first = self.first_ping_from(start);
+            let last = self.pings.partition_point(|&i| self.entries[i].time <= end);
+            return (first..last.max(first)).map(|n| self.ping(n)).collect();
+        }
+
+        self.pings()
+            .filter(|entry| entry.time >= start && entry.time <= end)
+            .collect()
+    }
+
+    // Number of pings before time, only meaningful when the times are sorted
+    fn first_ping_from(&self, time: f64) -> usize {
+        self.pings.partition_point(|&i| self.entries[i].time < time)
+    }
+
+    // Writes the sidecar for xtf_path next to it
+    pub fn save(&self, xtf_path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let stamp = source_stamp(xtf_path)?;
+        let path = index_path(xtf_path);
+        let mut writer = BufWriter::new(File::create(&path)?);
+
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u64::<LittleEndian>(stamp.length)?;
+        writer.write_u64::<LittleEndian>(stamp.mtime_secs)?;
+        writer.write_u32::<LittleEndian>(stamp.mtime_nanos)?;
+        writer.write_u64::<LittleEndian>(self.entries.len() as u64)?;
+
+        for entry in &self.entries {
+            writer.write_u64::<LittleEndian>(entry.offset)?;
+            writer.write_u8(entry.header_type)?;
+            writer.write_u32::<LittleEndian>(entry.ping_number)?;
+            writer.write_f64::<LittleEndian>(entry.time)?;
+        }
+
+        writer.flush()?;
+        Ok(path)
+    }
+
+    // Loads the sidecar for xtf_path, Ok(None) if there isn't one or it no longer matches the file.
+    // A sidecar whose entry count doesn't fit its length, or that points past the end of the
+    // file, is treated as not matching rather than trusted
+    pub fn load(xtf_path: &Path) -> Result<Option<RecordIndex>, Box<dyn Error>> {
+        let path = index_path(xtf_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let sidecar_length = fs::metadata(&path)?.len();
+        if sidecar_length < INDEX_HEADER_LENGTH {
+            return Ok(None);
+        }
+
+        let mut reader = BufReader::new(File::open(&path)?);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Ok(None);
+        }
+
+        let stamp = SourceStamp {
+            length: reader.read_u64::<LittleEndian>()?,
+            mtime_secs: reader.read_u64::<LittleEndian>()?,
+            mtime_nanos: reader.read_u32::<LittleEndian>()?,
+        };
+        if stamp != source_stamp(xtf_path)? {
+            return Ok(None);
+        }
+
+        let count = reader.read_u64::<LittleEndian>()?;
+        if count.checked_mul(INDEX_ENTRY_LENGTH) != Some(sidecar_length - INDEX_HEADER_LENGTH) {
+            return Ok(None);
+        }
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let entry = IndexEntry {
+                offset: reader.read_u64::<LittleEndian>()?,
+                header_type: reader.read_u8()?,
+                ping_number: reader.read_u32::<LittleEndian>()?,
+                time: reader.read_f64::<LittleEndian>()?,
+            };
+            if entry.offset >= stamp.length {
+                return Ok(None);
+            }
+            entries.push(entry);
+        }
+
+        Ok(Some(RecordIndex::new(entries)))
+    }
+}
+
+
+// foo.xtf -> foo.xtf.idx
+pub fn index_path(xtf_path: &Path) -> PathBuf {
+    let mut name = xtf_path.as_os_str().to_owned();
+    name.push(".");
+    name.push(INDEX_EXTENSION);
+    PathBuf::from(name)
+}
+
+
+fn source_stamp(xtf_path: &Path) -> Result<SourceStamp, Box<dyn Error>> {
+    let metadata = fs::metadata(xtf_path)?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+
+    Ok(SourceStamp {
+        length: metadata.len(),
+        mtime_secs: mtime.as_secs(),
+        mtime_nanos: mtime.subsec_nanos(),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::headers::get_number;
+    use crate::test_data::{other_record, xtf_bytes, TempPath, TestPing};
+    use crate::xtf_file::XtfFile;
+
+    // An indexed file on disk, with the sidecar removed along with it
+    fn indexed_file(name: &str) -> (TempPath, TempPath, RecordIndex) {
+        let xtf = TempPath::new(name);
+        let sidecar = TempPath(index_path(&xtf.0));
+        let mut bytes = xtf_bytes(2, 16, &(0..5).map(TestPing::numbered).collect::<Vec<_>>());
+        bytes.extend(other_record(107, 64));
+        fs::write(&xtf.0, bytes).unwrap();
+
+        let file = XtfFile::open(&xtf.0).unwrap();
+        file.write_index().unwrap();
+        (xtf, sidecar, file.index)
+    }
+
+    #[test]
+    fn round_trips_through_the_sidecar() {
+        let (xtf, _sidecar, built) = indexed_file("index-round-trip.xtf");
+        let loaded = RecordIndex::load(&xtf.0).unwrap().expect("sidecar should match the file");
+
+        assert_eq!(loaded.entries.len(), 6);
+        for (loaded, built) in loaded.entries.iter().zip(&built.entries) {
+            assert_eq!((loaded.offset, loaded.header_type, loaded.ping_number), (built.offset, built.header_type, built.ping_number));
+            assert_eq!(loaded.time.to_bits(), built.time.to_bits());
+        }
+        assert!(loaded.entries[5].time.is_nan());
+    }
+
+    #[test]
+    fn ignores_a_sidecar_older_than_the_file() {
+        let (xtf, _sidecar, _) = indexed_file("index-stale.xtf");
+        let file = OpenOptions::new().write(true).open(&xtf.0).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+
+        assert!(RecordIndex::load(&xtf.0).unwrap().is_none());
+    }
+
+    #[test]
+    fn ignores_a_truncated_sidecar() {
+        let (xtf, sidecar, _) = indexed_file("index-truncated.xtf");
+        let length = fs::metadata(&sidecar.0).unwrap().len();
+        OpenOptions::new().write(true).open(&sidecar.0).unwrap().set_len(length - 5).unwrap();
+
+        assert!(RecordIndex::load(&xtf.0).unwrap().is_none());
+        // and XtfFile::open falls back to indexing the file itself
+        assert_eq!(XtfFile::open(&xtf.0).unwrap().index.entries.len(), 6);
+    }
+
+    #[test]
+    fn ignores_a_sidecar_with_a_bad_count_or_offset() {
+        let (xtf, sidecar, _) = indexed_file("index-corrupt.xtf");
+        let good = fs::read(&sidecar.0).unwrap();
+
+        let mut bytes = good.clone();
+        bytes[28..36].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&sidecar.0, &bytes).unwrap();
+        assert!(RecordIndex::load(&xtf.0).unwrap().is_none());
+
+        let mut bytes = good;
+        bytes[36..44].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&sidecar.0, &bytes).unwrap();
+        assert!(RecordIndex::load(&xtf.0).unwrap().is_none());
+    }
+
+    // Pings with the given (ping number, time), plus a non-sonar record up front
+    fn index_of(pings: &[(u32, f64)]) -> RecordIndex {
+        let mut entries = vec![IndexEntry { offset: 0, header_type: 107, ping_number: 0, time: f64::NAN }];
+        entries.extend(pings.iter().enumerate().map(|(i, &(ping_number, time))| IndexEntry {
+            offset: (i as u64 + 1) * 100,
+            header_type: XTF_HEADER_SONAR,
+            ping_number,
+            time,
+        }));
+        RecordIndex::new(entries)
+    }
+
+    fn numbers(entries: Vec<&IndexEntry>) -> Vec<u32> {
+        entries.iter().map(|entry| entry.ping_number).collect()
+    }
+
+    #[test]
+    fn looks_up_pings_in_sorted_and_unsorted_indexes() {
+        let sorted = index_of(&[(1, 10.0), (2, 11.0), (3, 11.0), (4, 13.0)]);
+        let unsorted = index_of(&[(4, 13.0), (2, 11.0), (3, 11.0), (1, 10.0)]);
+        assert!(sorted.times_sorted && sorted.numbers_sorted);
+        assert!(!unsorted.times_sorted && !unsorted.numbers_sorted);
+
+        for index in [&sorted, &unsorted] {
+            assert_eq!(index.ping_by_number(3).unwrap().time, 11.0);
+            assert!(index.ping_by_number(5).is_none());
+            assert!(index.ping_by_number(0).is_none(), "the non-sonar record has ping number 0");
+
+            // ties go to whichever ping comes first in the file
+            assert_eq!(index.ping_at_time(12.0).unwrap().ping_number, if index.times_sorted { 2 } else { 4 });
+            assert_eq!(index.ping_at_time(10.5).unwrap().ping_number, if index.times_sorted { 1 } else { 2 });
+            assert_eq!(index.ping_at_time(-1e9).unwrap().ping_number, 1);
+            assert_eq!(index.ping_at_time(1e9).unwrap().ping_number, 4);
+            assert!(index.ping_at_time(f64::NAN).is_none());
+
+            assert_eq!(numbers(index.pings_between(11.0, 13.0)).len(), 3);
+            assert!(index.pings_between(13.0, 11.0).is_empty());
+            assert!(index.pings_between(11.5, 12.5).is_empty());
+        }
+        assert_eq!(numbers(sorted.pings_between(10.5, 11.0)), [2, 3]);
+        assert_eq!(numbers(unsorted.pings_between(10.0, 11.0)), [2, 3, 1]);
+    }
+
+    #[test]
+    fn skips_pings_without_a_time() {
+        let index = index_of(&[(1, 10.0), (2, f64::NAN), (3, 12.0)]);
+        assert!(!index.times_sorted);
+
+        assert_eq!(index.ping_at_time(11.1).unwrap().ping_number, 3);
+        assert_eq!(numbers(index.pings_between(0.0, 100.0)), [1, 3]);
+        assert!(index.ping_by_number(2).unwrap().time.is_nan());
+    }
+
+    #[test]
+    fn finds_nothing_in_a_file_without_pings() {
+        let xtf = XtfFile::from_bytes(xtf_bytes(2, 16, &[])).unwrap();
+        assert!(xtf.index.entries.is_empty());
+        assert!(xtf.ping_by_number(0).unwrap().is_none());
+        assert!(xtf.ping_at_time(1_714_521_600.0).unwrap().is_none());
+        assert!(xtf.pings_between(0.0, f64::INFINITY).unwrap().is_empty());
+
+        let index = index_of(&[]);
+        assert!(index.ping_at_time(0.0).is_none());
+        assert!(index.pings_between(f64::NEG_INFINITY, f64::INFINITY).is_empty());
+    }
+
+    #[test]
+    fn reads_the_pings_it_finds() {
+        let xtf = XtfFile::from_bytes(xtf_bytes(2, 16, &(0..5).map(TestPing::numbered).collect::<Vec<_>>())).unwrap();
+        let time = TestPing::numbered(3).time;
+
+        let ping = xtf.ping_at_time(time + 0.4).unwrap().unwrap();
+        assert_eq!(get_number(&ping.header, "PingNumber"), Some(3.0));
+        let pings = xtf.pings_between(time - 1.0, time).unwrap();
+        assert_eq!(pings.len(), 2);
+        assert_eq!(get_number(&xtf.ping_by_number(4).unwrap().unwrap().header, "PingNumber"), Some(4.0));
+    }
+}
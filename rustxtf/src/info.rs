@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_derive::Serialize;
+
+use crate::coords::is_position;
+use crate::headers::{get_number, get_string, header_type_name};
+use crate::time::format_time;
+use crate::xtf_file::XtfFile;
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelSummary {
+    pub index: usize,
+    pub name: String,
+    pub type_of_channel: Option<u8>, // 0 subbottom, 1 port, 2 starboard, 3 bathymetry
+    pub frequency: Option<f64>,
+    pub bytes_per_sample: Option<u16>,
+    pub sample_format: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MinMax {
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BoundingBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordTypeCount {
+    pub header_type: u8,
+    pub name: &'static str,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileSummary {
+    pub file: String,
+    pub sonar_name: Option<String>,
+    pub recording_program: Option<String>,
+    pub recording_program_version: Option<String>,
+    pub system_type: Option<u8>,
+    pub nav_units: Option<u16>, // 0 metres, 3 lat/lon
+    pub channels: Vec<ChannelSummary>,
+    pub records: usize,
+    pub pings: usize,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub duration_seconds: Option<f64>,
+    pub ping_rate_hz: Option<f64>,
+    pub bounding_box: Option<BoundingBox>, // sensor positions, ship where the sensor's are empty
+    pub sensor_depth: Option<MinMax>,
+    pub altitude: Option<MinMax>,
+    pub record_types: Vec<RecordTypeCount>,
+}
+
+
+// Tracks min and max of whatever is fed in, ignoring NaNs
+#[derive(Default)]
+struct Extent(Option<MinMax>);
+
+impl Extent {
+    fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        self.0 = Some(match self.0 {
+            Some(range) => MinMax { min: range.min.min(value), max: range.max.max(value) },
+            None => MinMax { min: value, max: value },
+        });
+    }
+}
+
+
+pub fn summarize(xtf: &XtfFile) -> FileSummary {
+    let channels = xtf
+        .channel_infos
+        .iter()
+        .enumerate()
+        .map(|(index, info)| ChannelSummary {
+            index,
+            name: trimmed(get_string(info, "ChannelName")).unwrap_or_default(),
+            type_of_channel: get_number(info, "TypeOfChannel").map(|v| v as u8),
+            frequency: get_number(info, "Frequency"),
+            bytes_per_sample: get_number(info, "BytesPerSample").map(|v| v as u16),
+            sample_format: get_number(info, "SampleFormat").map(|v| v as u8),
+        })
+        .collect();
+
+    let mut record_types: BTreeMap<u8, usize> = BTreeMap::new();
+    for entry in &xtf.index.entries {
+        *record_types.entry(entry.header_type).or_insert(0) += 1;
+    }
+
+    let mut time = Extent::default();
+    let mut x = Extent::default();
+    let mut y = Extent::default();
+    let mut depth = Extent::default();
+    let mut altitude = Extent::default();
+    let mut pings = 0;
+
+    for entry in xtf.index.pings() {
+        pings += 1;
+        time.add(entry.time);
+
+        let field = |name: &str| xtf.ping_field(entry, name).unwrap_or(f64::NAN);
+        let (sensor_x, sensor_y) = (field("SensorXcoordinate"), field("SensorYcoordinate"));
+        let (position_x, position_y) = if sensor_x == 0.0 && sensor_y == 0.0 {
+            (field("ShipXcoordinate"), field("ShipYcoordinate"))
+        } else {
+            (sensor_x, sensor_y)
+        };
+
+        if is_position(position_x, position_y) {
+            x.add(position_x);
+            y.add(position_y);
+        }
+        depth.add(field("SensorDepth"));
+        altitude.add(field("SensorPrimaryAltitude"));
+    }
+
+    let duration = time.0.map(|range| range.max - range.min);
+    let ping_rate = match duration {
+        Some(duration) if duration > 0.0 && pings > 1 => Some((pings - 1) as f64 / duration),
+        _ => None,
+    };
+
+    let bounding_box = match (x.0, y.0) {
+        (Some(x), Some(y)) => Some(BoundingBox { min_x: x.min, min_y: y.min, max_x: x.max, max_y: y.max }),
+        _ => None,
+    };
+
+    FileSummary {
+        file: xtf.path.display().to_string(),
+        sonar_name: trimmed(get_string(&xtf.file_header, "SonarName")),
+        recording_program: trimmed(get_string(&xtf.file_header, "RecordingProgramName")),
+        recording_program_version: trimmed(get_string(&xtf.file_header, "RecordingProgramVersion")),
+        system_type: get_number(&xtf.file_header, "SystemType").map(|v| v as u8),
+        nav_units: get_number(&xtf.file_header, "NavUnits").map(|v| v as u16),
+        channels,
+        records: xtf.index.entries.len(),
+        pings,
+        start_time: time.0.map(|range| format_time(range.min)),
+        end_time: time.0.map(|range| format_time(range.max)),
+        duration_seconds: duration,
+        ping_rate_hz: ping_rate,
+        bounding_box,
+        sensor_depth: depth.0,
+        altitude: altitude.0,
+        record_types: record_types
+            .into_iter()
+            .map(|(header_type, count)| RecordTypeCount { header_type, name: header_type_name(header_type), count })
+            .collect(),
+    }
+}
+
+
+// Header strings are space or null padded
+fn trimmed(value: Option<String>) -> Option<String> {
+    value.map(|value| value.trim().to_string())
+}
+
+
+fn or_unknown<T: fmt::Display>(value: &Option<T>) -> String {
+    value.as_ref().map_or("unknown".to_string(), |value| value.to_string())
+}
+
+
+impl fmt::Display for FileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "File:              {}", self.file)?;
+        writeln!(f, "Sonar:             {}", or_unknown(&self.sonar_name))?;
+        writeln!(
+            f,
+            "Recorded with:     {} {}",
+            or_unknown(&self.recording_program),
+            self.recording_program_version.as_deref().unwrap_or("")
+        )?;
+        writeln!(f, "System type:       {}", or_unknown(&self.system_type))?;
+        let nav_units = match self.nav_units {
+            Some(0) => "metres".to_string(),
+            Some(3) => "lat/lon".to_string(),
+            other => or_unknown(&other),
+        };
+        writeln!(f, "Nav units:         {}", nav_units)?;
+
+        writeln!(f, "Channels:")?;
+        for channel in &self.channels {
+            let side = match channel.type_of_channel {
+                Some(0) => "subbottom",
+                Some(1) => "port",
+                Some(2) => "starboard",
+                Some(3) => "bathymetry",
+                _ => "unknown",
+            };
+            writeln!(
+                f,
+                "  {:>2} {:<16} {:<10} {:>8} kHz  format {} ({} bytes/sample)",
+                channel.index,
+                channel.name,
+                side,
+                or_unknown(&channel.frequency),
+                or_unknown(&channel.sample_format),
+                or_unknown(&channel.bytes_per_sample)
+            )?;
+        }
+
+        writeln!(f, "Records:           {}", self.records)?;
+        writeln!(f, "Pings:             {}", self.pings)?;
+        writeln!(f, "Start:             {}", or_unknown(&self.start_time))?;
+        writeln!(f, "End:               {}", or_unknown(&self.end_time))?;
+        writeln!(f, "Duration:          {}", self.duration_seconds.map_or("unknown".to_string(), |d| format!("{:.2} s", d)))?;
+        writeln!(f, "Ping rate:         {}", self.ping_rate_hz.map_or("unknown".to_string(), |r| format!("{:.3} Hz", r)))?;
+
+        match &self.bounding_box {
+            Some(bb) => writeln!(f, "Position bounds:   x {} to {}, y {} to {}", bb.min_x, bb.max_x, bb.min_y, bb.max_y)?,
+            None => writeln!(f, "Position bounds:   no positions")?,
+        }
+        let range = |range: &Option<MinMax>| range.map_or("unknown".to_string(), |r| format!("{:.2} to {:.2} m", r.min, r.max));
+        writeln!(f, "Sensor depth:      {}", range(&self.sensor_depth))?;
+        writeln!(f, "Altitude:          {}", range(&self.altitude))?;
+
+        writeln!(f, "Record types:")?;
+        for record_type in &self.record_types {
+            writeln!(f, "  {:>3} {:<24} {}", record_type.header_type, record_type.name, record_type.count)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{write_field, XTF_PING_HEADER};
+    use crate::test_data::{file_header, other_record, ping_record, TestPing};
+
+    // Four pings a second apart, the last with only a ship position, and a raw navigation record
+    fn summary() -> FileSummary {
+        let mut bytes = file_header(2);
+        for n in 0..4 {
+            let mut ping = ping_record(2, 16, &TestPing::numbered(n));
+            if n == 3 {
+                write_field(XTF_PING_HEADER, &mut ping, 0, "SensorXcoordinate", 0.0).unwrap();
+                write_field(XTF_PING_HEADER, &mut ping, 0, "SensorYcoordinate", 0.0).unwrap();
+                write_field(XTF_PING_HEADER, &mut ping, 0, "ShipXcoordinate", 1.5).unwrap();
+            }
+            write_field(XTF_PING_HEADER, &mut ping, 0, "SensorDepth", 20.0 + n as f64).unwrap();
+            bytes.extend(ping);
+        }
+        bytes.extend(other_record(107, 64));
+        summarize(&XtfFile::from_bytes(bytes).unwrap())
+    }
+
+    #[test]
+    fn summarizes_pings_and_records() {
+        let summary = summary();
+
+        assert_eq!((summary.records, summary.pings), (5, 4));
+        assert_eq!(summary.nav_units, Some(3));
+        let record_types: Vec<(u8, &str, usize)> = summary.record_types.iter().map(|r| (r.header_type, r.name, r.count)).collect();
+        assert_eq!(record_types, [(0, "SONAR", 4), (107, "POS_RAW_NAVIGATION", 1)]);
+
+        let types: Vec<Option<u8>> = summary.channels.iter().map(|channel| channel.type_of_channel).collect();
+        assert_eq!(types, [Some(1), Some(2)]);
+        assert_eq!(summary.channels[1].bytes_per_sample, Some(2));
+    }
+
+    #[test]
+    fn summarizes_time_and_rate() {
+        let summary = summary();
+
+        assert_eq!(summary.start_time.as_deref(), Some("2024-05-01T00:00:00.00Z"));
+        assert_eq!(summary.end_time.as_deref(), Some("2024-05-01T00:00:03.00Z"));
+        assert_eq!(summary.duration_seconds, Some(3.0));
+        assert_eq!(summary.ping_rate_hz, Some(1.0));
+    }
+
+    #[test]
+    fn summarizes_positions_depth_and_altitude() {
+        let summary = summary();
+
+        // the last ping falls back to its ship position
+        let bounds = summary.bounding_box.unwrap();
+        assert_eq!((bounds.min_x, bounds.max_x), (1.0, 1.5));
+        assert_eq!(bounds.min_y, 54.0);
+        assert!((bounds.max_y - 54.00003).abs() < 1e-9);
+
+        let depth = summary.sensor_depth.unwrap();
+        assert_eq!((depth.min, depth.max), (20.0, 23.0));
+        let altitude = summary.altitude.unwrap();
+        assert_eq!((altitude.min, altitude.max), (10.0, 10.0));
+
+        let text = summary.to_string();
+        assert!(text.contains("Nav units:         lat/lon"), "{}", text);
+        assert!(text.contains("Sensor depth:      20.00 to 23.00 m"), "{}", text);
+    }
+
+    #[test]
+    fn summarizes_a_file_without_pings() {
+        let summary = summarize(&XtfFile::from_bytes(file_header(2)).unwrap());
+
+        assert_eq!((summary.records, summary.pings), (0, 0));
+        assert!(summary.start_time.is_none() && summary.duration_seconds.is_none() && summary.ping_rate_hz.is_none());
+        assert!(summary.bounding_box.is_none());
+        assert!(summary.to_string().contains("Position bounds:   no positions"));
+    }
+}
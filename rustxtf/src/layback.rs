@@ -0,0 +1,315 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde_derive::Serialize;
+
+use crate::coords::{is_position, CoordinateSystem};
+use crate::heading::normalise_heading;
+use crate::headers::{get_number, read_headers, write_field, HeaderMap, XTF_HEADER_SONAR, XTF_PING_HEADER};
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+// Towfish position from the ship's. The fish is put straight astern of the ship's heading at
+// the horizontal layback, taken from the logged Layback when there is one and worked out from
+// cable out and fish depth otherwise. Sensor positions the logger already filled in with
+// something other than the ship's position are left alone unless forced
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum LaybackModel {
+    // cable runs in a straight line from the tow point to the fish
+    Straight,
+    // cable hangs in a catenary that is flat at the fish, so lies shorter than Straight
+    Catenary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FishPosition {
+    pub ping_number: u32,
+    pub layback: f64, // horizontal, metres
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LaybackReport {
+    pub records: usize,
+    pub changed: Vec<FishPosition>,
+    pub kept: Vec<u32>, // pings whose own sensor positions were left in place
+}
+
+
+// CableOut is whole metres, CableOutHundredths the fraction
+pub fn cable_out(header: &HeaderMap) -> Option<f64> {
+    let metres = get_number(header, "CableOut")?;
+    Some(metres + get_number(header, "CableOutHundredths").unwrap_or(0.0) / 100.0)
+}
+
+
+// Horizontal distance from tow point to fish for a cable of `cable` metres with the fish
+// `depth` metres below the tow point
+pub fn horizontal_layback(cable: f64, depth: f64, model: LaybackModel) -> f64 {
+    let depth = depth.max(0.0);
+    if cable <= depth {
+        return 0.0;
+    }
+    if depth == 0.0 {
+        return cable;
+    }
+
+    match model {
+        LaybackModel::Straight => (cable * cable - depth * depth).sqrt(),
+        LaybackModel::Catenary => {
+            // y = a (cosh(x / a) - 1) with the vertex at the fish. Arc length cable and rise
+            // depth give a = (cable^2 - depth^2) / (2 depth), and the layback is a asinh(cable / a)
+            let a = (cable * cable - depth * depth) / (2.0 * depth);
+            a * (cable / a).asinh()
+        }
+    }
+}
+
+
+// Logged Layback when the logger filled it in, otherwise from CableOut and SensorDepth
+pub fn ping_layback(header: &HeaderMap, model: LaybackModel) -> Option<f64> {
+    match get_number(header, "Layback") {
+        Some(layback) if layback > 0.0 && layback.is_finite() => Some(layback),
+        _ => {
+            let cable = cable_out(header)?;
+            Some(horizontal_layback(cable, get_number(header, "SensorDepth").unwrap_or(0.0), model))
+        }
+    }
+}
+
+
+// Ship's gyro, the sensor heading when the gyro is zero and the sensor's isn't. Both zero is
+// a due north tow rather than no heading
+fn ship_heading(header: &HeaderMap) -> Option<f64> {
+    let heading = |name: &str| get_number(header, name).filter(|heading| heading.is_finite());
+    let heading = match (heading("ShipGyro"), heading("SensorHeading")) {
+        (Some(gyro), Some(sensor)) if gyro == 0.0 && sensor != 0.0 => Some(sensor),
+        (Some(gyro), _) => Some(gyro),
+        (None, sensor) => sensor,
+    };
+    heading.map(normalise_heading)
+}
+
+
+// Fish position for one ping, None when there's no ship position, heading or layback
+pub fn fish_position(header: &HeaderMap, system: CoordinateSystem, model: LaybackModel) -> Option<FishPosition> {
+    let x = get_number(header, "ShipXcoordinate")?;
+    let y = get_number(header, "ShipYcoordinate")?;
+    if !is_position(x, y) {
+        return None;
+    }
+
+    let layback = ping_layback(header, model)?;
+    let heading = ship_heading(header)?;
+    let (x, y) = system.offset(x, y, layback, heading + 180.0);
+
+    Some(FishPosition {
+        ping_number: get_number(header, "PingNumber").unwrap_or(0.0) as u32,
+        layback,
+        x,
+        y,
+    })
+}
+
+
+pub fn fish_positions(xtf: &XtfFile, model: LaybackModel) -> Vec<FishPosition> {
+    let system = CoordinateSystem::of_file(&xtf.file_header, None);
+    xtf.index
+        .pings()
+        .filter_map(|entry| {
+            let (header, _) = read_headers(XTF_PING_HEADER, &xtf.data, entry.offset as usize);
+            fish_position(&header, system, model)
+        })
+        .collect()
+}
+
+
+// Whether the sensor position is missing or just a copy of the ship's, so the fish position
+// isn't overwriting anything the logger knew
+pub fn sensor_position_unset(header: &HeaderMap) -> bool {
+    let field = |name: &str| get_number(header, name).unwrap_or(f64::NAN);
+    let sensor = (field("SensorXcoordinate"), field("SensorYcoordinate"));
+    !is_position(sensor.0, sensor.1) || sensor == (field("ShipXcoordinate"), field("ShipYcoordinate"))
+}
+
+
+// Copies the file with SensorXcoordinate, SensorYcoordinate and Layback filled in from the
+// computed fish positions, where the sensor position is unset or force is given. Pings we
+// can't place or that keep their own positions are copied unchanged
+pub fn write_layback<P: AsRef<Path>>(xtf: &XtfFile, model: LaybackModel, force: bool, output: P) -> Result<LaybackReport, Box<dyn Error>> {
+    let system = CoordinateSystem::of_file(&xtf.file_header, None);
+    check_not_input(&output, &[&xtf.path])?;
+    let mut writer = XtfWriter::create(output, xtf.header_bytes())?;
+    let mut changed = Vec::new();
+    let mut kept = Vec::new();
+
+    for entry in &xtf.index.entries {
+        let bytes = xtf.record_bytes(entry)?;
+        if entry.header_type != XTF_HEADER_SONAR {
+            writer.write_record(bytes)?;
+            continue;
+        }
+
+        let (header, _) = read_headers(XTF_PING_HEADER, bytes, 0);
+        match fish_position(&header, system, model) {
+            Some(fish) if force || sensor_position_unset(&header) => {
+                let mut bytes = bytes.to_vec();
+                write_field(XTF_PING_HEADER, &mut bytes, 0, "SensorXcoordinate", fish.x)?;
+                write_field(XTF_PING_HEADER, &mut bytes, 0, "SensorYcoordinate", fish.y)?;
+                write_field(XTF_PING_HEADER, &mut bytes, 0, "Layback", fish.layback)?;
+                writer.write_record(&bytes)?;
+                changed.push(fish);
+            }
+            Some(fish) => {
+                writer.write_record(bytes)?;
+                kept.push(fish.ping_number);
+            }
+            None => writer.write_record(bytes)?,
+        }
+    }
+
+    Ok(LaybackReport { records: writer.finish()?, changed, kept })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_data::{file_header, ping_record, TempPath, TestPing};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    // Ping record with 50 m of cable out and the fish 30 m down, 40 m behind on a straight cable
+    fn towed_ping(n: u32, fields: &[(&str, f64)]) -> Vec<u8> {
+        let mut bytes = ping_record(2, 8, &TestPing::numbered(n));
+        for &(name, value) in [("CableOut", 50.0), ("SensorDepth", 30.0)].iter().chain(fields) {
+            write_field(XTF_PING_HEADER, &mut bytes, 0, name, value).unwrap();
+        }
+        bytes
+    }
+
+    fn header(fields: &[(&str, f64)]) -> HeaderMap {
+        let bytes = towed_ping(0, &[[("ShipXcoordinate", 500_000.0), ("ShipYcoordinate", 6_000_000.0)].as_slice(), fields].concat());
+        read_headers(XTF_PING_HEADER, &bytes, 0).0
+    }
+
+    #[test]
+    fn works_out_horizontal_layback() {
+        assert!(close(horizontal_layback(50.0, 30.0, LaybackModel::Straight), 40.0));
+
+        // a = 1600 / 60, a asinh(50 / a)
+        let a: f64 = 80.0 / 3.0;
+        let catenary = horizontal_layback(50.0, 30.0, LaybackModel::Catenary);
+        assert!(close(catenary, a * (50.0 / a).asinh()));
+        assert!(catenary < 40.0 && catenary > 30.0, "{}", catenary);
+
+        for model in [LaybackModel::Straight, LaybackModel::Catenary] {
+            assert_eq!(horizontal_layback(50.0, 0.0, model), 50.0);
+            assert_eq!(horizontal_layback(50.0, -5.0, model), 50.0);
+            assert_eq!(horizontal_layback(20.0, 30.0, model), 0.0);
+        }
+    }
+
+    #[test]
+    fn prefers_the_logged_layback() {
+        let mut bytes = towed_ping(0, &[("CableOutHundredths", 50.0)]);
+        assert_eq!(cable_out(&read_headers(XTF_PING_HEADER, &bytes, 0).0), Some(50.5));
+
+        write_field(XTF_PING_HEADER, &mut bytes, 0, "Layback", 12.0).unwrap();
+        assert_eq!(ping_layback(&read_headers(XTF_PING_HEADER, &bytes, 0).0, LaybackModel::Straight), Some(12.0));
+    }
+
+    #[test]
+    fn puts_the_fish_astern() {
+        let grid = CoordinateSystem::UnknownGrid;
+        let fish = |fields: &[(&str, f64)]| {
+            let fish = fish_position(&header(fields), grid, LaybackModel::Straight).unwrap();
+            (fish.x - 500_000.0, fish.y - 6_000_000.0)
+        };
+
+        // heading east the fish is west of the ship, heading south it's north
+        let (east, north) = fish(&[("ShipGyro", 90.0)]);
+        assert!(close(east, -40.0) && close(north, 0.0), "{} {}", east, north);
+        let (east, north) = fish(&[("ShipGyro", 180.0)]);
+        assert!(close(east, 0.0) && close(north, 40.0), "{} {}", east, north);
+
+        // the sensor heading only stands in for a zero gyro
+        let (east, _) = fish(&[("ShipGyro", 0.0), ("SensorHeading", 270.0)]);
+        assert!(close(east, 40.0));
+        let (east, _) = fish(&[("ShipGyro", 90.0), ("SensorHeading", 270.0)]);
+        assert!(close(east, -40.0));
+
+        // both zero is towing due north, not a missing heading
+        let (east, north) = fish(&[("ShipGyro", 0.0), ("SensorHeading", 0.0)]);
+        assert!(close(east, 0.0) && close(north, -40.0), "{} {}", east, north);
+    }
+
+    #[test]
+    fn needs_a_ship_position() {
+        let no_fix = header(&[("ShipXcoordinate", 0.0), ("ShipYcoordinate", 0.0)]);
+        assert!(fish_position(&no_fix, CoordinateSystem::UnknownGrid, LaybackModel::Straight).is_none());
+    }
+
+    #[test]
+    fn spots_unset_sensor_positions() {
+        let at_ship = header(&[("SensorXcoordinate", 500_000.0), ("SensorYcoordinate", 6_000_000.0)]);
+        let empty = header(&[("SensorXcoordinate", 0.0), ("SensorYcoordinate", 0.0)]);
+        let own = header(&[("SensorXcoordinate", 500_010.0), ("SensorYcoordinate", 5_999_960.0)]);
+
+        assert!(sensor_position_unset(&at_ship));
+        assert!(sensor_position_unset(&empty));
+        assert!(!sensor_position_unset(&own));
+    }
+
+    // Pings 0 and 1 have the sensor at the ship, ping 2 its own sensor position, all due north
+    fn layback_input(name: &str) -> (TempPath, XtfFile) {
+        let mut bytes = file_header(2);
+        bytes.extend(towed_ping(0, &[]));
+        bytes.extend(towed_ping(1, &[]));
+        bytes.extend(towed_ping(2, &[("SensorYcoordinate", 53.9)]));
+        let path = TempPath::new(name);
+        fs::write(&path.0, bytes).unwrap();
+        let xtf = XtfFile::open(&path.0).unwrap();
+        (path, xtf)
+    }
+
+    fn sensor_y(xtf: &XtfFile, ping: usize) -> f64 {
+        xtf.ping_field(&xtf.index.entries[ping], "SensorYcoordinate").unwrap()
+    }
+
+    #[test]
+    fn fills_only_unset_sensor_positions() {
+        let (_input, xtf) = layback_input("layback-in.xtf");
+        let output = TempPath::new("layback-out.xtf");
+
+        let report = write_layback(&xtf, LaybackModel::Straight, false, &output.0).unwrap();
+        assert_eq!(report.records, 3);
+        assert_eq!(report.changed.iter().map(|fish| fish.ping_number).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(report.kept, [2]);
+
+        let written = XtfFile::open(&output.0).unwrap();
+        // 40 m south of the ship
+        let ship = TestPing::numbered(0).y;
+        assert!(close(sensor_y(&written, 0), ship - (40.0 / 6_371_008.8f64).to_degrees()));
+        assert_eq!(written.ping_field(&written.index.entries[0], "Layback"), Some(40.0));
+        assert_eq!(sensor_y(&written, 2), 53.9);
+    }
+
+    #[test]
+    fn replaces_every_sensor_position_with_force() {
+        let (_input, xtf) = layback_input("layback-force-in.xtf");
+        let output = TempPath::new("layback-force-out.xtf");
+
+        let report = write_layback(&xtf, LaybackModel::Straight, true, &output.0).unwrap();
+        assert_eq!(report.changed.len(), 3);
+        assert!(report.kept.is_empty());
+        let written = XtfFile::open(&output.0).unwrap();
+        assert!(close(sensor_y(&written, 2), TestPing::numbered(2).y - (40.0 / 6_371_008.8f64).to_degrees()));
+    }
+}
@@ -0,0 +1,40 @@
+pub mod bottom;
+pub mod channels;
+pub mod coords;
+pub mod dump;
+pub mod extract;
+pub mod filters;
+pub mod gain;
+pub mod georef;
+pub mod heading;
+pub mod headers;
+pub mod image;
+pub mod index;
+pub mod info;
+pub mod layback;
+pub mod lines;
+pub mod merge;
+pub mod mosaic;
+pub mod navigation;
+pub mod processing;
+pub mod record;
+pub mod samples;
+pub mod slant_range;
+pub mod split;
+pub mod time;
+pub mod time_repair;
+pub mod timing;
+pub mod track;
+pub mod validate;
+pub mod water_column;
+pub mod waterfall;
+pub mod writer;
+pub mod xtf_file;
+
+#[cfg(test)]
+mod test_data;
+
+pub use headers::{HeaderMap, HeaderValue};
+pub use index::{IndexEntry, RecordIndex};
+pub use record::{Ping, PingChannel, Record};
+pub use xtf_file::XtfFile;
@@ -0,0 +1,309 @@
+use serde_derive::Serialize;
+
+use crate::coords::{is_position, projected_for, CoordinateSystem};
+use crate::heading::{heading_difference, normalise_heading, record_heading};
+use crate::headers::XTF_HEADER_SONAR;
+use crate::xtf_file::XtfFile;
+
+// Splits a file's pings into straight survey lines and the turns between them. A ping is on
+// line when both its heading and its course over the ground are changing slower than the
+// turn rate, measured across a window of pings centred on it. Lines too short to be worth
+// having are counted as part of the turn around them
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LineSettings {
+    pub max_turn_rate: f64, // degrees per second
+    pub window: usize,      // pings the turn rate is measured over
+    pub min_length: f64,    // metres, shorter lines become part of the turn
+}
+
+impl Default for LineSettings {
+    fn default() -> LineSettings {
+        LineSettings { max_turn_rate: 1.0, window: 9, min_length: 50.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SegmentKind {
+    Line,
+    Turn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub first_ping: u32,
+    pub last_ping: u32,
+    pub pings: usize,
+    pub start_time: f64,
+    pub end_time: f64,
+    pub heading: Option<f64>, // course made good from the first position to the last, degrees
+    pub turn: Option<f64>,    // signed heading change from the first ping to the last, degrees
+    pub length: f64,          // metres along the track
+    #[serde(skip)]
+    pub first_entry: usize, // index entries the segment starts and ends on
+    #[serde(skip)]
+    pub last_entry: usize,
+}
+
+// What segmentation needs from each ping
+struct LinePing {
+    entry: usize,
+    ping_number: u32,
+    time: f64,
+    position: Option<(f64, f64)>, // as logged
+    metres: Option<(f64, f64)>,   // projected
+    heading: Option<f64>,
+}
+
+
+fn read_pings(xtf: &XtfFile) -> Vec<LinePing> {
+    let system = CoordinateSystem::of_file(&xtf.file_header, None);
+    let mut pings: Vec<LinePing> = xtf
+        .index
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.header_type == XTF_HEADER_SONAR)
+        .map(|(i, entry)| {
+            let field = |name: &str| xtf.ping_field(entry, name).unwrap_or(f64::NAN);
+            let sensor = (field("SensorXcoordinate"), field("SensorYcoordinate"));
+            let ship = (field("ShipXcoordinate"), field("ShipYcoordinate"));
+            let position = [sensor, ship].into_iter().find(|&(x, y)| is_position(x, y));
+            LinePing {
+                entry: i,
+                ping_number: entry.ping_number,
+                time: entry.time,
+                position,
+                metres: None,
+                heading: record_heading(&xtf.data, entry.offset as usize),
+            }
+        })
+        .collect();
+
+    // a metre grid without a zone is fine here, only distances and directions matter
+    if let Some((x, y)) = pings.iter().find_map(|ping| ping.position) {
+        let metres = projected_for(system, x, y, None);
+        for ping in &mut pings {
+            ping.metres = ping.position.and_then(|(x, y)| system.convert(x, y, metres));
+        }
+    }
+    pings
+}
+
+
+// Course between two positions in degrees. Longitude/latitude over the short distances
+// involved is near enough flat once longitude is scaled by cos(latitude)
+fn course(from: (f64, f64), to: (f64, f64), geographic: bool) -> Option<f64> {
+    let scale = if geographic { ((from.1 + to.1) / 2.0).to_radians().cos() } else { 1.0 };
+    let (dx, dy) = ((to.0 - from.0) * scale, to.1 - from.1);
+    if dx == 0.0 && dy == 0.0 {
+        return None;
+    }
+    Some(normalise_heading(dx.atan2(dy).to_degrees()))
+}
+
+
+// Metres along the track, leaving out steps to or from pings without a position
+fn track_length(pings: &[LinePing]) -> f64 {
+    pings
+        .windows(2)
+        .filter_map(|pair| match (pair[0].metres, pair[1].metres) {
+            (Some(a), Some(b)) => Some((b.0 - a.0).hypot(b.1 - a.1)),
+            _ => None,
+        })
+        .sum()
+}
+
+
+// Seconds from ping a to ping b, or a second per ping when the times are missing
+fn seconds_between(pings: &[LinePing], a: usize, b: usize) -> f64 {
+    let dt = pings[b].time - pings[a].time;
+    if dt.is_finite() && dt > 0.0 {
+        dt
+    } else {
+        (b - a) as f64
+    }
+}
+
+
+// Whether each ping is on line. Pings with nothing to measure by count as on line, a turn
+// needs evidence
+fn on_line(pings: &[LinePing], settings: &LineSettings) -> Vec<bool> {
+    let half = settings.window.max(2) / 2;
+    (0..pings.len())
+        .map(|i| {
+            let (before, after) = (i.saturating_sub(half), (i + half).min(pings.len() - 1));
+            if before == after {
+                return true;
+            }
+            let dt = seconds_between(pings, before, after);
+
+            let heading_rate = match (pings[before].heading, pings[after].heading) {
+                (Some(a), Some(b)) => heading_difference(a, b).abs() / dt,
+                _ => 0.0,
+            };
+
+            // course into the ping against course out of it
+            let course_rate = match (pings[before].metres, pings[i].metres, pings[after].metres) {
+                (Some(a), Some(b), Some(c)) if before < i && i < after => {
+                    match (course(a, b, false), course(b, c, false)) {
+                        (Some(into), Some(out)) => heading_difference(into, out).abs() / (dt / 2.0),
+                        _ => 0.0,
+                    }
+                }
+                _ => 0.0,
+            };
+
+            heading_rate <= settings.max_turn_rate && course_rate <= settings.max_turn_rate
+        })
+        .collect()
+}
+
+
+fn make_segment(pings: &[LinePing], kind: SegmentKind, first: usize, last: usize, geographic: bool) -> Segment {
+    let run = &pings[first..=last];
+    let heading = match (run.iter().find_map(|ping| ping.position), run.iter().rev().find_map(|ping| ping.position)) {
+        (Some(start), Some(end)) => course(start, end, geographic),
+        _ => None,
+    };
+    let turn = match (run.iter().find_map(|ping| ping.heading), run.iter().rev().find_map(|ping| ping.heading)) {
+        (Some(start), Some(end)) => Some(heading_difference(start, end)),
+        _ => None,
+    };
+
+    Segment {
+        kind,
+        first_ping: pings[first].ping_number,
+        last_ping: pings[last].ping_number,
+        pings: run.len(),
+        start_time: pings[first].time,
+        end_time: pings[last].time,
+        heading,
+        turn,
+        length: track_length(run),
+        first_entry: pings[first].entry,
+        last_entry: pings[last].entry,
+    }
+}
+
+
+// Lines and turns covering every ping in file order
+pub fn find_lines(xtf: &XtfFile, settings: &LineSettings) -> Vec<Segment> {
+    let pings = read_pings(xtf);
+    if pings.is_empty() {
+        return Vec::new();
+    }
+    let geographic = CoordinateSystem::of_file(&xtf.file_header, None).is_geographic();
+    let straight = on_line(&pings, settings);
+
+    // runs of pings that are all on line or all turning
+    let mut runs: Vec<(bool, usize, usize)> = Vec::new();
+    for (i, &is_straight) in straight.iter().enumerate() {
+        match runs.last_mut() {
+            Some((kind, _, last)) if *kind == is_straight => *last = i,
+            _ => runs.push((is_straight, i, i)),
+        }
+    }
+
+    // short lines go into the turn, then neighbouring turns join up
+    let mut merged: Vec<(SegmentKind, usize, usize)> = Vec::new();
+    for (is_straight, first, last) in runs {
+        let kind = if is_straight && track_length(&pings[first..=last]) >= settings.min_length {
+            SegmentKind::Line
+        } else {
+            SegmentKind::Turn
+        };
+        match merged.last_mut() {
+            Some((previous, _, end)) if *previous == kind => *end = last,
+            _ => merged.push((kind, first, last)),
+        }
+    }
+
+    merged.into_iter().map(|(kind, first, last)| make_segment(&pings, kind, first, last, geographic)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{write_field, XTF_FILE_HEADER};
+    use crate::test_data::{file_header, ping_record, TestPing};
+
+    // A ping a second, 2 m apart on a metre grid, following the headings
+    fn survey(headings: &[f64]) -> XtfFile {
+        let mut bytes = file_header(2);
+        write_field(XTF_FILE_HEADER, &mut bytes, 0, "NavUnits", 0.0).unwrap();
+        let (mut x, mut y) = (1000.0, 1000.0);
+        for (n, &heading) in headings.iter().enumerate() {
+            let ping = TestPing { x, y, heading, ..TestPing::numbered(n as u32) };
+            bytes.extend(ping_record(2, 16, &ping));
+            x += 2.0 * heading.to_radians().sin();
+            y += 2.0 * heading.to_radians().cos();
+        }
+        XtfFile::from_bytes(bytes).unwrap()
+    }
+
+    // straight for `pings`, then turning `degrees` at 3 degrees a second
+    fn leg(headings: &mut Vec<f64>, pings: usize, degrees: f64) {
+        let heading = headings.last().copied().unwrap_or(0.0);
+        headings.extend(std::iter::repeat_n(heading, pings));
+        headings.extend((1..=(degrees / 3.0) as usize).map(|i| heading + i as f64 * 3.0));
+    }
+
+    fn kinds(segments: &[Segment]) -> Vec<SegmentKind> {
+        segments.iter().map(|segment| segment.kind).collect()
+    }
+
+    fn assert_covers(segments: &[Segment], pings: usize) {
+        assert_eq!(segments.iter().map(|segment| segment.pings).sum::<usize>(), pings);
+        for pair in segments.windows(2) {
+            assert_eq!(pair[1].first_ping, pair[0].last_ping + 1);
+        }
+    }
+
+    #[test]
+    fn separates_lines_from_the_turn_between_them() {
+        let mut headings = Vec::new();
+        leg(&mut headings, 40, 90.0);
+        leg(&mut headings, 40, 0.0);
+        let segments = find_lines(&survey(&headings), &LineSettings::default());
+
+        use SegmentKind::{Line, Turn};
+        assert_eq!(kinds(&segments), [Line, Turn, Line], "{:?}", segments);
+        assert_covers(&segments, headings.len());
+
+        let (north, turn, east) = (&segments[0], &segments[1], &segments[2]);
+        assert!((north.heading.unwrap() - 0.0).abs() < 1.0 && (east.heading.unwrap() - 90.0).abs() < 1.0);
+        assert!(north.turn.unwrap().abs() < 1e-9 && (turn.turn.unwrap() - 90.0).abs() < 10.0, "{:?}", turn);
+        // pings 40 - 69 turn, the window takes up to half its width either side into it too
+        assert!((36..=40).contains(&turn.first_ping) && (69..=73).contains(&turn.last_ping), "{:?}", turn);
+        assert!((north.length - 2.0 * (north.pings - 1) as f64).abs() < 1e-6);
+    }
+
+    #[test]
+    fn short_lines_join_the_turns_around_them() {
+        let mut headings = Vec::new();
+        leg(&mut headings, 40, 90.0);
+        leg(&mut headings, 16, 90.0);
+        leg(&mut headings, 40, 0.0);
+        let xtf = survey(&headings);
+
+        use SegmentKind::{Line, Turn};
+        let segments = find_lines(&xtf, &LineSettings::default());
+        assert_eq!(kinds(&segments), [Line, Turn, Line], "{:?}", segments);
+        assert_covers(&segments, headings.len());
+        assert!((segments[1].turn.unwrap() - 180.0).abs() < 10.0, "{:?}", segments[1]);
+
+        let segments = find_lines(&xtf, &LineSettings { min_length: 5.0, ..LineSettings::default() });
+        assert_eq!(kinds(&segments), [Line, Turn, Line, Turn, Line], "{:?}", segments);
+        assert_covers(&segments, headings.len());
+    }
+
+    #[test]
+    fn a_file_without_pings_has_no_lines() {
+        let xtf = XtfFile::from_bytes(file_header(2)).unwrap();
+        assert!(find_lines(&xtf, &LineSettings::default()).is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::headers::{get_number, get_string, write_field, XTF_HEADER_SONAR, XTF_PING_HEADER};
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+// Chan info fields that have to agree for pings from two files to mean the same thing
+const CHANNEL_FIELDS: &[&str] = &["TypeOfChannel", "SubChannelNumber", "BytesPerSample", "Frequency", "SampleFormat"];
+
+
+// Errors if other can't be appended to first, naming the first thing that differs
+pub fn check_compatible(first: &XtfFile, other: &XtfFile) -> Result<(), Box<dyn Error>> {
+    let name = other.path.display();
+
+    // NavUnits too, or a lat/lon track and a metres track would end up in the same file
+    for field in ["NumberOfSonarChannels", "NumberOfBathymetryChannels", "NavUnits"] {
+        let expected = get_number(&first.file_header, field);
+        let found = get_number(&other.file_header, field);
+        if expected != found {
+            return Err(format!("{}: {} is {}, expected {}", name, field, describe(found), describe(expected)).into());
+        }
+    }
+
+    for (channel, (expected, found)) in first.channel_infos.iter().zip(&other.channel_infos).enumerate() {
+        for &field in CHANNEL_FIELDS {
+            if get_number(expected, field) != get_number(found, field) {
+                return Err(format!(
+                    "{}: channel {} {} is {}, expected {}",
+                    name,
+                    channel,
+                    field,
+                    describe(get_number(found, field)),
+                    describe(get_number(expected, field))
+                )
+                .into());
+            }
+        }
+
+        if get_string(expected, "ChannelName") != get_string(found, "ChannelName") {
+            return Err(format!("{}: channel {} has a different ChannelName", name, channel).into());
+        }
+    }
+
+    Ok(())
+}
+
+
+fn describe(value: Option<f64>) -> String {
+    value.map_or("missing".to_string(), |v| v.to_string())
+}
+
+
+// Concatenates inputs into output using the first file's header. With renumber_pings the
+// PingNumbers carry on counting from the first ping of the first file
+pub fn merge<P: AsRef<Path>, Q: AsRef<Path>>(inputs: &[P], output: Q, renumber_pings: bool) -> Result<usize, Box<dyn Error>> {
+    let first_path = inputs.first().ok_or("Nothing to merge")?;
+
+    // check every header before writing anything
+    let input_paths: Vec<&Path> = inputs.iter().map(|input| input.as_ref()).collect();
+    check_not_input(&output, &input_paths)?;
+    let first = XtfFile::open_header_only(first_path)?;
+    for input in &inputs[1..] {
+        check_compatible(&first, &XtfFile::open_header_only(input)?)?;
+    }
+
+    let mut writer = XtfWriter::create(output, first.header_bytes())?;
+    let mut next_ping_number: Option<u32> = None;
+
+    for input in inputs {
+        let xtf = XtfFile::open(input)?;
+
+        for entry in &xtf.index.entries {
+            let bytes = xtf.record_bytes(entry)?;
+
+            if renumber_pings && entry.header_type == XTF_HEADER_SONAR {
+                let ping_number = next_ping_number.unwrap_or(entry.ping_number);
+                let mut bytes = bytes.to_vec();
+                write_field(XTF_PING_HEADER, &mut bytes, 0, "PingNumber", ping_number as f64)?;
+                writer.write_record(&bytes)?;
+                next_ping_number = Some(ping_number.wrapping_add(1));
+            } else {
+                writer.write_record(bytes)?;
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::ops::Range;
+
+    use super::*;
+    use crate::headers::XTF_FILE_HEADER;
+    use crate::test_data::{assert_consistent, other_record, xtf_bytes, TempPath, TestPing};
+
+    fn input(name: &str, channels: u16, pings: Range<u32>) -> TempPath {
+        let path = TempPath::new(name);
+        let mut bytes = xtf_bytes(channels, 16, &pings.map(TestPing::numbered).collect::<Vec<_>>());
+        bytes.extend(other_record(107, 64));
+        fs::write(&path.0, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn merges_and_renumbers() {
+        let (a, b) = (input("merge-a.xtf", 2, 0..4), input("merge-b.xtf", 2, 100..103));
+        let output = TempPath::new("merge-out.xtf");
+
+        assert_eq!(merge(&[&a.0, &b.0], &output.0, true).unwrap(), 9);
+        let xtf = XtfFile::open(&output.0).unwrap();
+        assert_consistent(&xtf);
+
+        assert_eq!(xtf.number_of_sonar_channels(), 2);
+        assert_eq!(xtf.index.pings().map(|entry| entry.ping_number).collect::<Vec<_>>(), (0..7).collect::<Vec<_>>());
+        assert_eq!(xtf.index.entries.iter().filter(|entry| entry.header_type == 107).count(), 2);
+        assert!(xtf.index.pings().all(|entry| xtf.ping(entry).unwrap().channels.len() == 2));
+    }
+
+    #[test]
+    fn keeps_ping_numbers_without_renumber() {
+        let (a, b) = (input("merge-keep-a.xtf", 2, 0..2), input("merge-keep-b.xtf", 2, 50..52));
+        let output = TempPath::new("merge-keep-out.xtf");
+
+        merge(&[&a.0, &b.0], &output.0, false).unwrap();
+        let xtf = XtfFile::open(&output.0).unwrap();
+        assert_eq!(xtf.index.pings().map(|entry| entry.ping_number).collect::<Vec<_>>(), vec![0, 1, 50, 51]);
+    }
+
+    #[test]
+    fn refuses_files_with_different_channels() {
+        let (a, b) = (input("merge-two.xtf", 2, 0..2), input("merge-four.xtf", 4, 0..2));
+        let output = TempPath::new("merge-mixed-out.xtf");
+
+        assert!(merge(&[&a.0, &b.0], &output.0, false).is_err());
+        assert!(!output.0.exists());
+    }
+
+    #[test]
+    fn refuses_files_with_different_nav_units() {
+        let (a, b) = (input("merge-latlon.xtf", 2, 0..2), input("merge-metres.xtf", 2, 2..4));
+        let mut bytes = fs::read(&b.0).unwrap();
+        write_field(XTF_FILE_HEADER, &mut bytes, 0, "NavUnits", 0.0).unwrap();
+        fs::write(&b.0, bytes).unwrap();
+        let output = TempPath::new("merge-units-out.xtf");
+
+        let error = merge(&[&a.0, &b.0], &output.0, false).unwrap_err();
+        assert!(error.to_string().contains("NavUnits is 0, expected 3"), "{}", error);
+        assert!(!output.0.exists());
+    }
+
+    #[test]
+    fn refuses_to_write_over_an_input() {
+        let (a, b) = (input("merge-self-a.xtf", 2, 0..2), input("merge-self-b.xtf", 2, 2..4));
+        let before = fs::read(&a.0).unwrap();
+
+        assert!(merge(&[&a.0, &b.0], &a.0, false).is_err());
+        assert_eq!(fs::read(&a.0).unwrap(), before);
+    }
+}
@@ -0,0 +1,447 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde_derive::Serialize;
+use serde_json::json;
+
+use crate::coords::{CoordinateSystem, UtmZone};
+use crate::gain::normalise_across_track;
+use crate::georef::Georeferencer;
+use crate::image::{write_tiff, TiffTag};
+use crate::navigation::{check_navigation, NavFlag, NavQcSettings};
+use crate::processing::Processing;
+use crate::track::TrackSource;
+use crate::xtf_file::XtfFile;
+
+// Grids ground range corrected, georeferenced sidescan from one or more files into a raster.
+// Each ping's samples are laid along the line from nadir out to the far range, and the gap to
+// the next ping on the same channel is filled in so there are no holes between pings
+
+// Pixels are a value and a priority, 8 bytes each, so this is under a gigabyte of grid
+const MAX_PIXELS: usize = 100_000_000;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Overlap {
+    // later pings (and later files) overwrite earlier ones
+    LastOnTop,
+    // samples within this many metres of nadir only fill pixels nothing else has covered
+    NadirAvoid(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MosaicSettings {
+    pub resolution: f64, // metres per pixel
+    pub overlap: Overlap,
+    pub processing: Processing, // ground_resolution is the mosaic resolution if not set
+    pub navigation: Option<NavQcSettings>, // pings whose sensor positions fail these checks are left out
+    pub max_ping_gap: f64, // metres, pings further apart aren't joined up, it's a line break or a nav jump
+}
+
+#[derive(Debug, Clone)]
+pub struct Mosaic {
+    pub system: CoordinateSystem,
+    pub min_x: f64, // left edge
+    pub max_y: f64, // top edge
+    pub resolution: f64,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<f32>, // NaN where there's no data, rows from the top
+    priority: Vec<f32>,
+}
+
+// One channel of one ping: ground range samples from near (nadir) to far
+struct Swath {
+    near: (f64, f64),
+    far: (f64, f64),
+    samples: Vec<f32>,
+    ground_spacing: f64,
+    order: usize, // ping count across all the files, for last on top
+}
+
+
+impl Overlap {
+    fn priority(&self, ground_range: f64) -> f32 {
+        match *self {
+            Overlap::LastOnTop => 0.0,
+            Overlap::NadirAvoid(distance) => {
+                if ground_range >= distance {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+
+// Every port and starboard channel of every ping as a run of swaths per file and channel
+fn read_swaths(
+    xtf: &XtfFile,
+    georeferencer: &Georeferencer,
+    processing: &Processing,
+    navigation: Option<&NavQcSettings>,
+    first_order: usize,
+) -> Result<Vec<Vec<Swath>>, Box<dyn Error>> {
+    let ground_spacing = processing.ground_resolution.unwrap_or(1.0);
+    let mut channels: Vec<Vec<Option<Swath>>> = (0..xtf.channel_infos.len()).map(|_| Vec::new()).collect();
+
+    let outliers: Vec<bool> = match navigation {
+        Some(settings) => check_navigation(xtf, georeferencer.source, TrackSource::Sensor, settings)
+            .iter()
+            .map(|point| matches!(point.flag, NavFlag::SpeedOutlier | NavFlag::AccelerationOutlier))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    for (order, entry) in xtf.index.pings().enumerate() {
+        let ping = xtf.ping(entry)?;
+        let outlier = outliers.get(order).copied().unwrap_or(false);
+        for (i, channel) in ping.channels.iter().enumerate() {
+            let Some(run) = channels.get_mut(channel.channel_number as usize) else { continue };
+            let samples = processing.channel_samples(xtf, &ping, i);
+            let far_range = samples.len() as f64 * ground_spacing;
+
+            let swath = match (
+                georeferencer.ground_position(xtf, &ping, i, 0.0),
+                georeferencer.ground_position(xtf, &ping, i, far_range),
+            ) {
+                (Some(near), Some(far)) if !samples.is_empty() && !outlier => {
+                    Some(Swath { near, far, samples, ground_spacing, order: first_order + order })
+                }
+                _ => None,
+            };
+            run.push(swath);
+        }
+    }
+
+    // normalisation needs the pings in order, with the ones we can't place left in the window
+    if let Some(window) = processing.normalise_window {
+        for run in &mut channels {
+            let mut rows: Vec<Vec<f32>> = run.iter().map(|swath| swath.as_ref().map(|s| s.samples.clone()).unwrap_or_default()).collect();
+            normalise_across_track(&mut rows, window);
+            for (swath, row) in run.iter_mut().zip(rows) {
+                if let Some(swath) = swath {
+                    swath.samples = row;
+                }
+            }
+        }
+    }
+
+    Ok(channels.into_iter().map(|run| run.into_iter().flatten().collect()).filter(|run: &Vec<Swath>| !run.is_empty()).collect())
+}
+
+
+impl Swath {
+    fn far_range(&self) -> f64 {
+        self.samples.len() as f64 * self.ground_spacing
+    }
+
+    // Along the line from near to far, fraction 0 at nadir and 1 at the far range
+    fn position(&self, fraction: f64) -> (f64, f64) {
+        (self.near.0 + (self.far.0 - self.near.0) * fraction, self.near.1 + (self.far.1 - self.near.1) * fraction)
+    }
+}
+
+
+// Output is in the first file's georeferencer target: output_zone, or the UTM zone the data is
+// in. input_zone is the zone of files whose positions are in metres
+pub fn build_mosaic(
+    files: &[XtfFile],
+    input_zone: Option<UtmZone>,
+    output_zone: Option<UtmZone>,
+    settings: &MosaicSettings,
+) -> Result<Mosaic, Box<dyn Error>> {
+    let resolution = settings.resolution;
+    if resolution.is_nan() || resolution <= 0.0 {
+        return Err(format!("mosaic resolution has to be more than zero, not {}", resolution).into());
+    }
+    let mut processing = settings.processing;
+    processing.ground_resolution = Some(processing.ground_resolution.unwrap_or(resolution));
+
+    let target = Georeferencer::for_file(files.first().ok_or("nothing to mosaic")?, input_zone, output_zone).target;
+    let mut runs: Vec<Vec<Swath>> = Vec::new();
+    let mut pings = 0;
+    for xtf in files {
+        let georeferencer = Georeferencer {
+            source: CoordinateSystem::of_file(&xtf.file_header, input_zone),
+            target,
+        };
+        runs.extend(read_swaths(xtf, &georeferencer, &processing, settings.navigation.as_ref(), pings)?);
+        pings += xtf.index.pings().count();
+    }
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for swath in runs.iter().flatten() {
+        for (x, y) in [swath.near, swath.far] {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if !min_x.is_finite() {
+        return Err("nothing to mosaic, no pings with positions, headings and port or starboard channels".into());
+    }
+
+    // snap the edges to whole pixels
+    let min_x = (min_x / resolution).floor() * resolution;
+    let max_y = (max_y / resolution).ceil() * resolution;
+    let (width, height) = ((max_x - min_x) / resolution, (max_y - min_y) / resolution);
+    let pixels = (width.ceil() + 1.0) * (height.ceil() + 1.0);
+    if pixels > MAX_PIXELS as f64 {
+        return Err(format!(
+            "a {:.0} x {:.0} m mosaic at {} m would be {:.0} x {:.0} pixels, more than {}. Try --resolution {:.2} or coarser",
+            max_x - min_x,
+            max_y - min_y,
+            resolution,
+            width.ceil() + 1.0,
+            height.ceil() + 1.0,
+            MAX_PIXELS,
+            resolution * (pixels / MAX_PIXELS as f64).sqrt() * 1.01
+        )
+        .into());
+    }
+    let (width, height) = (width.ceil() as usize + 1, height.ceil() as usize + 1);
+    let size = width.checked_mul(height).ok_or("mosaic too big")?;
+
+    let mut mosaic = Mosaic {
+        system: target,
+        min_x,
+        max_y,
+        resolution,
+        width,
+        height,
+        pixels: vec![f32::NAN; size],
+        priority: vec![f32::NEG_INFINITY; size],
+    };
+
+    // in ping order, so last on top means the latest ping whichever channel it's on
+    let mut order: Vec<(usize, usize, usize)> = Vec::new();
+    for (r, run) in runs.iter().enumerate() {
+        order.extend(run.iter().enumerate().map(|(i, swath)| (swath.order, r, i)));
+    }
+    order.sort();
+    for (_, r, i) in order {
+        mosaic.add_swath(&runs[r][i], runs[r].get(i + 1), settings.overlap, settings.max_ping_gap);
+    }
+
+    Ok(mosaic)
+}
+
+
+impl Mosaic {
+    fn put(&mut self, x: f64, y: f64, value: f32, priority: f32) {
+        let column = ((x - self.min_x) / self.resolution).floor();
+        let row = ((self.max_y - y) / self.resolution).floor();
+        if column < 0.0 || row < 0.0 || column >= self.width as f64 || row >= self.height as f64 {
+            return;
+        }
+
+        let i = row as usize * self.width + column as usize;
+        if priority >= self.priority[i] {
+            self.pixels[i] = value;
+            self.priority[i] = priority;
+        }
+    }
+
+    // Lays a swath's samples down, filling across to the next swath when it's no more than
+    // max_gap metres on. Steps are half a pixel so rotated swaths don't leave holes, and taken
+    // at the middle of each step so none lands on a pixel edge, where port and starboard would
+    // round different ways
+    fn add_swath(&mut self, swath: &Swath, next: Option<&Swath>, overlap: Overlap, max_gap: f64) {
+        let n = swath.samples.len();
+        let next = next.filter(|next| {
+            let (dx, dy) = (next.near.0 - swath.near.0, next.near.1 - swath.near.1);
+            dx.hypot(dy) <= max_gap
+        });
+
+        let across_steps = (swath.far_range() / (self.resolution / 2.0)).ceil().max(1.0) as usize;
+        for step in 0..across_steps {
+            let fraction = (step as f64 + 0.5) / across_steps as f64;
+            let bin = ((fraction * n as f64) as usize).min(n - 1);
+            let value = swath.samples[bin];
+            if !value.is_finite() {
+                continue;
+            }
+            let ground_range = fraction * swath.far_range();
+            let priority = overlap.priority(ground_range);
+
+            let start = swath.position(fraction);
+            let end = match next {
+                Some(next) => next.position((ground_range / next.far_range()).min(1.0)),
+                None => start,
+            };
+            let along_steps = ((end.0 - start.0).hypot(end.1 - start.1) / (self.resolution / 2.0)).ceil().max(1.0) as usize;
+            for along in 0..along_steps {
+                let t = along as f64 / along_steps as f64;
+                self.put(start.0 + (end.0 - start.0) * t, start.1 + (end.1 - start.1) * t, value, priority);
+            }
+        }
+    }
+
+    // Float32 GeoTIFF, NaN for no data, with the grid and CRS in the GeoTIFF tags and the
+    // settings it was made with in ImageDescription
+    pub fn write_geotiff<P: AsRef<Path>>(&self, path: P, settings: &MosaicSettings) -> Result<(), Box<dyn Error>> {
+        let bytes: Vec<u8> = self.pixels.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+
+        // GeoKeyDirectory: version 1.1.0, then (key, location 0 = value here, count 1, value)
+        let mut keys: Vec<[u16; 4]> = vec![[1025, 0, 1, 1]]; // GTRasterTypeGeoKey PixelIsArea
+        match self.system {
+            CoordinateSystem::Utm(zone) => {
+                keys.push([1024, 0, 1, 1]); // GTModelTypeGeoKey projected
+                keys.push([3072, 0, 1, zone.epsg()]); // ProjectedCSTypeGeoKey
+                keys.push([3076, 0, 1, 9001]); // ProjLinearUnitsGeoKey metre
+            }
+            CoordinateSystem::Geographic => {
+                keys.push([1024, 0, 1, 2]); // GTModelTypeGeoKey geographic
+                keys.push([2048, 0, 1, 4326]); // GeographicTypeGeoKey WGS84
+            }
+            CoordinateSystem::UnknownGrid => {
+                keys.push([1024, 0, 1, 1]);
+                keys.push([3072, 0, 1, 32767]); // user defined
+                keys.push([3076, 0, 1, 9001]);
+            }
+        }
+        keys.sort_by_key(|key| key[0]);
+        let mut directory = vec![1, 1, 0, keys.len() as u16];
+        directory.extend(keys.iter().flatten());
+
+        let description = json!({ "settings": settings, "system": self.system }).to_string();
+        let tags = vec![
+            TiffTag::ascii(270, &description),                                                  // ImageDescription
+            TiffTag::doubles(33550, &[self.resolution, self.resolution, 0.0]),                  // ModelPixelScale
+            TiffTag::doubles(33922, &[0.0, 0.0, 0.0, self.min_x, self.max_y, 0.0]),             // ModelTiepoint
+            TiffTag::shorts(34735, &directory),                                                 // GeoKeyDirectory
+            TiffTag::ascii(42113, "nan"),                                                       // GDAL_NODATA
+        ];
+
+        write_tiff(path, self.width, self.height, 32, 3, &bytes, tags)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::headers::{write_field, XTF_FILE_HEADER, XTF_PING_HEADER};
+    use crate::test_data::{file_header, ping_record, tiff_tags, TempPath, TestPing};
+
+    const X: f64 = 500_000.0;
+    const Y: f64 = 6_000_000.0;
+
+    fn settings(resolution: f64, overlap: Overlap) -> MosaicSettings {
+        MosaicSettings { resolution, overlap, processing: Processing::default(), navigation: None, max_ping_gap: 10.0 }
+    }
+
+    fn swath(near: (f64, f64), far: (f64, f64), value: f32, order: usize) -> Swath {
+        let samples = vec![value; (far.0 - near.0).hypot(far.1 - near.1).round() as usize];
+        Swath { near, far, samples, ground_spacing: 1.0, order }
+    }
+
+    fn empty(width: usize, height: usize) -> Mosaic {
+        Mosaic {
+            system: CoordinateSystem::UnknownGrid,
+            min_x: 0.0,
+            max_y: height as f64,
+            resolution: 1.0,
+            width,
+            height,
+            pixels: vec![f32::NAN; width * height],
+            priority: vec![f32::NEG_INFINITY; width * height],
+        }
+    }
+
+    #[test]
+    fn lays_a_ping_out_either_side_of_nadir() {
+        // heading north in metres at zero altitude, 10 samples over 50 m each side
+        let mut bytes = file_header(2);
+        write_field(XTF_FILE_HEADER, &mut bytes, 0, "NavUnits", 0.0).unwrap();
+        let mut ping = ping_record(2, 10, &TestPing { x: X, y: Y, ..TestPing::numbered(0) });
+        write_field(XTF_PING_HEADER, &mut ping, 0, "SensorPrimaryAltitude", 0.0).unwrap();
+        bytes.extend(ping);
+        let xtf = XtfFile::from_bytes(bytes).unwrap();
+
+        let mosaic = build_mosaic(&[xtf], None, None, &settings(5.0, Overlap::LastOnTop)).unwrap();
+        assert_eq!((mosaic.min_x, mosaic.max_y), (X - 50.0, Y));
+        assert_eq!((mosaic.width, mosaic.height), (21, 1));
+        // port samples count up from 0 and are west, starboard from 1 and east
+        assert_eq!(mosaic.pixels[7], 2.0, "10 - 15 m west");
+        assert_eq!(mosaic.pixels[12], 3.0, "10 - 15 m east");
+        assert_eq!(mosaic.pixels[19], 10.0);
+        assert_eq!(mosaic.pixels[0], 9.0);
+    }
+
+    #[test]
+    fn puts_the_last_ping_on_top() {
+        // two pings looking at the same ground from either end
+        let mut mosaic = empty(4, 1);
+        let (first, second) = (swath((0.0, 0.5), (4.0, 0.5), 1.0, 0), swath((4.0, 0.5), (0.0, 0.5), 2.0, 1));
+        mosaic.add_swath(&first, None, Overlap::LastOnTop, 10.0);
+        mosaic.add_swath(&second, None, Overlap::LastOnTop, 10.0);
+        assert_eq!(mosaic.pixels, [2.0; 4]);
+    }
+
+    #[test]
+    fn keeps_near_nadir_samples_underneath() {
+        let mut mosaic = empty(4, 1);
+        let (first, second) = (swath((0.0, 0.5), (4.0, 0.5), 1.0, 0), swath((4.0, 0.5), (0.0, 0.5), 2.0, 1));
+        mosaic.add_swath(&first, None, Overlap::NadirAvoid(1.5), 10.0);
+        mosaic.add_swath(&second, None, Overlap::NadirAvoid(1.5), 10.0);
+        // the second ping's nadir doesn't cover the first ping's far range
+        assert_eq!(mosaic.pixels, [2.0, 2.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn joins_pings_up_to_the_max_gap() {
+        let (first, next) = (swath((0.0, 15.5), (2.0, 15.5), 5.0, 0), swath((0.0, 0.5), (2.0, 0.5), 5.0, 1));
+        let filled = |max_gap: f64| {
+            let mut mosaic = empty(2, 16);
+            mosaic.add_swath(&first, Some(&next), Overlap::LastOnTop, max_gap);
+            (0..16).filter(|row| mosaic.pixels[row * 2].is_finite()).count()
+        };
+        assert_eq!(filled(10.0), 1);
+        assert_eq!(filled(20.0), 16);
+    }
+
+    #[test]
+    fn refuses_a_mosaic_too_big_to_hold() {
+        let xtf = XtfFile::from_bytes([file_header(2), ping_record(2, 10, &TestPing { heading: 90.0, ..TestPing::numbered(0) })].concat()).unwrap();
+        let error = build_mosaic(&[xtf], None, None, &settings(0.001, Overlap::LastOnTop)).unwrap_err();
+        assert!(error.to_string().contains("Try --resolution"), "{}", error);
+    }
+
+    #[test]
+    fn writes_the_grid_and_crs_to_geotiff_tags() {
+        let mut mosaic = empty(3, 2);
+        mosaic.system = CoordinateSystem::Utm(UtmZone { zone: 38, north: true });
+        mosaic.min_x = 444_100.0;
+        mosaic.max_y = 3_684_800.0;
+        mosaic.resolution = 0.5;
+        mosaic.pixels[4] = 7.5;
+
+        let path = TempPath::new("mosaic.tif");
+        mosaic.write_geotiff(&path.0, &settings(0.5, Overlap::LastOnTop)).unwrap();
+        let bytes = fs::read(&path.0).unwrap();
+        let tags = tiff_tags(&bytes);
+
+        let doubles = |tag: u16| tags[&tag].2.chunks(8).map(|d| f64::from_le_bytes(d.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(doubles(33550), [0.5, 0.5, 0.0]);
+        assert_eq!(doubles(33922), [0.0, 0.0, 0.0, 444_100.0, 3_684_800.0, 0.0]);
+
+        let keys: Vec<u16> = tags[&34735].2.chunks(2).map(|s| u16::from_le_bytes([s[0], s[1]])).collect();
+        assert_eq!(keys[..4], [1, 1, 0, 4]);
+        let keys: Vec<&[u16]> = keys[4..].chunks(4).collect();
+        assert_eq!(keys, [[1024, 0, 1, 1], [1025, 0, 1, 1], [3072, 0, 1, 32638], [3076, 0, 1, 9001]]);
+
+        assert_eq!(tags[&339].2[..2], [3, 0], "float samples");
+        let strip = u32::from_le_bytes(tags[&273].2[..4].try_into().unwrap()) as usize;
+        let pixels: Vec<f32> = bytes[strip..strip + 24].chunks(4).map(|p| f32::from_le_bytes(p.try_into().unwrap())).collect();
+        assert_eq!(pixels[4], 7.5);
+        assert!(pixels[0].is_nan());
+    }
+}
@@ -0,0 +1,368 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde_derive::Serialize;
+
+use crate::coords::{is_position, projected_for, CoordinateSystem};
+use crate::headers::{write_field, XTF_HEADER_SONAR, XTF_PING_HEADER};
+use crate::time::{ping_positions, spread_between_anchors};
+use crate::track::TrackSource;
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+// Navigation QC and smoothing. Fixes are checked in metres (geographic positions go through
+// UTM) against the last good fix: repeated fixes, jumps needing an impossible speed and speed
+// changes needing an impossible acceleration are flagged. A constant velocity Kalman filter
+// with a Rauch-Tung-Striebel smoothing pass then gives a position for every ping, flagged
+// fixes and pings without one being filled in from the motion around them
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NavQcSettings {
+    pub max_speed: f64,        // m/s
+    pub max_acceleration: f64, // m/s^2
+    pub process_noise: f64,    // m/s^2, how hard the vessel is allowed to manoeuvre
+    pub fix_noise: f64, // m, fix accuracy
+}
+
+impl Default for NavQcSettings {
+    fn default() -> NavQcSettings {
+        NavQcSettings { max_speed: 10.0, max_acceleration: 2.0, process_noise: 0.2, fix_noise: 3.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum NavFlag {
+    Good,
+    NoPosition,
+    // same position as the fix before, the logger repeating a stale fix
+    Duplicate,
+    SpeedOutlier,
+    AccelerationOutlier,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NavPoint {
+    pub ping_number: u32,
+    pub time: f64,
+    pub x: f64, // as logged, in the file's units
+    pub y: f64,
+    pub flag: NavFlag,
+    pub smoothed_x: f64, // NaN if there was nothing to smooth from
+    pub smoothed_y: f64,
+}
+
+
+fn position_fields(source: TrackSource) -> (&'static str, &'static str) {
+    match source {
+        TrackSource::Ship => ("ShipXcoordinate", "ShipYcoordinate"),
+        TrackSource::Sensor => ("SensorXcoordinate", "SensorYcoordinate"),
+    }
+}
+
+
+// Checks and smooths the ship or sensor positions of every ping. `system` is what the file's
+// positions are in
+pub fn check_navigation(xtf: &XtfFile, system: CoordinateSystem, source: TrackSource, settings: &NavQcSettings) -> Vec<NavPoint> {
+    let (x_field, y_field) = position_fields(source);
+    let mut points: Vec<NavPoint> = xtf
+        .index
+        .pings()
+        .map(|entry| {
+            let x = xtf.ping_field(entry, x_field).unwrap_or(f64::NAN);
+            let y = xtf.ping_field(entry, y_field).unwrap_or(f64::NAN);
+            NavPoint {
+                ping_number: entry.ping_number,
+                time: entry.time,
+                x,
+                y,
+                flag: if is_position(x, y) { NavFlag::Good } else { NavFlag::NoPosition },
+                smoothed_x: f64::NAN,
+                smoothed_y: f64::NAN,
+            }
+        })
+        .collect();
+
+    // work in metres
+    let metres = match points.iter().find(|point| point.flag == NavFlag::Good) {
+        Some(first) => projected_for(system, first.x, first.y, None),
+        None => return points,
+    };
+    let projected: Vec<Option<(f64, f64)>> = points
+        .iter()
+        .map(|point| if point.flag == NavFlag::Good { system.convert(point.x, point.y, metres) } else { None })
+        .collect();
+    let times = ping_times(&points);
+
+    flag_outliers(&mut points, &projected, &times, settings);
+
+    let fixes: Vec<Option<(f64, f64)>> = points
+        .iter()
+        .zip(&projected)
+        .map(|(point, position)| if point.flag == NavFlag::Good { *position } else { None })
+        .collect();
+    let smoothed_x = smooth_axis(&times, &fixes.iter().map(|fix| fix.map(|(x, _)| x)).collect::<Vec<_>>(), settings);
+    let smoothed_y = smooth_axis(&times, &fixes.iter().map(|fix| fix.map(|(_, y)| y)).collect::<Vec<_>>(), settings);
+
+    for (i, point) in points.iter_mut().enumerate() {
+        if let Some((x, y)) = metres.convert(smoothed_x[i], smoothed_y[i], system) {
+            point.smoothed_x = x;
+            point.smoothed_y = y;
+        }
+    }
+    points
+}
+
+
+// Ping times, with missing ones spread by ping number between the pings around them, or a
+// second per ping when there aren't enough times to go on
+fn ping_times(points: &[NavPoint]) -> Vec<f64> {
+    let positions = ping_positions(&points.iter().map(|point| point.ping_number).collect::<Vec<_>>());
+    let times: Vec<Option<f64>> = points.iter().map(|point| Some(point.time).filter(|time| time.is_finite())).collect();
+    let filled = spread_between_anchors(&times, &positions);
+    times.iter().zip(filled).map(|(time, filled)| time.or(filled)).collect::<Option<Vec<f64>>>().unwrap_or(positions)
+}
+
+
+// A jump that the fixes after it agree with is a real step in the navigation (a new
+// correction source, say) rather than a bad fix. After this many fixes the track carries on
+// from there instead of flagging everything after the step
+const FIXES_TO_ACCEPT_JUMP: usize = 5;
+
+
+fn flag_outliers(points: &mut [NavPoint], projected: &[Option<(f64, f64)>], times: &[f64], settings: &NavQcSettings) {
+    // last good fix and the speed into it
+    let mut last: Option<(usize, f64)> = None;
+    // fixes since the last good one that are consistent with each other
+    let mut rejected: Vec<usize> = Vec::new();
+
+    for i in 0..points.len() {
+        let Some((x, y)) = projected[i] else {
+            if points[i].flag == NavFlag::Good {
+                points[i].flag = NavFlag::NoPosition;
+            }
+            continue;
+        };
+        let Some((previous, previous_speed)) = last else {
+            last = Some((i, f64::NAN));
+            continue;
+        };
+        let (px, py) = projected[previous].unwrap_or((x, y));
+
+        if (x, y) == (px, py) {
+            points[i].flag = NavFlag::Duplicate;
+            continue;
+        }
+
+        let dt = times[i] - times[previous];
+        if dt <= 0.0 {
+            continue; // timing problem, not a navigation one, leave it for the timing checks
+        }
+        let speed = (x - px).hypot(y - py) / dt;
+        let flag = if speed > settings.max_speed {
+            NavFlag::SpeedOutlier
+        } else if previous_speed.is_finite() && (speed - previous_speed).abs() / dt > settings.max_acceleration {
+            NavFlag::AccelerationOutlier
+        } else {
+            rejected.clear();
+            last = Some((i, speed));
+            continue;
+        };
+        points[i].flag = flag;
+
+        let follows_on = match rejected.last().and_then(|&r| projected[r].map(|position| (r, position))) {
+            Some((r, (rx, ry))) => {
+                let dt = times[i] - times[r];
+                dt > 0.0 && (x - rx).hypot(y - ry) / dt <= settings.max_speed
+            }
+            None => true,
+        };
+        if !follows_on {
+            rejected.clear();
+        }
+        rejected.push(i);
+        if rejected.len() >= FIXES_TO_ACCEPT_JUMP {
+            // the first fix after the step keeps its flag, the ones confirming it are fine
+            for &r in &rejected[1..] {
+                points[r].flag = NavFlag::Good;
+            }
+            rejected.clear();
+            last = Some((i, f64::NAN));
+        }
+    }
+}
+
+
+// Constant velocity Kalman filter over one axis with an RTS smoother on the way back. State is
+// position and velocity, fixes are positions, None where there isn't a usable one
+fn smooth_axis(times: &[f64], fixes: &[Option<f64>], settings: &NavQcSettings) -> Vec<f64> {
+    type State = [f64; 2];
+    type Covariance = [[f64; 2]; 2];
+
+    let n = fixes.len();
+    let Some(first) = fixes.iter().position(|fix| fix.is_some()) else {
+        return vec![f64::NAN; n];
+    };
+
+    let q = settings.process_noise * settings.process_noise;
+    let r = settings.fix_noise * settings.fix_noise;
+
+    let mut predicted: Vec<(State, Covariance)> = Vec::with_capacity(n);
+    let mut filtered: Vec<(State, Covariance)> = Vec::with_capacity(n);
+    let mut state: State = [fixes[first].unwrap_or(0.0), 0.0];
+    let mut covariance: Covariance = [[r, 0.0], [0.0, 100.0]];
+
+    for i in 0..n {
+        // predict, skipped for the first ping which is the starting point
+        if i > 0 {
+            let dt = (times[i] - times[i - 1]).max(0.0);
+            state = [state[0] + dt * state[1], state[1]];
+            let p = covariance;
+            let (dt2, dt3, dt4) = (dt * dt, dt * dt * dt, dt * dt * dt * dt);
+            covariance = [
+                [p[0][0] + dt * (p[1][0] + p[0][1]) + dt2 * p[1][1] + q * dt4 / 4.0, p[0][1] + dt * p[1][1] + q * dt3 / 2.0],
+                [p[1][0] + dt * p[1][1] + q * dt3 / 2.0, p[1][1] + q * dt2],
+            ];
+        }
+        predicted.push((state, covariance));
+
+        // update with the fix, if there is one
+        if let Some(z) = fixes[i] {
+            let p = covariance;
+            let s = p[0][0] + r;
+            let gain = [p[0][0] / s, p[1][0] / s];
+            let innovation = z - state[0];
+            state = [state[0] + gain[0] * innovation, state[1] + gain[1] * innovation];
+            covariance = [
+                [(1.0 - gain[0]) * p[0][0], (1.0 - gain[0]) * p[0][1]],
+                [p[1][0] - gain[1] * p[0][0], p[1][1] - gain[1] * p[0][1]],
+            ];
+        }
+        filtered.push((state, covariance));
+    }
+
+    // smoothing pass, newest to oldest
+    let mut smoothed: Vec<State> = vec![[0.0; 2]; n];
+    smoothed[n - 1] = filtered[n - 1].0;
+    for i in (0..n - 1).rev() {
+        let (state, p) = filtered[i];
+        let (next_predicted, next_predicted_covariance) = predicted[i + 1];
+        let dt = (times[i + 1] - times[i]).max(0.0);
+
+        // C = P F' inverse(P predicted)
+        let pf = [[p[0][0] + dt * p[0][1], p[0][1]], [p[1][0] + dt * p[1][1], p[1][1]]];
+        let m = next_predicted_covariance;
+        let determinant = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        if determinant.abs() < f64::EPSILON {
+            smoothed[i] = state;
+            continue;
+        }
+        let inverse = [[m[1][1] / determinant, -m[0][1] / determinant], [-m[1][0] / determinant, m[0][0] / determinant]];
+        let c = [
+            [pf[0][0] * inverse[0][0] + pf[0][1] * inverse[1][0], pf[0][0] * inverse[0][1] + pf[0][1] * inverse[1][1]],
+            [pf[1][0] * inverse[0][0] + pf[1][1] * inverse[1][0], pf[1][0] * inverse[0][1] + pf[1][1] * inverse[1][1]],
+        ];
+
+        let difference = [smoothed[i + 1][0] - next_predicted[0], smoothed[i + 1][1] - next_predicted[1]];
+        smoothed[i] = [
+            state[0] + c[0][0] * difference[0] + c[0][1] * difference[1],
+            state[1] + c[1][0] * difference[0] + c[1][1] * difference[1],
+        ];
+    }
+
+    // nothing to go on before the first fix
+    smoothed.iter().enumerate().map(|(i, state)| if i < first { f64::NAN } else { state[0] }).collect()
+}
+
+
+// Copies the file with the checked positions replaced by the smoothed ones. Returns records
+// written
+pub fn write_smoothed<P: AsRef<Path>>(xtf: &XtfFile, points: &[NavPoint], source: TrackSource, output: P) -> Result<usize, Box<dyn Error>> {
+    let (x_field, y_field) = position_fields(source);
+    check_not_input(&output, &[&xtf.path])?;
+    let mut writer = XtfWriter::create(output, xtf.header_bytes())?;
+    let mut points = points.iter();
+
+    for entry in &xtf.index.entries {
+        let bytes = xtf.record_bytes(entry)?;
+        if entry.header_type != XTF_HEADER_SONAR {
+            writer.write_record(bytes)?;
+            continue;
+        }
+
+        match points.next().filter(|point| point.smoothed_x.is_finite() && point.smoothed_y.is_finite()) {
+            Some(point) => {
+                let mut bytes = bytes.to_vec();
+                write_field(XTF_PING_HEADER, &mut bytes, 0, x_field, point.smoothed_x)?;
+                write_field(XTF_PING_HEADER, &mut bytes, 0, y_field, point.smoothed_y)?;
+                writer.write_record(&bytes)?;
+            }
+            None => writer.write_record(bytes)?,
+        }
+    }
+
+    writer.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{xtf_bytes, TestPing};
+
+    #[test]
+    fn smooths_a_straight_constant_speed_track_onto_itself() {
+        let times: Vec<f64> = (0..60).map(|i| i as f64 * 0.5).collect();
+        let truth: Vec<f64> = times.iter().map(|t| 100.0 + 2.0 * t).collect();
+        // every fifth fix missing, they should be filled in on the line
+        let fixes: Vec<Option<f64>> = truth.iter().enumerate().map(|(i, x)| if i % 5 == 3 { None } else { Some(*x) }).collect();
+
+        let smoothed = smooth_axis(&times, &fixes, &NavQcSettings::default());
+        for (i, (smoothed, truth)) in smoothed.iter().zip(&truth).enumerate() {
+            assert!((smoothed - truth).abs() < 0.05, "ping {}: {} not {}", i, smoothed, truth);
+        }
+    }
+
+    #[test]
+    fn nothing_before_the_first_fix() {
+        let smoothed = smooth_axis(&[0.0, 1.0, 2.0, 3.0], &[None, None, Some(5.0), Some(6.0)], &NavQcSettings::default());
+        assert!(smoothed[0].is_nan() && smoothed[1].is_nan());
+        assert!(smoothed[2].is_finite() && smoothed[3].is_finite());
+    }
+
+    #[test]
+    fn flags_a_jump_and_smooths_over_it() {
+        let pings: Vec<TestPing> = (0..30)
+            .map(|i| {
+                let ping = TestPing::numbered(i);
+                if i == 12 { TestPing { y: ping.y + 0.01, ..ping } } else { ping }
+            })
+            .collect();
+        let xtf = XtfFile::from_bytes(xtf_bytes(2, 8, &pings)).unwrap();
+
+        let points = check_navigation(&xtf, CoordinateSystem::Geographic, TrackSource::Sensor, &NavQcSettings::default());
+        assert_eq!(points[12].flag, NavFlag::SpeedOutlier);
+        assert!(points.iter().enumerate().all(|(i, point)| i == 12 || point.flag == NavFlag::Good));
+        // back on the line, which moves 1e-5 degrees a ping
+        assert!((points[12].smoothed_y - TestPing::numbered(12).y).abs() < 2e-6, "{}", points[12].smoothed_y);
+    }
+
+    #[test]
+    fn fills_in_missing_times_by_ping_number() {
+        let point = |ping_number: u32, time: f64| NavPoint {
+            ping_number,
+            time,
+            x: 0.0,
+            y: 0.0,
+            flag: NavFlag::Good,
+            smoothed_x: f64::NAN,
+            smoothed_y: f64::NAN,
+        };
+        // ping 12 was dropped, so 13 is two steps after 11
+        let points = [point(10, 100.0), point(11, f64::NAN), point(13, 103.0), point(14, f64::NAN)];
+        assert_eq!(ping_times(&points), vec![100.0, 101.0, 103.0, 104.0]);
+
+        let untimed = [point(1, f64::NAN), point(2, f64::NAN), point(4, f64::NAN)];
+        assert_eq!(ping_times(&untimed), vec![1.0, 2.0, 4.0]);
+    }
+}
@@ -0,0 +1,39 @@
+use serde_derive::Serialize;
+
+use crate::gain::{apply_tvg, channel_range, Tvg};
+use crate::headers::get_number;
+use crate::record::Ping;
+use crate::slant_range::slant_to_ground;
+use crate::xtf_file::XtfFile;
+
+
+// What to do to decoded samples before they go into an image. Serialises so outputs can
+// record how they were made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Processing {
+    pub tvg: Option<Tvg>,
+    pub ground_resolution: Option<f64>, // metres per sample after slant range correction
+    pub normalise_window: Option<usize>, // pings averaged for across track normalisation
+}
+
+impl Processing {
+    // Per ping steps for one channel: gain first, while samples are still in slant range,
+    // then the ground range correction. Normalisation works over many pings so the caller does
+    // that once it has them all
+    pub fn channel_samples(&self, xtf: &XtfFile, ping: &Ping, channel_index: usize) -> Vec<f32> {
+        let mut samples = xtf.samples(ping, channel_index);
+
+        if let Some(tvg) = self.tvg {
+            apply_tvg(&mut samples, channel_range(ping, channel_index), tvg);
+        }
+
+        match self.ground_resolution {
+            Some(resolution) => {
+                let slant_range = get_number(&ping.channels[channel_index].header, "SlantRange").unwrap_or(0.0);
+                let altitude = get_number(&ping.header, "SensorPrimaryAltitude").unwrap_or(0.0);
+                slant_to_ground(&samples, slant_range, altitude, resolution)
+            }
+            None => samples,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::error::Error;
+
+use crate::headers::{
+    find_byte_offset_for_value, get_number, read_and_decode_byte_as_number_u8, read_headers,
+    read_unsigned_long, read_unsigned_short, HeaderMap, MAGIC_NUMBER, PING_HEADER_LENGTH,
+    XTF_HEADER_SONAR, XTF_PING_CHAN_HEADER, XTF_PING_HEADER,
+};
+
+// Every record starts with the same 14 bytes: magic, header type, ... NumBytesThisRecord
+pub const RECORD_PREFIX_LENGTH: usize = 14;
+
+
+#[derive(Debug, Clone)]
+pub enum Record {
+    // Sidescan ping (header type 0) with its channel headers
+    Sonar(Ping),
+    // Anything we don't interpret, kept byte for byte (0xFACE header included) so it can be copied out again
+    Unknown { header_type: u8, bytes: Vec<u8> },
+}
+
+impl Record {
+    pub fn header_type(&self) -> u8 {
+        match self {
+            Record::Sonar(_) => XTF_HEADER_SONAR,
+            Record::Unknown { header_type, .. } => *header_type,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Record::Sonar(ping) => &ping.bytes,
+            Record::Unknown { bytes, .. } => bytes,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Ping {
+    pub header: HeaderMap,
+    pub channels: Vec<PingChannel>,
+    pub bytes: Vec<u8>, // whole record, samples are sliced out of this
+}
+
+#[derive(Debug, Clone)]
+pub struct PingChannel {
+    pub header: HeaderMap,
+    pub channel_number: u16,
+    pub bytes_per_sample: usize,
+    pub num_samples: usize,
+    pub data_offset: usize, // start of the samples within the record bytes
+}
+
+impl Ping {
+    // Raw little endian sample bytes for one of the channels that follow the ping header
+    pub fn raw_samples(&self, channel_index: usize) -> &[u8] {
+        let channel = &self.channels[channel_index];
+        let len = channel.num_samples * channel.bytes_per_sample;
+        &self.bytes[channel.data_offset..channel.data_offset + len]
+    }
+}
+
+
+// Reads the type and length of the record at offset without parsing the body
+pub fn read_record_prefix(data: &[u8], offset: usize) -> Result<(u8, usize), Box<dyn Error>> {
+    let magic = read_unsigned_short(data, offset)?;
+    if magic != MAGIC_NUMBER {
+        return Err(format!("No magic number at offset {}", offset).into());
+    }
+
+    let header_type = read_and_decode_byte_as_number_u8(data, offset + 2)?;
+    let length = read_unsigned_long(data, offset + 10)? as usize;
+
+    if length < RECORD_PREFIX_LENGTH {
+        return Err(format!("Record at offset {} claims only {} bytes", offset, length).into());
+    }
+    if offset + length > data.len() {
+        return Err(format!("Record at offset {} runs past end of file ({} bytes)", offset, length).into());
+    }
+
+    Ok((header_type, length))
+}
+
+
+// Finds the start of the next record at or after offset. Normally this is offset itself,
+// if the data is damaged we fall back to scanning for the magic number
+pub fn next_record_offset(data: &[u8], offset: usize) -> Option<usize> {
+    let mut offset = offset;
+
+    while let Some(candidate) = find_byte_offset_for_value(data, offset, MAGIC_NUMBER) {
+        if read_record_prefix(data, candidate).is_ok() {
+            return Some(candidate);
+        }
+        offset = candidate + 1;
+    }
+
+    None
+}
+
+
+// Parses the record at offset, bytes_per_sample is indexed by ChannelNumber (from the chan infos)
+pub fn read_record(data: &[u8], offset: usize, bytes_per_sample: &[usize]) -> Result<Record, Box<dyn Error>> {
+    let (header_type, length) = read_record_prefix(data, offset)?;
+    let bytes = data[offset..offset + length].to_vec();
+
+    if header_type != XTF_HEADER_SONAR {
+        return Ok(Record::Unknown { header_type, bytes });
+    }
+
+    Ok(Record::Sonar(parse_ping(bytes, bytes_per_sample)?))
+}
+
+
+pub fn parse_ping(bytes: Vec<u8>, bytes_per_sample: &[usize]) -> Result<Ping, Box<dyn Error>> {
+    if bytes.len() < PING_HEADER_LENGTH {
+        return Err("Ping record shorter than ping header".into());
+    }
+
+    let (header, mut channel_offset) = read_headers(XTF_PING_HEADER, &bytes, 0);
+    let chans_to_follow = get_number(&header, "NumChansToFollow").unwrap_or(0.0) as usize;
+
+    let mut channels = Vec::with_capacity(chans_to_follow);
+
+    for i in 0..chans_to_follow {
+        let (chan_header, data_offset) = read_headers(XTF_PING_CHAN_HEADER, &bytes, channel_offset);
+
+        let channel_number = get_number(&chan_header, "ChannelNumber").unwrap_or(i as f64) as u16;
+        let num_samples = get_number(&chan_header, "NumSamples").unwrap_or(0.0) as usize;
+        let sample_size = bytes_per_sample.get(channel_number as usize).copied().unwrap_or(1);
+
+        let end = data_offset + num_samples * sample_size;
+        if end > bytes.len() {
+            return Err(format!("Channel {} samples run past end of ping record", channel_number).into());
+        }
+
+        channels.push(PingChannel {
+            header: chan_header,
+            channel_number,
+            bytes_per_sample: sample_size,
+            num_samples,
+            data_offset,
+        });
+
+        channel_offset = end;
+    }
+
+    Ok(Ping { header, channels, bytes })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::HeaderValue;
+    use crate::test_data::{other_record, ping_record, TestPing};
+
+    #[test]
+    fn reads_2h_fields_past_u16() {
+        // 2 channels of 20000 two byte samples is more than a u16 of record
+        let ping = TestPing { ping_number: 70_000, ..TestPing::numbered(0) };
+        let bytes = ping_record(2, 20_000, &ping);
+        assert!(bytes.len() > u16::MAX as usize);
+
+        let (header_type, length) = read_record_prefix(&bytes, 0).unwrap();
+        assert_eq!((header_type, length), (XTF_HEADER_SONAR, bytes.len()));
+
+        let ping = parse_ping(bytes.clone(), &[2, 2]).unwrap();
+        assert_eq!(get_number(&ping.header, "PingNumber"), Some(70_000.0));
+        assert_eq!(get_number(&ping.header, "NumBytesThisRecord"), Some(bytes.len() as f64));
+        assert_eq!(ping.channels[1].num_samples, 20_000);
+        assert_eq!(get_number(&ping.channels[1].header, "NumSamples"), Some(20_000.0));
+    }
+
+    #[test]
+    fn keeps_2h_fields_past_i32_unsigned() {
+        let ping = TestPing { ping_number: 3_000_000_000, ..TestPing::numbered(0) };
+        let ping = parse_ping(ping_record(1, 10, &ping), &[2]).unwrap();
+
+        let value = ping.header["PingNumber"].clone().unwrap();
+        assert!(matches!(value, HeaderValue::UInt(3_000_000_000)), "{:?}", value);
+        assert_eq!(serde_json::to_string(&value).unwrap(), "3000000000");
+        assert_eq!(get_number(&ping.header, "PingNumber"), Some(3_000_000_000.0));
+    }
+
+    #[test]
+    fn keeps_unknown_records_byte_for_byte() {
+        let bytes = other_record(107, 64);
+        match read_record(&bytes, 0, &[]).unwrap() {
+            Record::Unknown { header_type, bytes: kept } => {
+                assert_eq!(header_type, 107);
+                assert_eq!(kept, bytes);
+            }
+            Record::Sonar(_) => panic!("read a type 107 record as a ping"),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// SampleFormat values from the chan info, 0 means work it out from BytesPerSample
+pub const SAMPLE_FORMAT_LEGACY: u8 = 0;
+pub const SAMPLE_FORMAT_IBM_FLOAT: u8 = 1;
+pub const SAMPLE_FORMAT_INT32: u8 = 2;
+pub const SAMPLE_FORMAT_INT16: u8 = 3;
+pub const SAMPLE_FORMAT_IEEE_FLOAT: u8 = 5;
+pub const SAMPLE_FORMAT_BYTE: u8 = 8;
+
+
+// Converts raw little endian samples to f32 whatever they were stored as
+pub fn decode_samples(raw: &[u8], bytes_per_sample: usize, sample_format: u8) -> Vec<f32> {
+    let bytes_per_sample = bytes_per_sample.max(1);
+    let chunks = raw.chunks_exact(bytes_per_sample);
+
+    match (sample_format, bytes_per_sample) {
+        (SAMPLE_FORMAT_IBM_FLOAT, 4) => chunks.map(|c| ibm_to_f32(u32::from_le_bytes([c[0], c[1], c[2], c[3]]))).collect(),
+        (SAMPLE_FORMAT_INT32, 4) => chunks.map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32).collect(),
+        (SAMPLE_FORMAT_INT16, 2) => chunks.map(|c| i16::from_le_bytes([c[0], c[1]]) as f32).collect(),
+        (SAMPLE_FORMAT_IEEE_FLOAT, 4) => chunks.map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+        // legacy and anything we don't recognise: unsigned integers of BytesPerSample width
+        (_, 1) => raw.iter().map(|&b| b as f32).collect(),
+        (_, 2) => chunks.map(|c| u16::from_le_bytes([c[0], c[1]]) as f32).collect(),
+        (_, 4) => chunks.map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32).collect(),
+        _ => chunks.map(|c| c[0] as f32).collect(),
+    }
+}
+
+
+// IBM System/360 single precision: sign, base 16 exponent biased by 64, 24 bit fraction
+fn ibm_to_f32(bits: u32) -> f32 {
+    let sign = if bits >> 31 == 1 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 24) & 0x7f) as i32 - 64;
+    let fraction = (bits & 0x00ff_ffff) as f64 / 16_777_216.0;
+    (sign * fraction * 16f64.powi(exponent)) as f32
+}
+
@@ -0,0 +1,71 @@
+// Resamples one channel from slant range to ground range. Sample i is taken to be at slant
+// range (i + 0.5) * slant_range / n, output bin j at ground range (j + 0.5) * resolution, and
+// each bin is linearly interpolated from the slant samples at sqrt(ground^2 + altitude^2).
+// Output stops at the ground range of the last sample. A missing or negative altitude
+// is treated as zero, which just resamples to the new resolution
+pub fn slant_to_ground(samples: &[f32], slant_range: f64, altitude: f64, resolution: f64) -> Vec<f32> {
+    let n = samples.len();
+    if n == 0 || slant_range.is_nan() || slant_range <= 0.0 || resolution.is_nan() || resolution <= 0.0 {
+        return Vec::new();
+    }
+    let altitude = if altitude.is_finite() { altitude.max(0.0) } else { 0.0 };
+    if altitude >= slant_range {
+        return Vec::new();
+    }
+
+    let sample_spacing = slant_range / n as f64;
+    let max_ground = (slant_range * slant_range - altitude * altitude).sqrt();
+    let bins = (max_ground / resolution).floor() as usize;
+
+    (0..bins)
+        .map(|j| {
+            let ground = (j as f64 + 0.5) * resolution;
+            let slant = (ground * ground + altitude * altitude).sqrt();
+            let position = (slant / sample_spacing - 0.5).clamp(0.0, (n - 1) as f64);
+            let below = position.floor() as usize;
+            let above = (below + 1).min(n - 1);
+            let fraction = (position - below as f64) as f32;
+            samples[below] * (1.0 - fraction) + samples[above] * fraction
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each sample holds its own slant range, so a corrected bin should hold the slant range
+    // to its ground range over a flat bottom
+    fn slant_ranges(n: usize, slant_range: f64) -> Vec<f32> {
+        (0..n).map(|i| ((i as f64 + 0.5) * slant_range / n as f64) as f32).collect()
+    }
+
+    #[test]
+    fn flat_bottom_maps_ground_to_slant_range() {
+        let (slant_range, altitude, resolution) = (100.0, 20.0, 0.5);
+        let ground = slant_to_ground(&slant_ranges(1000, slant_range), slant_range, altitude, resolution);
+
+        // out to sqrt(100^2 - 20^2) = 97.98 m
+        assert_eq!(ground.len(), 195);
+        for (j, value) in ground.iter().enumerate() {
+            let ground_range = (j as f64 + 0.5) * resolution;
+            let expected = (ground_range * ground_range + altitude * altitude).sqrt();
+            assert!((*value as f64 - expected).abs() < 1e-3, "bin {}: {} not {}", j, value, expected);
+        }
+    }
+
+    #[test]
+    fn zero_altitude_just_resamples() {
+        let ground = slant_to_ground(&slant_ranges(100, 50.0), 50.0, 0.0, 1.0);
+        assert_eq!(ground.len(), 50);
+        assert!((ground[10] - 10.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn nothing_when_the_bottom_is_out_of_range() {
+        assert!(slant_to_ground(&slant_ranges(100, 50.0), 50.0, 60.0, 1.0).is_empty());
+        assert!(slant_to_ground(&[], 50.0, 10.0, 1.0).is_empty());
+        assert!(slant_to_ground(&slant_ranges(100, 50.0), 50.0, 10.0, 0.0).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use std::error::Error;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::heading::{heading_difference, mean_heading, record_heading};
+use crate::headers::XTF_HEADER_SONAR;
+use crate::lines::{find_lines, LineSettings};
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitRule {
+    // Largest output file in bytes, header included
+    MaxBytes(u64),
+    // Longest time span of a part in seconds
+    MaxDuration(f64),
+    // New part when the heading moves more than max_change degrees away from the current
+    // line's heading for at least window pings in a row. The next line starts once the
+    // heading has held steady for settle_seconds
+    Turns { max_change: f64, window: usize, settle_seconds: f64 },
+    // A part per survey line and per turn, see lines::find_lines
+    Lines(LineSettings),
+}
+
+
+// Splits the file's records into consecutive ranges of index entries. Parts only ever
+// start on a ping, other records stay with the ping before them
+pub fn plan_split(xtf: &XtfFile, rule: SplitRule) -> Vec<Range<usize>> {
+    let entries = &xtf.index.entries;
+    if entries.is_empty() {
+        return Vec::new();
+    }
+
+    let starts = match rule {
+        SplitRule::MaxBytes(max_bytes) => split_by_size(xtf, max_bytes),
+        SplitRule::MaxDuration(max_seconds) => split_by_duration(xtf, max_seconds),
+        SplitRule::Turns { max_change, window, settle_seconds } => split_at_turns(xtf, max_change, window.max(1), settle_seconds),
+        SplitRule::Lines(settings) => find_lines(xtf, &settings).iter().map(|segment| segment.first_entry).collect(),
+    };
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for next in starts.into_iter().filter(|&next| next > 0) {
+        ranges.push(start..next);
+        start = next;
+    }
+    ranges.push(start..entries.len());
+    ranges
+}
+
+
+fn split_by_size(xtf: &XtfFile, max_bytes: u64) -> Vec<usize> {
+    let entries = &xtf.index.entries;
+    let mut starts = Vec::new();
+    let mut size = xtf.header_length as u64;
+    let mut has_records = false;
+
+    for (i, entry) in entries.iter().enumerate() {
+        let end = entries.get(i + 1).map_or(xtf.data.len() as u64, |next| next.offset);
+        let length = end - entry.offset;
+
+        if has_records && entry.header_type == XTF_HEADER_SONAR && size + length > max_bytes {
+            starts.push(i);
+            size = xtf.header_length as u64;
+        }
+
+        size += length;
+        has_records = true;
+    }
+
+    starts
+}
+
+
+fn split_by_duration(xtf: &XtfFile, max_seconds: f64) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut part_start: Option<f64> = None;
+
+    for (i, entry) in xtf.index.entries.iter().enumerate() {
+        if entry.header_type != XTF_HEADER_SONAR || entry.time.is_nan() {
+            continue;
+        }
+
+        match part_start {
+            Some(start) if entry.time - start > max_seconds => {
+                starts.push(i);
+                part_start = Some(entry.time);
+            }
+            None => part_start = Some(entry.time),
+            _ => {}
+        }
+    }
+
+    starts
+}
+
+
+// Once a turn starts we wait for the heading to settle before taking the new line's heading,
+// so a single turn only gives one split however far round it goes. Settled means steady for
+// settle_seconds, a slow turn can look steady over a handful of pings
+fn split_at_turns(xtf: &XtfFile, max_change: f64, window: usize, settle_seconds: f64) -> Vec<usize> {
+    let pings: Vec<(usize, f64, f64)> = xtf
+        .index
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.header_type == XTF_HEADER_SONAR)
+        .filter_map(|(i, entry)| record_heading(&xtf.data, entry.offset as usize).map(|heading| (i, entry.time, heading)))
+        .collect();
+
+    let mut starts = Vec::new();
+    let mut line_heading: Option<f64> = None;
+    let mut off_line = 0;
+
+    for (n, &(_, _, heading)) in pings.iter().enumerate() {
+        match line_heading {
+            Some(line) => {
+                if heading_difference(line, heading).abs() > max_change {
+                    off_line += 1;
+                } else {
+                    off_line = 0;
+                }
+
+                if off_line >= window {
+                    // the turn began at the first of the off line pings
+                    starts.push(pings[n + 1 - window].0);
+                    line_heading = None;
+                    off_line = 0;
+                }
+            }
+            None => {
+                // settled once every heading over the last settle_seconds is within a third of
+                // the threshold of their mean
+                if let Some(first) = settle_start(&pings, n, settle_seconds) {
+                    let headings: Vec<f64> = pings[first..=n].iter().map(|&(_, _, h)| h).collect();
+                    if let Some(mean) = mean_heading(&headings) {
+                        if headings.iter().all(|&h| heading_difference(mean, h).abs() <= max_change / 3.0) {
+                            line_heading = Some(mean);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    starts
+}
+
+
+// Latest ping at least seconds before ping n, counting a second per ping where the times are
+// missing or don't go forwards. None if the file hasn't been going that long yet
+fn settle_start(pings: &[(usize, f64, f64)], n: usize, seconds: f64) -> Option<usize> {
+    (0..=n).rev().find(|&first| {
+        let dt = pings[n].1 - pings[first].1;
+        let elapsed = if dt.is_finite() && dt > 0.0 { dt } else { (n - first) as f64 };
+        elapsed >= seconds
+    })
+}
+
+
+// Writes each planned part to <prefix>_001.xtf, <prefix>_002.xtf, ... and returns their paths
+pub fn split<P: AsRef<Path>>(xtf: &XtfFile, rule: SplitRule, output_prefix: P) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let prefix = output_prefix.as_ref().as_os_str().to_owned();
+    let mut outputs = Vec::new();
+
+    for (part, range) in plan_split(xtf, rule).into_iter().enumerate() {
+        let mut name = prefix.clone();
+        name.push(format!("_{:03}.xtf", part + 1));
+        let path = PathBuf::from(name);
+
+        check_not_input(&path, &[&xtf.path])?;
+        let mut writer = XtfWriter::create(&path, xtf.header_bytes())?;
+        for entry in &xtf.index.entries[range] {
+            writer.write_record(xtf.record_bytes(entry)?)?;
+        }
+        writer.finish()?;
+
+        outputs.push(path);
+    }
+
+    Ok(outputs)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_data::{assert_consistent, file_header, other_record, ping_record, xtf_bytes, TempPath, TestPing};
+
+    fn pings(count: u32) -> XtfFile {
+        XtfFile::from_bytes(xtf_bytes(2, 16, &(0..count).map(TestPing::numbered).collect::<Vec<_>>())).unwrap()
+    }
+
+    fn lengths(ranges: &[Range<usize>]) -> Vec<usize> {
+        ranges.iter().map(|range| range.len()).collect()
+    }
+
+    #[test]
+    fn splits_by_size_keeping_other_records_with_their_ping() {
+        let mut bytes = file_header(2);
+        for n in 0..10 {
+            bytes.extend(ping_record(2, 16, &TestPing::numbered(n)));
+            if n == 2 {
+                bytes.extend(other_record(107, 64));
+            }
+        }
+        let source = TempPath::new("split-size.xtf");
+        fs::write(&source.0, bytes).unwrap();
+        let xtf = XtfFile::open(&source.0).unwrap();
+
+        let ping_length = ping_record(2, 16, &TestPing::numbered(0)).len() as u64;
+        let max_bytes = xtf.header_length as u64 + 3 * ping_length + 64;
+        let plan = plan_split(&xtf, SplitRule::MaxBytes(max_bytes));
+        assert_eq!(lengths(&plan), [4, 3, 3, 1]);
+
+        let prefix = TempPath::new("split-size-part");
+        let outputs: Vec<TempPath> = split(&xtf, SplitRule::MaxBytes(max_bytes), &prefix.0).unwrap().into_iter().map(TempPath).collect();
+        assert_eq!(outputs.len(), 4);
+        for output in &outputs {
+            assert!(fs::metadata(&output.0).unwrap().len() <= max_bytes);
+            assert_consistent(&XtfFile::open(&output.0).unwrap());
+        }
+        let first = XtfFile::open(&outputs[0].0).unwrap();
+        assert_eq!(first.index.entries.last().unwrap().header_type, 107);
+    }
+
+    #[test]
+    fn keeps_a_ping_bigger_than_the_limit_in_its_own_part() {
+        assert_eq!(lengths(&plan_split(&pings(3), SplitRule::MaxBytes(10))), [1, 1, 1]);
+    }
+
+    #[test]
+    fn splits_by_duration() {
+        // pings a second apart, parts may span up to 2.5 s
+        assert_eq!(lengths(&plan_split(&pings(10), SplitRule::MaxDuration(2.5))), [3, 3, 3, 1]);
+        assert_eq!(lengths(&plan_split(&pings(10), SplitRule::MaxDuration(100.0))), [10]);
+    }
+
+    // Straight north, a turn to south at turn_rate degrees a second and straight south, a ping a second
+    fn turning(turn_rate: f64) -> XtfFile {
+        let turn = (180.0 / turn_rate) as u32;
+        let headings = (0..300).map(|_| 0.0).chain((1..=turn).map(|i| i as f64 * turn_rate)).chain((0..300).map(|_| 180.0));
+        let pings: Vec<TestPing> = headings
+            .enumerate()
+            .map(|(n, heading)| TestPing { heading, ..TestPing::numbered(n as u32) })
+            .collect();
+        XtfFile::from_bytes(xtf_bytes(2, 16, &pings)).unwrap()
+    }
+
+    const TURNS: SplitRule = SplitRule::Turns { max_change: 30.0, window: 5, settle_seconds: 30.0 };
+
+    #[test]
+    fn splits_a_slow_turn_once() {
+        // settling over a few pings used to split this turn into six parts
+        let plan = plan_split(&turning(1.2), TURNS);
+
+        // more than 30 degrees off line 26 s into the turn
+        assert_eq!(plan.len(), 2, "{:?}", lengths(&plan));
+        assert_eq!(plan[1].start, 325);
+    }
+
+    #[test]
+    fn splits_a_fast_turn_once() {
+        assert_eq!(plan_split(&turning(10.0), TURNS).len(), 2);
+    }
+
+    #[test]
+    fn leaves_a_straight_line_alone() {
+        assert_eq!(plan_split(&pings(100), TURNS).len(), 1);
+    }
+}
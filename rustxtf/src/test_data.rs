@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use crate::headers::{
+    get_number, write_field, CHAN_INFO_LENGTH, FILE_HEADER_BLOCK_LENGTH, FILE_HEADER_LENGTH, MAGIC_NUMBER,
+    PING_CHAN_HEADER_LENGTH, PING_HEADER_LENGTH, XTF_CHAN_INFO, XTF_FILE_HEADER, XTF_PING_CHAN_HEADER, XTF_PING_HEADER,
+};
+use crate::record::Record;
+use crate::time::write_ping_time;
+use crate::validate::validate;
+use crate::xtf_file::XtfFile;
+
+// Small XTF files for the unit tests, built through the header tables so the layout comes
+// from the same place the reader gets it. Channels alternate port and starboard, 2 byte
+// samples, 50 m slant range, positions in longitude and latitude
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct TestPing {
+    pub ping_number: u32,
+    pub time: f64,
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64, // SensorHeading, ShipGyro is left at 0
+}
+
+impl TestPing {
+    // Pings a second apart heading north from 1E 54N
+    pub fn numbered(ping_number: u32) -> TestPing {
+        let i = ping_number as f64;
+        TestPing { ping_number, time: 1_714_521_600.0 + i, x: 1.0, y: 54.0 + i * 1e-5, heading: 0.0 }
+    }
+}
+
+
+pub fn file_header(channels: u16) -> Vec<u8> {
+    let mut bytes = vec![0u8; FILE_HEADER_BLOCK_LENGTH];
+    let field = |bytes: &mut Vec<u8>, name: &str, value: f64| write_field(XTF_FILE_HEADER, bytes, 0, name, value).unwrap();
+    field(&mut bytes, "FileFormat", 123.0);
+    field(&mut bytes, "NavUnits", 3.0);
+    field(&mut bytes, "NumberOfSonarChannels", channels as f64);
+
+    for channel in 0..channels as usize {
+        let base = FILE_HEADER_LENGTH + channel * CHAN_INFO_LENGTH;
+        write_field(XTF_CHAN_INFO, &mut bytes, base, "TypeOfChannel", (1 + channel % 2) as f64).unwrap();
+        write_field(XTF_CHAN_INFO, &mut bytes, base, "SubChannelNumber", channel as f64).unwrap();
+        write_field(XTF_CHAN_INFO, &mut bytes, base, "BytesPerSample", 2.0).unwrap();
+    }
+    bytes
+}
+
+
+// A sonar record with every channel, sample values counting up from the channel number
+pub fn ping_record(channels: u16, samples: usize, ping: &TestPing) -> Vec<u8> {
+    let length = PING_HEADER_LENGTH + channels as usize * (PING_CHAN_HEADER_LENGTH + samples * 2);
+    let mut bytes = vec![0u8; PING_HEADER_LENGTH];
+    let mut field = |name: &str, value: f64| write_field(XTF_PING_HEADER, &mut bytes, 0, name, value).unwrap();
+    field("MagicNumber", MAGIC_NUMBER as f64);
+    field("NumChansToFollow", channels as f64);
+    field("NumBytesThisRecord", length as f64);
+    field("PingNumber", ping.ping_number as f64);
+    field("ShipXcoordinate", ping.x);
+    field("ShipYcoordinate", ping.y);
+    field("SensorXcoordinate", ping.x);
+    field("SensorYcoordinate", ping.y);
+    field("SensorHeading", ping.heading);
+    field("SensorPrimaryAltitude", 10.0);
+    write_ping_time(&mut bytes, ping.time).unwrap();
+
+    for channel in 0..channels {
+        let mut header = vec![0u8; PING_CHAN_HEADER_LENGTH];
+        write_field(XTF_PING_CHAN_HEADER, &mut header, 0, "ChannelNumber", channel as f64).unwrap();
+        write_field(XTF_PING_CHAN_HEADER, &mut header, 0, "SlantRange", 50.0).unwrap();
+        write_field(XTF_PING_CHAN_HEADER, &mut header, 0, "SecondsPerPing", 1.0).unwrap();
+        write_field(XTF_PING_CHAN_HEADER, &mut header, 0, "NumSamples", samples as f64).unwrap();
+        bytes.extend(header);
+        bytes.extend((0..samples).flat_map(|i| (channel + i as u16).to_le_bytes()));
+    }
+    bytes
+}
+
+
+// Overwrites one channel's samples in a ping_record with `samples` per channel
+pub fn set_samples(record: &mut [u8], samples: usize, channel: usize, values: &[u16]) {
+    let start = PING_HEADER_LENGTH + channel * (PING_CHAN_HEADER_LENGTH + samples * 2) + PING_CHAN_HEADER_LENGTH;
+    let bytes: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+    record[start..start + bytes.len()].copy_from_slice(&bytes);
+}
+
+
+// A record of a type the reader doesn't interpret, filled with a recognisable pattern
+pub fn other_record(header_type: u8, length: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = (0..length).map(|i| i as u8).collect();
+    bytes[0..2].copy_from_slice(&MAGIC_NUMBER.to_le_bytes());
+    bytes[2] = header_type;
+    bytes[10..14].copy_from_slice(&(length as u32).to_le_bytes());
+    bytes
+}
+
+
+pub fn xtf_bytes(channels: u16, samples: usize, pings: &[TestPing]) -> Vec<u8> {
+    let mut bytes = file_header(channels);
+    for ping in pings {
+        bytes.extend(ping_record(channels, samples, ping));
+    }
+    bytes
+}
+
+
+// A path in the temp directory no other test (or test run) is using, removed on drop
+pub struct TempPath(pub PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> TempPath {
+        TempPath(std::env::temp_dir().join(format!("rustxtf-{}-{}", process::id(), name)))
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+
+// Asserts every record's NumBytesThisRecord matches what it holds and the records exactly fill
+// the file after the header, so the file re-parses the same way anything else would read it
+pub fn assert_consistent(xtf: &XtfFile) {
+    let mut end = xtf.header_length;
+    for entry in &xtf.index.entries {
+        assert_eq!(entry.offset as usize, end, "record doesn't start where the one before ended");
+        let length = xtf.record_bytes(entry).unwrap().len();
+        if let Record::Sonar(ping) = xtf.record(entry).unwrap() {
+            let held: usize = ping.channels.iter().map(|c| PING_CHAN_HEADER_LENGTH + c.num_samples * c.bytes_per_sample).sum();
+            assert_eq!(length, PING_HEADER_LENGTH + held, "ping {} length", entry.ping_number);
+            assert_eq!(get_number(&ping.header, "NumChansToFollow"), Some(ping.channels.len() as f64));
+        }
+        end += length;
+    }
+    assert_eq!(end, xtf.data.len(), "records don't fill the file");
+    assert!(validate(xtf).is_empty(), "{:?}", validate(xtf));
+}
+
+
+// Tags of a little endian TIFF's first IFD as (field type, count, value bytes), following the
+// offset for values that don't fit in the entry
+pub fn tiff_tags(bytes: &[u8]) -> BTreeMap<u16, (u16, u32, Vec<u8>)> {
+    let u16_at = |offset: usize| u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap());
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    assert_eq!(&bytes[..4], b"II*\0");
+
+    let ifd = u32_at(4) as usize;
+    (0..u16_at(ifd) as usize)
+        .map(|i| {
+            let entry = ifd + 2 + i * 12;
+            let (field_type, count) = (u16_at(entry + 2), u32_at(entry + 4));
+            let size = count as usize * match field_type {
+                1 | 2 => 1,
+                3 => 2,
+                4 => 4,
+                _ => 8,
+            };
+            let start = if size <= 4 { entry + 8 } else { u32_at(entry + 8) as usize };
+            (u16_at(entry), (field_type, count, bytes[start..start + size].to_vec()))
+        })
+        .collect()
+}
@@ -0,0 +1,345 @@
+use std::error::Error;
+
+use crate::headers::{get_number, read_field, write_field, HeaderMap, XTF_PING_HEADER};
+
+// Ping times are handled as seconds since the unix epoch (UTC) so they can be compared and subtracted
+
+
+// Days since 1970-01-01 for a proleptic gregorian date (Howard Hinnant's algorithm)
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+
+// Inverse of days_from_civil, returns (year, month, day)
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 };
+    (year, month, day)
+}
+
+
+pub fn timestamp(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64, hseconds: i64) -> f64 {
+    let days = days_from_civil(year, month, day);
+    (days * 86400 + hour * 3600 + minute * 60 + second) as f64 + hseconds as f64 / 100.0
+}
+
+
+// Time of a parsed ping header, None if the date fields are zeroed or nonsense
+pub fn ping_time(header: &HeaderMap) -> Option<f64> {
+    let field = |name: &str| get_number(header, name).map(|v| v as i64);
+    checked_timestamp(
+        field("Year")?,
+        field("Month")?,
+        field("Day")?,
+        field("Hour")?,
+        field("Minute")?,
+        field("Second")?,
+        field("HSeconds")?,
+    )
+}
+
+
+// Same as ping_time but straight from the record bytes, used when indexing
+pub fn record_time(data: &[u8], offset: usize) -> Option<f64> {
+    let field = |name: &str| read_field(XTF_PING_HEADER, data, offset, name).and_then(|v| v.as_f64()).map(|v| v as i64);
+    checked_timestamp(
+        field("Year")?,
+        field("Month")?,
+        field("Day")?,
+        field("Hour")?,
+        field("Minute")?,
+        field("Second")?,
+        field("HSeconds")?,
+    )
+}
+
+
+fn checked_timestamp(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64, hseconds: i64) -> Option<f64> {
+    if year == 0 || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 || hseconds > 99 {
+        return None;
+    }
+    Some(timestamp(year, month, day, hour, minute, second, hseconds))
+}
+
+
+// ISO 8601 with hundredths, e.g. 2024-05-01T12:00:03.25Z
+pub fn format_time(time: f64) -> String {
+    let hundredths = (time * 100.0).round() as i64;
+    let seconds = hundredths.div_euclid(100);
+    let days = seconds.div_euclid(86400);
+    let of_day = seconds.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:02}Z",
+        year,
+        month,
+        day,
+        of_day / 3600,
+        (of_day / 60) % 60,
+        of_day % 60,
+        hundredths.rem_euclid(100)
+    )
+}
+
+
+// Sets the date and time fields (JulianDay included) of a ping record to time, to the nearest
+// hundredth of a second
+pub fn write_ping_time(bytes: &mut [u8], time: f64) -> Result<(), Box<dyn Error>> {
+    let hundredths = (time * 100.0).round() as i64;
+    let seconds = hundredths.div_euclid(100);
+    let days = seconds.div_euclid(86400);
+    let of_day = seconds.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    let fields = [
+        ("Year", year),
+        ("Month", month),
+        ("Day", day),
+        ("Hour", of_day / 3600),
+        ("Minute", (of_day / 60) % 60),
+        ("Second", of_day % 60),
+        ("HSeconds", hundredths.rem_euclid(100)),
+        ("JulianDay", days - days_from_civil(year, 1, 1) + 1),
+    ];
+    for (name, value) in fields {
+        write_field(XTF_PING_HEADER, bytes, 0, name, value as f64)?;
+    }
+    Ok(())
+}
+
+
+// Accepts either plain seconds since the epoch or YYYY-MM-DDTHH:MM:SS[.ss][Z]
+pub fn parse_time(text: &str) -> Option<f64> {
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Some(seconds);
+    }
+
+    let text = text.trim_end_matches('Z');
+    let (date, clock) = text.split_once(['T', ' '])?;
+
+    let mut date_parts = date.split('-').map(|p| p.parse::<i64>());
+    let year = date_parts.next()?.ok()?;
+    let month = date_parts.next()?.ok()?;
+    let day = date_parts.next()?.ok()?;
+
+    let mut clock_parts = clock.split(':');
+    let hour = clock_parts.next()?.parse::<i64>().ok()?;
+    let minute = clock_parts.next()?.parse::<i64>().ok()?;
+    let second = clock_parts.next().unwrap_or("0").parse::<f64>().ok()?;
+
+    Some(timestamp(year, month, day, hour, minute, 0, 0) + second)
+}
+
+
+// Where each ping is in the sequence: ping numbers when they only go up, so dropped pings keep
+// their place, otherwise just the count
+pub fn ping_positions(ping_numbers: &[u32]) -> Vec<f64> {
+    if ping_numbers.windows(2).all(|pair| pair[1] > pair[0]) {
+        ping_numbers.iter().map(|&number| number as f64).collect()
+    } else {
+        (0..ping_numbers.len()).map(|i| i as f64).collect()
+    }
+}
+
+
+// Middle value, the upper one of an even count
+pub fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    Some(values[values.len() / 2])
+}
+
+
+// Pings where the source time changes, less any that are out of line with the changes either
+// side of them: a time outside the two around it when those two are in order, or at the ends
+// past both of the two next to it. A one ping glitch forward or back is dropped this way
+// without taking the good times after it down too
+fn time_changes(source: &[Option<f64>]) -> Vec<usize> {
+    let mut changes: Vec<usize> = Vec::new();
+    for (i, time) in source.iter().enumerate() {
+        let Some(time) = *time else { continue };
+        if changes.last().is_none_or(|&last| source[last] != Some(time)) {
+            changes.push(i);
+        }
+    }
+
+    let time = |k: usize| source[changes[k]].unwrap_or(f64::NAN);
+    let n = changes.len();
+    let glitch = |k: usize| {
+        if k > 0 && k + 1 < n {
+            let (before, after) = (time(k - 1), time(k + 1));
+            before <= after && (time(k) < before || time(k) > after)
+        } else if k == 0 && n >= 3 {
+            time(0) > time(1) && time(0) > time(2)
+        } else if k == n - 1 && n >= 3 {
+            time(k) < time(k - 1) && time(k) < time(k - 2)
+        } else {
+            false
+        }
+    };
+    (0..n).filter(|&k| !glitch(k)).map(|k| changes[k]).collect()
+}
+
+
+// Fills in every ping from the anchors, the pings where the source time moves forward.
+// Pings before the first anchor and after the last carry on at the typical rate
+pub fn spread_between_anchors(source: &[Option<f64>], positions: &[f64]) -> Vec<Option<f64>> {
+    let mut anchors: Vec<usize> = Vec::new();
+    for i in time_changes(source) {
+        match anchors.last() {
+            Some(&last) if source[last] >= source[i] => {}
+            _ => anchors.push(i),
+        }
+    }
+
+    let time = |i: usize| source[i].unwrap_or(f64::NAN);
+    let mut rates: Vec<f64> = anchors
+        .windows(2)
+        .map(|pair| (time(pair[1]) - time(pair[0])) / (positions[pair[1]] - positions[pair[0]]))
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+        .collect();
+    let rate = median(&mut rates);
+
+    let mut times = vec![None; source.len()];
+    let (Some(&first), Some(&last)) = (anchors.first(), anchors.last()) else { return times };
+    for pair in anchors.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        for (i, slot) in times.iter_mut().enumerate().take(b).skip(a) {
+            let fraction = (positions[i] - positions[a]) / (positions[b] - positions[a]);
+            *slot = Some(time(a) + fraction * (time(b) - time(a)));
+        }
+    }
+    times[last] = Some(time(last));
+    if let Some(rate) = rate {
+        for (i, slot) in times.iter_mut().enumerate() {
+            if i < first || i > last {
+                let anchor = if i < first { first } else { last };
+                *slot = Some(time(anchor) + (positions[i] - positions[anchor]) * rate);
+            }
+        }
+    }
+    times
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::PING_HEADER_LENGTH;
+
+    fn close(times: &[Option<f64>], expected: &[f64]) -> bool {
+        times.len() == expected.len() && times.iter().zip(expected).all(|(time, expected)| time.is_some_and(|time| (time - expected).abs() < 1e-9))
+    }
+
+    // Fields write_ping_time set for time: year, month, day, hour, minute, second, hundredths, julian day
+    fn written(time: f64) -> (Vec<i64>, Option<f64>) {
+        let mut bytes = vec![0u8; PING_HEADER_LENGTH];
+        write_ping_time(&mut bytes, time).unwrap();
+        let fields = ["Year", "Month", "Day", "Hour", "Minute", "Second", "HSeconds", "JulianDay"]
+            .iter()
+            .map(|name| read_field(XTF_PING_HEADER, &bytes, 0, name).and_then(|v| v.as_f64()).unwrap() as i64)
+            .collect();
+        (fields, record_time(&bytes, 0))
+    }
+
+    #[test]
+    fn round_trips_around_midnight_and_leap_days() {
+        let cases = [
+            ("2023-12-31T23:59:59.99", vec![2023, 12, 31, 23, 59, 59, 99, 365]),
+            ("2024-01-01T00:00:00.00", vec![2024, 1, 1, 0, 0, 0, 0, 1]),
+            ("2024-02-29T12:30:15.25", vec![2024, 2, 29, 12, 30, 15, 25, 60]),
+            ("2024-03-01T00:00:00.01", vec![2024, 3, 1, 0, 0, 0, 1, 61]),
+            ("2024-12-31T23:59:59.50", vec![2024, 12, 31, 23, 59, 59, 50, 366]),
+            ("2000-02-29T00:00:00.00", vec![2000, 2, 29, 0, 0, 0, 0, 60]),
+            ("2100-03-01T00:00:00.00", vec![2100, 3, 1, 0, 0, 0, 0, 60]),
+        ];
+        for (text, expected) in cases {
+            let time = parse_time(text).unwrap();
+            let (fields, read_back) = written(time);
+            assert_eq!(fields, expected, "{}", text);
+            assert!((read_back.unwrap() - time).abs() < 1e-6, "{} read back as {:?}", text, read_back.map(format_time));
+        }
+    }
+
+    #[test]
+    fn rounding_carries_into_the_next_day() {
+        let (fields, _) = written(parse_time("2024-02-29T23:59:59.996").unwrap());
+        assert_eq!(fields, vec![2024, 3, 1, 0, 0, 0, 0, 61]);
+    }
+
+    #[test]
+    fn spreads_pings_between_anchors() {
+        // a one second clock ticking over every fourth ping of a quarter second sonar
+        let source: Vec<Option<f64>> = (0..12).map(|i| Some((i / 4) as f64)).collect();
+        let positions: Vec<f64> = (0..12).map(|i| i as f64).collect();
+        let expected: Vec<f64> = (0..12).map(|i| i as f64 * 0.25).collect();
+        assert!(close(&spread_between_anchors(&source, &positions), &expected));
+    }
+
+    #[test]
+    fn keeps_dropped_pings_in_their_place() {
+        // ping 3 missing, so the time step from 2 to 4 is twice the others
+        let source = [Some(10.0), None, None, Some(14.0), None];
+        let positions = ping_positions(&[0, 1, 2, 4, 5]);
+        assert_eq!(positions, vec![0.0, 1.0, 2.0, 4.0, 5.0]);
+        assert!(close(&spread_between_anchors(&source, &positions), &[10.0, 11.0, 12.0, 14.0, 15.0]));
+    }
+
+    #[test]
+    fn carries_on_past_the_anchors_at_the_typical_rate() {
+        let source = [None, Some(100.0), None, Some(102.0), None, Some(104.0), None];
+        let positions: Vec<f64> = (0..7).map(|i| i as f64).collect();
+        assert!(close(&spread_between_anchors(&source, &positions), &[99.0, 100.0, 101.0, 102.0, 103.0, 104.0, 105.0]));
+    }
+
+    #[test]
+    fn ignores_times_that_go_backwards() {
+        // the 99 is a clock glitch, not an anchor
+        let source = [Some(100.0), Some(99.0), Some(102.0)];
+        let positions = [0.0, 1.0, 2.0];
+        assert!(close(&spread_between_anchors(&source, &positions), &[100.0, 101.0, 102.0]));
+    }
+
+    #[test]
+    fn a_glitch_forward_doesnt_hold_back_the_times_after_it() {
+        // the one second clock reads 5000 for a ping
+        let mut source: Vec<Option<f64>> = (0..16).map(|i| Some((i / 4) as f64)).collect();
+        source[5] = Some(5000.0);
+        let positions: Vec<f64> = (0..16).map(|i| i as f64).collect();
+        let expected: Vec<f64> = (0..16).map(|i| i as f64 * 0.25).collect();
+        assert!(close(&spread_between_anchors(&source, &positions), &expected));
+
+        // at the start as well
+        let source = [Some(5000.0), Some(1.0), Some(2.0), Some(3.0)];
+        assert!(close(&spread_between_anchors(&source, &[0.0, 1.0, 2.0, 3.0]), &[0.0, 1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn nothing_to_spread_without_anchors() {
+        assert_eq!(spread_between_anchors(&[None, None], &[0.0, 1.0]), vec![None, None]);
+        // one anchor, no rate to carry on at
+        assert_eq!(spread_between_anchors(&[None, Some(5.0), None], &[0.0, 1.0, 2.0]), vec![None, Some(5.0), None]);
+    }
+
+    #[test]
+    fn ping_positions_fall_back_to_the_count() {
+        assert_eq!(ping_positions(&[5, 3, 9]), vec![0.0, 1.0, 2.0]);
+    }
+}
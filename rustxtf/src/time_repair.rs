@@ -0,0 +1,294 @@
+use std::error::Error;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde_derive::Serialize;
+
+use crate::headers::{get_number, read_field, XTF_HEADER_POS_RAW_NAVIGATION, XTF_HEADER_SONAR, XTF_POS_RAW_NAVIGATION};
+use crate::index::IndexEntry;
+use crate::time::{median, ping_positions, spread_between_anchors, timestamp, write_ping_time};
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+// Rebuilds ping times from a source other than the ping's own date and time, for files where
+// the sonar clock jumped or the date fields were never filled in. The clock sources only give
+// a time of day and most of them tick slower than the sonar pings, so the pings where the
+// source time moves on are taken as anchors and the pings between them are spread out by ping
+// number. Midnight is crossed when the time of day drops by more than twelve hours
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TimeSource {
+    // FixTimeHour, FixTimeMinute, FixTimeSecond, FixTimeHsecond on the reference date
+    NavFix,
+    // ComputerClockHour, ... on the reference date
+    ComputerClock,
+    // times of the raw navigation (type 107) records in among the pings, full dates so the
+    // reference date isn't used
+    PositionRecords,
+    // a start time plus SecondsPerPing (or a given interval) for each ping number
+    PingInterval,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RepairSettings {
+    pub source: TimeSource,
+    pub date: Option<f64>,     // midnight of the day clock times start on, else the first ping's date
+    pub start: Option<f64>,    // PingInterval: time of the first ping, else from the first good time
+    pub interval: Option<f64>, // PingInterval: seconds per ping, else SecondsPerPing or the median
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TimeChange {
+    pub ping_number: u32,
+    pub old: Option<f64>,
+    pub new: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RepairReport {
+    pub settings: RepairSettings,
+    pub pings: usize,
+    pub changed: usize,
+    pub unresolved: usize, // pings the source couldn't give a time, left as they were
+    pub changes: Vec<TimeChange>,
+}
+
+
+// "navfix", "clock", "position" or "interval"
+impl FromStr for TimeSource {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<TimeSource, String> {
+        match text.to_lowercase().as_str() {
+            "navfix" => Ok(TimeSource::NavFix),
+            "clock" => Ok(TimeSource::ComputerClock),
+            "position" => Ok(TimeSource::PositionRecords),
+            "interval" => Ok(TimeSource::PingInterval),
+            _ => Err(format!("unknown time source {}, expected navfix, clock, position or interval", text)),
+        }
+    }
+}
+
+
+// Seconds per step in ping position, from consecutive pings whose times both look right
+fn median_interval(times: &[Option<f64>], positions: &[f64]) -> Option<f64> {
+    let mut intervals: Vec<f64> = (1..times.len())
+        .filter_map(|i| {
+            let (a, b) = (times[i - 1]?, times[i]?);
+            let steps = positions[i] - positions[i - 1];
+            (b > a && steps > 0.0).then(|| (b - a) / steps)
+        })
+        .collect();
+    median(&mut intervals)
+}
+
+
+// Seconds into the day from four clock fields, None when they're out of range or all zero
+fn time_of_day(xtf: &XtfFile, entry: &IndexEntry, fields: [&str; 4]) -> Option<f64> {
+    let values: Vec<f64> = fields.iter().map(|name| xtf.ping_field(entry, name)).collect::<Option<_>>()?;
+    let (hour, minute, second, hundredths) = (values[0], values[1], values[2], values[3]);
+    if hour > 23.0 || minute > 59.0 || second > 60.0 || hundredths > 99.0 || values.iter().all(|v| *v == 0.0) {
+        return None;
+    }
+    Some(hour * 3600.0 + minute * 60.0 + second + hundredths / 100.0)
+}
+
+
+// Clock times with the day added in, stepping on a day whenever the clock goes round
+fn on_date(times_of_day: &[Option<f64>], date: f64) -> Vec<Option<f64>> {
+    let mut day = date;
+    let mut previous: Option<f64> = None;
+    times_of_day
+        .iter()
+        .map(|time| {
+            let time = (*time)?;
+            if previous.is_some_and(|previous| time < previous - 43200.0) {
+                day += 86400.0;
+            }
+            previous = Some(time);
+            Some(day + time)
+        })
+        .collect()
+}
+
+
+// Time of the latest raw navigation record before each ping
+fn position_record_times(xtf: &XtfFile) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+    let mut times = Vec::new();
+    let mut latest: Option<f64> = None;
+    let mut records = 0;
+
+    for entry in &xtf.index.entries {
+        match entry.header_type {
+            XTF_HEADER_SONAR => times.push(latest),
+            XTF_HEADER_POS_RAW_NAVIGATION => {
+                records += 1;
+                let field = |name: &str| {
+                    read_field(XTF_POS_RAW_NAVIGATION, &xtf.data, entry.offset as usize, name).and_then(|v| v.as_f64()).map(|v| v as i64)
+                };
+                let parts = [field("Year"), field("Month"), field("Day"), field("Hour"), field("Minute"), field("Second"), field("MicroSeconds")];
+                if let [Some(year), Some(month), Some(day), Some(hour), Some(minute), Some(second), Some(ten_thousandths)] = parts {
+                    if year > 0 && (1..=12).contains(&month) && (1..=31).contains(&day) && hour < 24 && minute < 60 && second <= 60 {
+                        latest = Some(timestamp(year, month, day, hour, minute, second, 0) + ten_thousandths as f64 / 10000.0);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if records == 0 {
+        return Err("no raw navigation (type 107) records in the file to take times from".into());
+    }
+    Ok(times)
+}
+
+
+// New time for every ping in file order, None where the source has nothing to go on
+pub fn rebuild_times(xtf: &XtfFile, settings: &RepairSettings) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+    let pings: Vec<&IndexEntry> = xtf.index.pings().collect();
+    let positions = ping_positions(&pings.iter().map(|entry| entry.ping_number).collect::<Vec<_>>());
+    let current: Vec<Option<f64>> = pings.iter().map(|entry| Some(entry.time).filter(|time| time.is_finite())).collect();
+
+    let date = || -> Result<f64, Box<dyn Error>> {
+        match settings.date {
+            Some(date) => Ok(date),
+            None => current
+                .iter()
+                .flatten()
+                .next()
+                .map(|time| (time / 86400.0).floor() * 86400.0)
+                .ok_or_else(|| "no ping has a valid date to put the clock times on, give the date".into()),
+        }
+    };
+
+    let source = match settings.source {
+        TimeSource::NavFix => {
+            let fields = ["FixTimeHour", "FixTimeMinute", "FixTimeSecond", "FixTimeHsecond"];
+            on_date(&pings.iter().map(|entry| time_of_day(xtf, entry, fields)).collect::<Vec<_>>(), date()?)
+        }
+        TimeSource::ComputerClock => {
+            let fields = ["ComputerClockHour", "ComputerClockMinute", "ComputerClockSecond", "ComputerClockHsec"];
+            on_date(&pings.iter().map(|entry| time_of_day(xtf, entry, fields)).collect::<Vec<_>>(), date()?)
+        }
+        TimeSource::PositionRecords => position_record_times(xtf)?,
+        TimeSource::PingInterval => return interval_times(xtf, &pings, &positions, &current, settings),
+    };
+
+    Ok(spread_between_anchors(&source, &positions))
+}
+
+
+fn interval_times(
+    xtf: &XtfFile,
+    pings: &[&IndexEntry],
+    positions: &[f64],
+    current: &[Option<f64>],
+    settings: &RepairSettings,
+) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+    let median = median_interval(current, positions);
+    let mut elapsed = Vec::with_capacity(pings.len());
+    let mut total = 0.0;
+
+    for (i, entry) in pings.iter().enumerate() {
+        if i > 0 {
+            let interval = match settings.interval {
+                Some(interval) => interval,
+                None => {
+                    let ping = xtf.ping(entry)?;
+                    let seconds_per_ping = ping
+                        .channels
+                        .iter()
+                        .find_map(|channel| get_number(&channel.header, "SecondsPerPing").filter(|seconds| *seconds > 0.0));
+                    seconds_per_ping.or(median).ok_or("no SecondsPerPing or good ping times to get the ping interval from, give it")?
+                }
+            };
+            total += (positions[i] - positions[i - 1]) * interval;
+        }
+        elapsed.push(total);
+    }
+
+    // from the start time, or lined up with the first ping that has a good time
+    let start = match settings.start {
+        Some(start) => start,
+        None => {
+            let first = current.iter().position(|time| time.is_some()).ok_or("no ping has a valid time to start from, give the start time")?;
+            current[first].unwrap_or(0.0) - elapsed[first]
+        }
+    };
+    Ok(elapsed.into_iter().map(|elapsed| Some(start + elapsed)).collect())
+}
+
+
+// Pings whose time moves by at least a hundredth of a second, the resolution of the header
+pub fn repair_report(xtf: &XtfFile, times: &[Option<f64>], settings: &RepairSettings) -> RepairReport {
+    let changes: Vec<TimeChange> = xtf
+        .index
+        .pings()
+        .zip(times)
+        .filter_map(|(entry, time)| {
+            let new = (*time)?;
+            let old = Some(entry.time).filter(|time| time.is_finite());
+            let moved = old.is_none_or(|old| (new - old).abs() >= 0.005);
+            moved.then_some(TimeChange { ping_number: entry.ping_number, old, new })
+        })
+        .collect();
+
+    RepairReport {
+        settings: *settings,
+        pings: times.len(),
+        changed: changes.len(),
+        unresolved: times.iter().filter(|time| time.is_none()).count(),
+        changes,
+    }
+}
+
+
+// Copies the file with each ping's date and time set from times, in ping order. Pings without
+// a new time are copied as they are. Returns records written
+pub fn write_times<P: AsRef<Path>>(xtf: &XtfFile, times: &[Option<f64>], output: P) -> Result<usize, Box<dyn Error>> {
+    check_not_input(&output, &[&xtf.path])?;
+    let mut writer = XtfWriter::create(output, xtf.header_bytes())?;
+    let mut times = times.iter();
+
+    for entry in &xtf.index.entries {
+        let bytes = xtf.record_bytes(entry)?;
+        if entry.header_type != XTF_HEADER_SONAR {
+            writer.write_record(bytes)?;
+            continue;
+        }
+
+        match times.next().copied().flatten() {
+            Some(time) => {
+                let mut bytes = bytes.to_vec();
+                write_ping_time(&mut bytes, time)?;
+                writer.write_record(&bytes)?;
+            }
+            None => writer.write_record(bytes)?,
+        }
+    }
+
+    writer.finish()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_times_step_on_a_day_at_midnight() {
+        let times = on_date(&[Some(86399.0), None, Some(1.0), Some(0.5), Some(3600.0)], 0.0);
+        // half a second back is a glitch, not another day
+        assert_eq!(times, vec![Some(86399.0), None, Some(86401.0), Some(86400.5), Some(90000.0)]);
+    }
+
+    #[test]
+    fn reads_time_sources() {
+        assert_eq!("NavFix".parse::<TimeSource>(), Ok(TimeSource::NavFix));
+        assert_eq!("interval".parse::<TimeSource>(), Ok(TimeSource::PingInterval));
+        assert!("gps".parse::<TimeSource>().is_err());
+    }
+}
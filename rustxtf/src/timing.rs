@@ -0,0 +1,319 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde_derive::Serialize;
+
+use crate::headers::get_number;
+use crate::xtf_file::XtfFile;
+
+// Ping timing checks: times that are missing, stand still, go backwards or jump, ping numbers
+// that skip (dropped pings), repeat or go backwards, and changes in the sonar's ping rate
+// (SecondsPerPing) and range settings part way through the file. A ping that can't be read
+// is reported as an issue too, its number and time still come from the index
+
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum TimingIssueKind {
+    NoTime,
+    // longer than gap_factor times the expected ping interval since the ping before
+    TimeGap { seconds: f64, expected: f64 },
+    TimeBackwards { seconds: f64 },
+    RepeatedTime,
+    DroppedPings { missing: u32 },
+    DuplicatePingNumber,
+    PingNumberBackwards { previous: u32 },
+    PingRateChange { from: f64, to: f64 }, // SecondsPerPing
+    RangeChange { channel: u16, from: f64, to: f64 }, // SlantRange
+    // the ping record couldn't be parsed, so its settings weren't checked
+    Unreadable { error: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimingIssue {
+    pub ping_number: u32,
+    pub time: f64,
+    pub issue: TimingIssueKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimingReport {
+    pub pings: usize,
+    pub start_time: Option<f64>,
+    pub end_time: Option<f64>,
+    pub median_interval: Option<f64>, // seconds between pings
+    pub dropped_pings: u64,
+    pub issues: Vec<TimingIssue>,
+}
+
+
+impl TimingIssueKind {
+    // Variant name, for counting issues by kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimingIssueKind::NoTime => "NoTime",
+            TimingIssueKind::TimeGap { .. } => "TimeGap",
+            TimingIssueKind::TimeBackwards { .. } => "TimeBackwards",
+            TimingIssueKind::RepeatedTime => "RepeatedTime",
+            TimingIssueKind::DroppedPings { .. } => "DroppedPings",
+            TimingIssueKind::DuplicatePingNumber => "DuplicatePingNumber",
+            TimingIssueKind::PingNumberBackwards { .. } => "PingNumberBackwards",
+            TimingIssueKind::PingRateChange { .. } => "PingRateChange",
+            TimingIssueKind::RangeChange { .. } => "RangeChange",
+            TimingIssueKind::Unreadable { .. } => "Unreadable",
+        }
+    }
+}
+
+
+impl fmt::Display for TimingIssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimingIssueKind::NoTime => write!(f, "no valid time"),
+            TimingIssueKind::TimeGap { seconds, expected } => write!(f, "{:.2} s gap, expected {:.2} s", seconds, expected),
+            TimingIssueKind::TimeBackwards { seconds } => write!(f, "time goes back {:.2} s", seconds),
+            TimingIssueKind::RepeatedTime => write!(f, "same time as the ping before"),
+            TimingIssueKind::DroppedPings { missing } => write!(f, "{} ping{} missing", missing, if missing == 1 { "" } else { "s" }),
+            TimingIssueKind::DuplicatePingNumber => write!(f, "ping number seen before"),
+            TimingIssueKind::PingNumberBackwards { previous } => write!(f, "ping number goes back from {}", previous),
+            TimingIssueKind::PingRateChange { from, to } => write!(f, "SecondsPerPing changes from {} to {}", from, to),
+            TimingIssueKind::RangeChange { channel, from, to } => write!(f, "channel {} range changes from {} m to {} m", channel, from, to),
+            TimingIssueKind::Unreadable { ref error } => write!(f, "couldn't read the ping: {}", error),
+        }
+    }
+}
+
+
+// Median of the positive intervals between consecutive timed pings
+fn median_interval(times: &[f64]) -> Option<f64> {
+    let mut intervals: Vec<f64> = times.windows(2).map(|pair| pair[1] - pair[0]).filter(|dt| *dt > 0.0).collect();
+    if intervals.is_empty() {
+        return None;
+    }
+    intervals.sort_by(|a, b| a.total_cmp(b));
+    Some(intervals[intervals.len() / 2])
+}
+
+
+// Checks every ping in file order. A gap is an interval over gap_factor times the ping's
+// SecondsPerPing, or the file's median interval where the sonar doesn't fill that in
+pub fn check_timing(xtf: &XtfFile, gap_factor: f64) -> TimingReport {
+    let times: Vec<f64> = xtf.index.pings().map(|entry| entry.time).filter(|time| time.is_finite()).collect();
+    let median = median_interval(&times);
+
+    let mut issues = Vec::new();
+    let mut dropped_pings = 0;
+    let mut seen = HashSet::new();
+    let mut previous_number: Option<u32> = None;
+    let mut previous_time: Option<f64> = None;
+    let mut seconds_per_ping: Option<f64> = None;
+    let mut ranges: Vec<Option<f64>> = vec![None; xtf.channel_infos.len()];
+
+    for entry in xtf.index.pings() {
+        let mut issue = |issue: TimingIssueKind| issues.push(TimingIssue { ping_number: entry.ping_number, time: entry.time, issue });
+
+        // ping numbers, a repeated number doesn't move on the one the next ping should follow
+        if !seen.insert(entry.ping_number) {
+            issue(TimingIssueKind::DuplicatePingNumber);
+        } else {
+            if let Some(previous) = previous_number {
+                // a counter wrapping round past u32::MAX shows up as going backwards
+                match previous.checked_add(1) {
+                    Some(next) if entry.ping_number >= next => {
+                        let missing = entry.ping_number - next;
+                        if missing > 0 {
+                            dropped_pings += missing as u64;
+                            issue(TimingIssueKind::DroppedPings { missing });
+                        }
+                    }
+                    _ => issue(TimingIssueKind::PingNumberBackwards { previous }),
+                }
+            }
+            previous_number = Some(entry.ping_number);
+        }
+
+        // settings, taken from the first channel that has them
+        let ping = match xtf.ping(entry) {
+            Ok(ping) => Some(ping),
+            Err(error) => {
+                issue(TimingIssueKind::Unreadable { error: error.to_string() });
+                None
+            }
+        };
+        let ping_interval = ping.as_ref().and_then(|ping| {
+            ping.channels
+                .iter()
+                .find_map(|channel| get_number(&channel.header, "SecondsPerPing").filter(|seconds| *seconds > 0.0))
+        });
+        if let (Some(from), Some(to)) = (seconds_per_ping, ping_interval) {
+            if (to - from).abs() > 1e-6 * from {
+                issue(TimingIssueKind::PingRateChange { from, to });
+            }
+        }
+        seconds_per_ping = ping_interval.or(seconds_per_ping);
+
+        for channel in ping.iter().flat_map(|ping| &ping.channels) {
+            let Some(range) = ranges.get_mut(channel.channel_number as usize) else { continue };
+            let Some(slant_range) = get_number(&channel.header, "SlantRange").filter(|range| *range > 0.0) else { continue };
+            if let Some(from) = *range {
+                if (slant_range - from).abs() > 1e-3 {
+                    issue(TimingIssueKind::RangeChange { channel: channel.channel_number, from, to: slant_range });
+                }
+            }
+            *range = Some(slant_range);
+        }
+
+        // times
+        if !entry.time.is_finite() {
+            issue(TimingIssueKind::NoTime);
+            continue;
+        }
+        if let Some(previous) = previous_time {
+            let dt = entry.time - previous;
+            let expected = ping_interval.or(median);
+            if dt < 0.0 {
+                issue(TimingIssueKind::TimeBackwards { seconds: -dt });
+            } else if dt == 0.0 {
+                issue(TimingIssueKind::RepeatedTime);
+            } else if let Some(expected) = expected.filter(|expected| dt > gap_factor * expected) {
+                issue(TimingIssueKind::TimeGap { seconds: dt, expected });
+            }
+        }
+        previous_time = Some(entry.time);
+    }
+
+    TimingReport {
+        pings: xtf.index.pings().count(),
+        start_time: times.first().copied(),
+        end_time: times.last().copied(),
+        median_interval: median,
+        dropped_pings,
+        issues,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{write_field, PING_CHAN_HEADER_LENGTH, PING_HEADER_LENGTH, XTF_PING_CHAN_HEADER};
+    use crate::test_data::{file_header, ping_record, TestPing};
+
+    // 2 channels of 16 samples, `change` edits a ping's record bytes before it goes in the file
+    fn report(pings: &[TestPing], change: impl Fn(usize, &mut Vec<u8>)) -> TimingReport {
+        let mut bytes = file_header(2);
+        for (i, ping) in pings.iter().enumerate() {
+            let mut record = ping_record(2, 16, ping);
+            change(i, &mut record);
+            bytes.extend(record);
+        }
+        check_timing(&XtfFile::from_bytes(bytes).unwrap(), 2.0)
+    }
+
+    fn issues(report: &TimingReport) -> Vec<(u32, TimingIssueKind)> {
+        report.issues.iter().map(|issue| (issue.ping_number, issue.issue.clone())).collect()
+    }
+
+    fn channel_field(record: &mut [u8], channel: usize, name: &str, value: f64) {
+        let base = PING_HEADER_LENGTH + channel * (PING_CHAN_HEADER_LENGTH + 32);
+        write_field(XTF_PING_CHAN_HEADER, record, base, name, value).unwrap();
+    }
+
+    fn numbered(numbers: &[u32]) -> Vec<TestPing> {
+        numbers.iter().enumerate().map(|(i, &ping_number)| TestPing { ping_number, ..TestPing::numbered(i as u32) }).collect()
+    }
+
+    #[test]
+    fn nothing_to_report_for_a_steady_file() {
+        let pings: Vec<TestPing> = (0..10).map(TestPing::numbered).collect();
+        let report = report(&pings, |_, _| {});
+        assert_eq!((report.pings, report.dropped_pings, report.median_interval), (10, 0, Some(1.0)));
+        assert_eq!((report.start_time, report.end_time), (Some(pings[0].time), Some(pings[9].time)));
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn finds_time_gaps() {
+        let mut pings: Vec<TestPing> = (0..6).map(TestPing::numbered).collect();
+        for ping in &mut pings[3..] {
+            ping.time += 0.5; // 1.5 s, within 2 x SecondsPerPing
+        }
+        for ping in &mut pings[5..] {
+            ping.time += 2.0; // 3 s
+        }
+        assert_eq!(issues(&report(&pings, |_, _| {})), [(5, TimingIssueKind::TimeGap { seconds: 3.0, expected: 1.0 })]);
+    }
+
+    #[test]
+    fn counts_dropped_pings() {
+        let report = report(&numbered(&[1, 2, 5, 6, 8]), |_, _| {});
+        assert_eq!(report.dropped_pings, 3);
+        assert_eq!(
+            issues(&report),
+            [(5, TimingIssueKind::DroppedPings { missing: 2 }), (8, TimingIssueKind::DroppedPings { missing: 1 })]
+        );
+    }
+
+    #[test]
+    fn finds_repeated_and_backwards_ping_numbers() {
+        let repeated = report(&numbered(&[1, 2, 3, 2, 4, 3]), |_, _| {});
+        assert_eq!(issues(&repeated), [(2, TimingIssueKind::DuplicatePingNumber), (3, TimingIssueKind::DuplicatePingNumber)]);
+        assert_eq!(repeated.dropped_pings, 0);
+
+        let backwards = report(&numbered(&[10, 11, 7, 8]), |_, _| {});
+        assert_eq!(issues(&backwards), [(7, TimingIssueKind::PingNumberBackwards { previous: 11 })]);
+    }
+
+    #[test]
+    fn a_wrapping_ping_counter_goes_backwards() {
+        let report = report(&numbered(&[u32::MAX - 1, u32::MAX, 0, 1]), |_, _| {});
+        assert_eq!(report.dropped_pings, 0);
+        assert_eq!(issues(&report), [(0, TimingIssueKind::PingNumberBackwards { previous: u32::MAX })]);
+    }
+
+    #[test]
+    fn finds_times_that_stand_still_or_go_backwards() {
+        let mut pings: Vec<TestPing> = (0..5).map(TestPing::numbered).collect();
+        pings[2].time = pings[1].time;
+        pings[4].time = pings[3].time - 0.5;
+        assert_eq!(
+            issues(&report(&pings, |_, _| {})),
+            [(2, TimingIssueKind::RepeatedTime), (4, TimingIssueKind::TimeBackwards { seconds: 0.5 })]
+        );
+    }
+
+    #[test]
+    fn finds_ping_rate_and_range_changes() {
+        let pings: Vec<TestPing> = (0..4).map(|n| TestPing { time: 1_714_521_600.0 + n as f64 * 0.5, ..TestPing::numbered(n) }).collect();
+        let report = report(&pings, |i, record| {
+            for channel in 0..2 {
+                channel_field(record, channel, "SecondsPerPing", if i < 2 { 1.0 } else { 0.5 });
+            }
+            if i == 3 {
+                channel_field(record, 1, "SlantRange", 75.0);
+            }
+        });
+        assert_eq!(
+            issues(&report),
+            [
+                (2, TimingIssueKind::PingRateChange { from: 1.0, to: 0.5 }),
+                (3, TimingIssueKind::RangeChange { channel: 1, from: 50.0, to: 75.0 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_an_unreadable_ping_and_carries_on() {
+        let pings = numbered(&[1, 2, 4, 5]);
+        let report = report(&pings, |i, record| {
+            if i == 1 {
+                channel_field(record, 1, "NumSamples", 1000.0);
+            }
+        });
+
+        let issues = issues(&report);
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert!(matches!(&issues[0], (2, TimingIssueKind::Unreadable { error }) if error.contains("past end")), "{:?}", issues);
+        assert_eq!(issues[1], (4, TimingIssueKind::DroppedPings { missing: 1 }));
+        assert_eq!(report.pings, 4);
+    }
+}
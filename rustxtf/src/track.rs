@@ -0,0 +1,371 @@
+use std::error::Error;
+use std::io::Write;
+
+use serde_json::{json, Value};
+
+use crate::coords::{is_position, CoordinateSystem};
+use crate::heading::record_heading;
+use crate::time::format_time;
+use crate::xtf_file::XtfFile;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    pub ping_number: u32,
+    pub time: f64, // seconds since epoch, NaN if unknown
+    pub ship_x: f64,
+    pub ship_y: f64,
+    pub sensor_x: f64,
+    pub sensor_y: f64,
+    pub heading: f64,
+    pub speed: f64, // ShipSpeed, knots
+    pub depth: f64,
+    pub altitude: f64,
+}
+
+impl TrackPoint {
+    // Towfish position when the logger filled it in, otherwise the ship's
+    pub fn sensor_or_ship(&self) -> (f64, f64) {
+        if self.sensor_x == 0.0 && self.sensor_y == 0.0 {
+            (self.ship_x, self.ship_y)
+        } else {
+            (self.sensor_x, self.sensor_y)
+        }
+    }
+
+    pub fn position(&self, source: TrackSource) -> (f64, f64) {
+        match source {
+            TrackSource::Ship => (self.ship_x, self.ship_y),
+            TrackSource::Sensor => self.sensor_or_ship(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackSource {
+    Ship,
+    Sensor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackFormat {
+    Csv,
+    // LineString, plus a Point feature per ping when points is set
+    GeoJson { points: bool },
+    Kml,
+    Gpx,
+}
+
+impl TrackFormat {
+    pub fn from_extension(extension: &str) -> Option<TrackFormat> {
+        match extension.to_lowercase().as_str() {
+            "csv" => Some(TrackFormat::Csv),
+            "geojson" => Some(TrackFormat::GeoJson { points: false }),
+            "kml" => Some(TrackFormat::Kml),
+            "gpx" => Some(TrackFormat::Gpx),
+            _ => None,
+        }
+    }
+}
+
+
+// One point per ping, keeping every `every`th ping (1 keeps them all). Pings without any
+// position are dropped
+pub fn read_track(xtf: &XtfFile, every: usize) -> Vec<TrackPoint> {
+    xtf.index
+        .pings()
+        .step_by(every.max(1))
+        .map(|entry| {
+            let field = |name: &str| xtf.ping_field(entry, name).unwrap_or(f64::NAN);
+            TrackPoint {
+                ping_number: entry.ping_number,
+                time: entry.time,
+                ship_x: field("ShipXcoordinate"),
+                ship_y: field("ShipYcoordinate"),
+                sensor_x: field("SensorXcoordinate"),
+                sensor_y: field("SensorYcoordinate"),
+                heading: record_heading(&xtf.data, entry.offset as usize).unwrap_or(f64::NAN),
+                speed: field("ShipSpeed"),
+                depth: field("SensorDepth"),
+                altitude: field("SensorPrimaryAltitude"),
+            }
+        })
+        .filter(|point| {
+            let (x, y) = point.sensor_or_ship();
+            is_position(x, y)
+        })
+        .collect()
+}
+
+
+// Moves the ship and sensor positions from one coordinate system to another. Empty sensor
+// positions stay empty so sensor_or_ship still falls back to the ship
+pub fn convert_track(points: &[TrackPoint], from: CoordinateSystem, to: CoordinateSystem) -> Result<Vec<TrackPoint>, Box<dyn Error>> {
+    let convert = |x: f64, y: f64| -> Result<(f64, f64), Box<dyn Error>> {
+        if !is_position(x, y) {
+            return Ok((x, y));
+        }
+        from.convert(x, y, to)
+            .ok_or_else(|| format!("can't convert position {}, {} from {:?} to {:?}", x, y, from, to).into())
+    };
+
+    points
+        .iter()
+        .map(|point| {
+            let (ship_x, ship_y) = convert(point.ship_x, point.ship_y)?;
+            let (sensor_x, sensor_y) = convert(point.sensor_x, point.sensor_y)?;
+            Ok(TrackPoint { ship_x, ship_y, sensor_x, sensor_y, ..*point })
+        })
+        .collect()
+}
+
+
+// GeoJSON, KML and GPX take x as longitude and y as latitude so the points need to be
+// geographic for those, see convert_track
+pub fn write_track<W: Write>(points: &[TrackPoint], format: TrackFormat, source: TrackSource, out: &mut W) -> Result<(), Box<dyn Error>> {
+    match format {
+        TrackFormat::Csv => write_csv(points, out)?,
+        TrackFormat::GeoJson { points: with_points } => write_geojson(points, source, with_points, out)?,
+        TrackFormat::Kml => write_kml(points, source, out)?,
+        TrackFormat::Gpx => write_gpx(points, source, out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+
+fn time_text(time: f64) -> String {
+    if time.is_nan() {
+        String::new()
+    } else {
+        format_time(time)
+    }
+}
+
+
+// NaN isn't valid json, null it
+fn number(value: f64) -> Value {
+    if value.is_finite() {
+        json!(value)
+    } else {
+        Value::Null
+    }
+}
+
+
+fn write_csv<W: Write>(points: &[TrackPoint], out: &mut W) -> Result<(), Box<dyn Error>> {
+    writeln!(out, "ping_number,time,ship_x,ship_y,sensor_x,sensor_y,heading,speed,depth,altitude")?;
+    for p in points {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            p.ping_number,
+            time_text(p.time),
+            p.ship_x,
+            p.ship_y,
+            p.sensor_x,
+            p.sensor_y,
+            p.heading,
+            p.speed,
+            p.depth,
+            p.altitude
+        )?;
+    }
+    Ok(())
+}
+
+
+fn write_geojson<W: Write>(points: &[TrackPoint], source: TrackSource, with_points: bool, out: &mut W) -> Result<(), Box<dyn Error>> {
+    let coordinates: Vec<[f64; 2]> = points
+        .iter()
+        .map(|p| {
+            let (x, y) = p.position(source);
+            [x, y]
+        })
+        .collect();
+
+    let mut features = vec![json!({
+        "type": "Feature",
+        "geometry": { "type": "LineString", "coordinates": coordinates },
+        "properties": {
+            "start_time": points.first().map(|p| time_text(p.time)),
+            "end_time": points.last().map(|p| time_text(p.time)),
+            "pings": points.len(),
+        },
+    })];
+
+    if with_points {
+        for (p, coordinate) in points.iter().zip(&coordinates) {
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": coordinate },
+                "properties": {
+                    "ping_number": p.ping_number,
+                    "time": time_text(p.time),
+                    "heading": number(p.heading),
+                    "speed": number(p.speed),
+                    "depth": number(p.depth),
+                    "altitude": number(p.altitude),
+                },
+            }));
+        }
+    }
+
+    serde_json::to_writer(&mut *out, &json!({ "type": "FeatureCollection", "features": features }))?;
+    writeln!(out)?;
+    Ok(())
+}
+
+
+fn write_kml<W: Write>(points: &[TrackPoint], source: TrackSource, out: &mut W) -> Result<(), Box<dyn Error>> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(out, "<Document>")?;
+    writeln!(out, "<Placemark>")?;
+    writeln!(out, "<name>Track</name>")?;
+    writeln!(out, "<LineString>")?;
+    writeln!(out, "<tessellate>1</tessellate>")?;
+    write!(out, "<coordinates>")?;
+    for p in points {
+        let (x, y) = p.position(source);
+        write!(out, "{},{},0 ", x, y)?;
+    }
+    writeln!(out, "</coordinates>")?;
+    writeln!(out, "</LineString>")?;
+    writeln!(out, "</Placemark>")?;
+    writeln!(out, "</Document>")?;
+    writeln!(out, "</kml>")?;
+    Ok(())
+}
+
+
+fn write_gpx<W: Write>(points: &[TrackPoint], source: TrackSource, out: &mut W) -> Result<(), Box<dyn Error>> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<gpx version="1.1" creator="rustxtf" xmlns="http://www.topografix.com/GPX/1/1">"#)?;
+    writeln!(out, "<trk>")?;
+    writeln!(out, "<name>Track</name>")?;
+    writeln!(out, "<trkseg>")?;
+    for p in points {
+        let (x, y) = p.position(source);
+        write!(out, r#"<trkpt lat="{}" lon="{}">"#, y, x)?;
+        if p.depth.is_finite() {
+            write!(out, "<ele>{}</ele>", -p.depth)?;
+        }
+        if !p.time.is_nan() {
+            write!(out, "<time>{}</time>", time_text(p.time))?;
+        }
+        writeln!(out, "</trkpt>")?;
+    }
+    writeln!(out, "</trkseg>")?;
+    writeln!(out, "</trk>")?;
+    writeln!(out, "</gpx>")?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{write_field, XTF_PING_HEADER};
+    use crate::test_data::{file_header, ping_record, TestPing};
+
+    fn point(ping_number: u32, sensor_x: f64, sensor_y: f64) -> TrackPoint {
+        TrackPoint {
+            ping_number,
+            time: 1_714_521_600.0 + ping_number as f64,
+            ship_x: 1.5,
+            ship_y: 54.5,
+            sensor_x,
+            sensor_y,
+            heading: 90.0,
+            speed: 4.0,
+            depth: 12.5,
+            altitude: f64::NAN,
+        }
+    }
+
+    fn written(points: &[TrackPoint], format: TrackFormat, source: TrackSource) -> String {
+        let mut out = Vec::new();
+        write_track(points, format, source, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn reads_every_nth_ping_with_a_position() {
+        let mut bytes = file_header(2);
+        for n in 0..6 {
+            let mut ping = ping_record(2, 16, &TestPing::numbered(n));
+            if n == 2 {
+                for field in ["SensorXcoordinate", "SensorYcoordinate", "ShipXcoordinate", "ShipYcoordinate"] {
+                    write_field(XTF_PING_HEADER, &mut ping, 0, field, 0.0).unwrap();
+                }
+            }
+            bytes.extend(ping);
+        }
+        let xtf = XtfFile::from_bytes(bytes).unwrap();
+
+        let numbers = |points: Vec<TrackPoint>| points.iter().map(|p| p.ping_number).collect::<Vec<_>>();
+        assert_eq!(numbers(read_track(&xtf, 1)), [0, 1, 3, 4, 5]);
+        assert_eq!(numbers(read_track(&xtf, 2)), [0, 4]);
+
+        let first = read_track(&xtf, 1)[0];
+        assert_eq!((first.sensor_x, first.sensor_y, first.altitude), (1.0, 54.0, 10.0));
+        assert_eq!(first.time, TestPing::numbered(0).time);
+    }
+
+    #[test]
+    fn falls_back_to_the_ship_for_empty_sensor_positions() {
+        assert_eq!(point(0, 0.0, 0.0).position(TrackSource::Sensor), (1.5, 54.5));
+        assert_eq!(point(0, 1.0, 54.0).position(TrackSource::Sensor), (1.0, 54.0));
+        assert_eq!(point(0, 1.0, 54.0).position(TrackSource::Ship), (1.5, 54.5));
+    }
+
+    #[test]
+    fn writes_csv() {
+        let csv = written(&[point(7, 1.0, 54.0)], TrackFormat::Csv, TrackSource::Sensor);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "ping_number,time,ship_x,ship_y,sensor_x,sensor_y,heading,speed,depth,altitude");
+        assert_eq!(lines[1], "7,2024-05-01T00:00:07.00Z,1.5,54.5,1,54,90,4,12.5,NaN");
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn writes_geojson() {
+        let points = [point(0, 1.0, 54.0), point(1, 0.0, 0.0)];
+        let text = written(&points, TrackFormat::GeoJson { points: true }, TrackSource::Sensor);
+        let geojson: Value = serde_json::from_str(&text).unwrap();
+
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(features[0]["geometry"]["coordinates"], json!([[1.0, 54.0], [1.5, 54.5]]));
+        assert_eq!(features[0]["properties"]["pings"], 2);
+        assert_eq!(features[2]["geometry"], json!({ "type": "Point", "coordinates": [1.5, 54.5] }));
+        assert_eq!(features[2]["properties"]["ping_number"], 1);
+        assert_eq!(features[2]["properties"]["altitude"], Value::Null);
+
+        let text = written(&points, TrackFormat::GeoJson { points: false }, TrackSource::Ship);
+        let geojson: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(geojson["features"][0]["geometry"]["coordinates"], json!([[1.5, 54.5], [1.5, 54.5]]));
+        assert_eq!(geojson["features"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn writes_kml_longitude_first() {
+        let kml = written(&[point(0, 1.0, 54.0), point(1, 2.0, 55.0)], TrackFormat::Kml, TrackSource::Sensor);
+        assert!(kml.contains("<coordinates>1,54,0 2,55,0 </coordinates>"), "{}", kml);
+        assert!(kml.trim_end().ends_with("</kml>"));
+    }
+
+    #[test]
+    fn writes_gpx_with_depth_as_negative_elevation() {
+        let mut untimed = point(1, 2.0, 55.0);
+        untimed.time = f64::NAN;
+        let gpx = written(&[point(0, 1.0, 54.0), untimed], TrackFormat::Gpx, TrackSource::Sensor);
+
+        assert!(gpx.contains(r#"<trkpt lat="54" lon="1"><ele>-12.5</ele><time>2024-05-01T00:00:00.00Z</time></trkpt>"#), "{}", gpx);
+        assert!(gpx.contains(r#"<trkpt lat="55" lon="2"><ele>-12.5</ele></trkpt>"#), "{}", gpx);
+        assert!(gpx.trim_end().ends_with("</gpx>"));
+    }
+}
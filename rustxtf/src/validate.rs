@@ -0,0 +1,87 @@
+use serde_derive::Serialize;
+
+use crate::headers::{get_number, XTF_HEADER_SONAR};
+use crate::record::{next_record_offset, parse_ping, read_record_prefix};
+use crate::time::ping_time;
+use crate::xtf_file::XtfFile;
+
+const XTF_FILE_FORMAT: f64 = 123.0; // FileFormat is always 0x7B
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub offset: usize,
+    pub message: String,
+}
+
+
+// Walks the file byte by byte rather than trusting the index, so damage the reader quietly
+// skips over is reported. Empty result means the file looks fine
+pub fn validate(xtf: &XtfFile) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |offset: usize, message: String| issues.push(Issue { offset, message });
+
+    let file_format = get_number(&xtf.file_header, "FileFormat");
+    if file_format != Some(XTF_FILE_FORMAT) {
+        issue(0, format!("FileFormat is {:?}, expected 123", file_format));
+    }
+    if xtf.number_of_sonar_channels() == 0 {
+        issue(0, "NumberOfSonarChannels is 0".to_string());
+    }
+
+    let bytes_per_sample = xtf.bytes_per_sample();
+    let mut offset = xtf.header_length;
+    let mut pings = 0;
+
+    while offset < xtf.data.len() {
+        let record_offset = match next_record_offset(&xtf.data, offset) {
+            Some(record_offset) => record_offset,
+            None => {
+                issue(offset, format!("{} trailing bytes that aren't a record", xtf.data.len() - offset));
+                break;
+            }
+        };
+        if record_offset != offset {
+            issue(offset, format!("{} bytes of junk before next record", record_offset - offset));
+        }
+
+        // next_record_offset only returns offsets with a readable prefix
+        let (header_type, length) = match read_record_prefix(&xtf.data, record_offset) {
+            Ok(prefix) => prefix,
+            Err(e) => {
+                issue(record_offset, e.to_string());
+                break;
+            }
+        };
+
+        if header_type == XTF_HEADER_SONAR {
+            pings += 1;
+            let bytes = xtf.data[record_offset..record_offset + length].to_vec();
+
+            match parse_ping(bytes, &bytes_per_sample) {
+                Ok(ping) => {
+                    if ping.channels.is_empty() {
+                        issue(record_offset, "Ping has no channels".to_string());
+                    }
+                    for channel in &ping.channels {
+                        if channel.channel_number as usize >= xtf.channel_infos.len() {
+                            issue(record_offset, format!("Ping channel {} has no chan info", channel.channel_number));
+                        }
+                    }
+                    if ping_time(&ping.header).is_none() {
+                        issue(record_offset, "Ping has no valid date/time".to_string());
+                    }
+                }
+                Err(e) => issue(record_offset, e.to_string()),
+            }
+        }
+
+        offset = record_offset + length;
+    }
+
+    if pings == 0 {
+        issue(xtf.header_length, "No sonar pings in file".to_string());
+    }
+
+    issues
+}
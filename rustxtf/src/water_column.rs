@@ -0,0 +1,242 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::bottom::{track_bottom, BottomTrackSettings};
+use crate::georef::{CHANNEL_TYPE_PORT, CHANNEL_TYPE_STARBOARD};
+use crate::headers::{get_number, XTF_HEADER_SONAR};
+use crate::record::{parse_ping, Ping};
+use crate::writer::{check_not_input, XtfWriter};
+use crate::xtf_file::XtfFile;
+
+// Everything before the first bottom return is water column, noise as far as the seabed
+// image goes. It can be blanked out or cut off the front of the samples, and blanked in place
+// in a copy of the file
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AltitudeSource {
+    // SensorPrimaryAltitude as logged
+    Logged,
+    // picked from the samples by the bottom tracker
+    Tracked { channels: (u16, u16), settings: BottomTrackSettings },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaterColumn {
+    // water column samples become NaN, array length unchanged
+    Blank,
+    // water column samples are dropped, so the array starts at the first return
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CleanChannel {
+    pub channel_number: u16,
+    // how many samples were before the bottom, 0 for channels that aren't port or starboard
+    pub water_column: usize,
+    pub samples: Vec<f32>,
+}
+
+
+// How many samples come before the bottom, sample i being at (i + 0.5) * slant_range / n
+pub fn water_column_samples(num_samples: usize, slant_range: f64, altitude: f64) -> usize {
+    if num_samples == 0 || slant_range.is_nan() || slant_range <= 0.0 || altitude.is_nan() || altitude <= 0.0 {
+        return 0;
+    }
+    ((altitude / slant_range * num_samples as f64 - 0.5).ceil().max(0.0) as usize).min(num_samples)
+}
+
+
+// Altitude for each ping in file order
+pub fn ping_altitudes(xtf: &XtfFile, source: AltitudeSource) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+    match source {
+        AltitudeSource::Logged => Ok(xtf
+            .index
+            .pings()
+            .map(|entry| xtf.ping_field(entry, "SensorPrimaryAltitude").filter(|altitude| *altitude > 0.0))
+            .collect()),
+        AltitudeSource::Tracked { channels, settings } => {
+            Ok(track_bottom(xtf, channels, &settings)?.into_iter().map(|pick| pick.altitude).collect())
+        }
+    }
+}
+
+
+// Decoded samples for every channel of the ping with the water column of the port and
+// starboard channels blanked or removed. Without an altitude the samples are left alone
+pub fn clean_ping(xtf: &XtfFile, ping: &Ping, altitude: Option<f64>, mode: WaterColumn) -> Vec<CleanChannel> {
+    ping.channels
+        .iter()
+        .enumerate()
+        .map(|(i, channel)| {
+            let mut samples = xtf.samples(ping, i);
+            let side = xtf
+                .channel_infos
+                .get(channel.channel_number as usize)
+                .and_then(|info| get_number(info, "TypeOfChannel"))
+                .map(|t| t as u8);
+            let water_column = match altitude {
+                Some(altitude) if side == Some(CHANNEL_TYPE_PORT) || side == Some(CHANNEL_TYPE_STARBOARD) => {
+                    let slant_range = get_number(&channel.header, "SlantRange").unwrap_or(0.0);
+                    water_column_samples(samples.len(), slant_range, altitude)
+                }
+                _ => 0,
+            };
+
+            match mode {
+                WaterColumn::Blank => samples[..water_column].fill(f32::NAN),
+                WaterColumn::Remove => {
+                    samples.drain(..water_column);
+                }
+            }
+            CleanChannel { channel_number: channel.channel_number, water_column, samples }
+        })
+        .collect()
+}
+
+
+// Copies the file with the water column samples of the port and starboard channels set to
+// zero, as clean_ping blanks them. Only blanking: cutting samples off would move sample 0
+// away from the sensor and anything reading the file would need to know. Pings without an
+// altitude are copied as they are. Returns records written
+pub fn write_blanked<P: AsRef<Path>>(xtf: &XtfFile, altitudes: &[Option<f64>], output: P) -> Result<usize, Box<dyn Error>> {
+    check_not_input(&output, &[&xtf.path])?;
+    let mut writer = XtfWriter::create(output, xtf.header_bytes())?;
+    let mut altitudes = altitudes.iter();
+    let bytes_per_sample = xtf.bytes_per_sample();
+
+    for entry in &xtf.index.entries {
+        let bytes = xtf.record_bytes(entry)?;
+        if entry.header_type != XTF_HEADER_SONAR {
+            writer.write_record(bytes)?;
+            continue;
+        }
+        let Some(altitude) = altitudes.next().copied().flatten() else {
+            writer.write_record(bytes)?;
+            continue;
+        };
+
+        let mut ping = parse_ping(bytes.to_vec(), &bytes_per_sample)?;
+        let cleaned = clean_ping(xtf, &ping, Some(altitude), WaterColumn::Blank);
+        for (channel, clean) in ping.channels.iter().zip(&cleaned) {
+            let start = channel.data_offset;
+            ping.bytes[start..start + clean.water_column * channel.bytes_per_sample].fill(0);
+        }
+        writer.write_record(&ping.bytes)?;
+    }
+
+    writer.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+    use crate::test_data::{file_header, other_record, ping_record, set_samples, TempPath, TestPing};
+
+    // Water column at 10 then seabed at 1000 from 15 m out on both sides, logged altitude 10 m
+    fn seabed_at_15m(pings: u32) -> XtfFile {
+        let mut bytes = file_header(2);
+        for n in 0..pings {
+            let mut ping = ping_record(2, 100, &TestPing::numbered(n));
+            let samples: Vec<u16> = (0..100).map(|i| if i >= 30 { 1000 } else { 10 }).collect();
+            set_samples(&mut ping, 100, 0, &samples);
+            set_samples(&mut ping, 100, 1, &samples);
+            bytes.extend(ping);
+            bytes.extend(other_record(107, 64));
+        }
+        XtfFile::from_bytes(bytes).unwrap()
+    }
+
+    fn first_ping(xtf: &XtfFile) -> Ping {
+        xtf.ping(xtf.index.pings().next().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn counts_samples_before_the_bottom() {
+        assert_eq!(water_column_samples(100, 50.0, 10.0), 20);
+        assert_eq!(water_column_samples(100, 50.0, 10.3), 21);
+        assert_eq!(water_column_samples(100, 50.0, 80.0), 100);
+        assert_eq!(water_column_samples(100, 50.0, 0.0), 0);
+        assert_eq!(water_column_samples(100, 0.0, 10.0), 0);
+        assert_eq!(water_column_samples(100, 50.0, f64::NAN), 0);
+    }
+
+    #[test]
+    fn blanks_the_water_column() {
+        let xtf = seabed_at_15m(1);
+        let cleaned = clean_ping(&xtf, &first_ping(&xtf), Some(10.0), WaterColumn::Blank);
+
+        assert_eq!(cleaned.len(), 2);
+        for clean in &cleaned {
+            assert_eq!((clean.water_column, clean.samples.len()), (20, 100));
+            assert!(clean.samples[..20].iter().all(|sample| sample.is_nan()));
+            assert_eq!((clean.samples[20], clean.samples[30]), (10.0, 1000.0));
+        }
+    }
+
+    #[test]
+    fn removes_the_water_column() {
+        let xtf = seabed_at_15m(1);
+        let cleaned = clean_ping(&xtf, &first_ping(&xtf), Some(10.0), WaterColumn::Remove);
+
+        for clean in &cleaned {
+            assert_eq!((clean.water_column, clean.samples.len()), (20, 80));
+            assert_eq!((clean.samples[0], clean.samples[10]), (10.0, 1000.0));
+        }
+        assert_eq!(cleaned[1].channel_number, 1);
+    }
+
+    #[test]
+    fn leaves_pings_without_an_altitude_alone() {
+        let xtf = seabed_at_15m(1);
+        let ping = first_ping(&xtf);
+        for mode in [WaterColumn::Blank, WaterColumn::Remove] {
+            let cleaned = clean_ping(&xtf, &ping, None, mode);
+            assert_eq!(cleaned[0].water_column, 0);
+            assert_eq!(cleaned[0].samples, xtf.samples(&ping, 0));
+        }
+    }
+
+    #[test]
+    fn uses_the_logged_or_tracked_altitude() {
+        let xtf = seabed_at_15m(5);
+        let logged = ping_altitudes(&xtf, AltitudeSource::Logged).unwrap();
+        let tracked = ping_altitudes(
+            &xtf,
+            AltitudeSource::Tracked { channels: (0, 1), settings: BottomTrackSettings::default() },
+        )
+        .unwrap();
+
+        assert_eq!(logged, vec![Some(10.0); 5]);
+        assert!(tracked.iter().all(|altitude| altitude.is_some_and(|a| (a - 15.0).abs() <= 0.5)), "{:?}", tracked);
+
+        // the logged altitude is short of the seabed, the tracked one takes out up to it
+        let ping = first_ping(&xtf);
+        let from_logged = clean_ping(&xtf, &ping, logged[0], WaterColumn::Remove);
+        let from_tracked = clean_ping(&xtf, &ping, tracked[0], WaterColumn::Remove);
+        assert_eq!(from_logged[0].water_column, 20);
+        assert_eq!(from_tracked[0].water_column, 29);
+        assert_eq!(from_tracked[0].samples[1], 1000.0);
+    }
+
+    #[test]
+    fn writes_a_blanked_copy() {
+        let xtf = seabed_at_15m(2);
+        let output = TempPath::new("blanked.xtf");
+        assert_eq!(write_blanked(&xtf, &[Some(10.0), None], &output.0).unwrap(), 4);
+
+        let blanked = XtfFile::open(&output.0).unwrap();
+        assert_eq!(blanked.data.len(), xtf.data.len());
+        let pings: Vec<Ping> = blanked.index.pings().map(|entry| blanked.ping(entry).unwrap()).collect();
+        let samples = blanked.samples(&pings[0], 1);
+        assert!(samples[..20].iter().all(|&sample| sample == 0.0));
+        assert_eq!(samples[20..], xtf.samples(&first_ping(&xtf), 1)[20..]);
+        assert_eq!(blanked.samples(&pings[1], 0), xtf.samples(&pings[1], 0), "no altitude, copied as it was");
+
+        let entry = &blanked.index.entries[1];
+        assert!(matches!(blanked.record(entry).unwrap(), Record::Unknown { header_type: 107, .. }));
+        assert!(write_blanked(&blanked, &[], &output.0).is_err(), "wrote over its input");
+    }
+}
@@ -0,0 +1,204 @@
+use std::error::Error;
+
+use crate::gain::normalise_across_track;
+use crate::headers::get_number;
+use crate::record::Ping;
+use crate::processing::Processing;
+use crate::xtf_file::XtfFile;
+
+
+// Port on the left read outwards to inwards, starboard on the right, one row per ping with
+// the first ping at the top. Missing samples are NaN
+#[derive(Debug, Clone)]
+pub struct Waterfall {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stretch {
+    // darkest sample to black, brightest to white
+    MinMax,
+    // percentiles (0 - 100) of the samples mapped to black and white, clipping outliers
+    Percentile(f64, f64),
+    // fixed sample values for black and white
+    Manual(f64, f64),
+}
+
+
+// First port and starboard channels by TypeOfChannel, falling back to channels 0 and 1
+pub fn default_pair(xtf: &XtfFile) -> (u16, u16) {
+    let find = |type_of_channel: f64| {
+        xtf.channel_infos
+            .iter()
+            .take(xtf.number_of_sonar_channels())
+            .position(|info| get_number(info, "TypeOfChannel") == Some(type_of_channel))
+            .map(|channel| channel as u16)
+    };
+    (find(1.0).unwrap_or(0), find(2.0).unwrap_or(1))
+}
+
+
+fn channel_samples(xtf: &XtfFile, ping: &Ping, channel_number: u16, processing: &Processing) -> Vec<f32> {
+    match ping.channels.iter().position(|channel| channel.channel_number == channel_number) {
+        Some(i) => processing.channel_samples(xtf, ping, i),
+        None => Vec::new(),
+    }
+}
+
+
+// Builds the image from every `ping_step`th ping with the processing applied. Each side is as
+// wide as the longest ping seen on it, shorter pings are padded out at the far range
+pub fn build_waterfall(
+    xtf: &XtfFile,
+    port: u16,
+    starboard: u16,
+    ping_step: usize,
+    processing: &Processing,
+) -> Result<Waterfall, Box<dyn Error>> {
+    let mut port_rows = Vec::new();
+    let mut starboard_rows = Vec::new();
+    for entry in xtf.index.pings().step_by(ping_step.max(1)) {
+        let ping = xtf.ping(entry)?;
+        port_rows.push(channel_samples(xtf, &ping, port, processing));
+        starboard_rows.push(channel_samples(xtf, &ping, starboard, processing));
+    }
+
+    if let Some(window) = processing.normalise_window {
+        normalise_across_track(&mut port_rows, window);
+        normalise_across_track(&mut starboard_rows, window);
+    }
+    let rows: Vec<(Vec<f32>, Vec<f32>)> = port_rows.into_iter().zip(starboard_rows).collect();
+
+    let port_width = rows.iter().map(|(p, _)| p.len()).max().unwrap_or(0);
+    let starboard_width = rows.iter().map(|(_, s)| s.len()).max().unwrap_or(0);
+    let width = port_width + starboard_width;
+    if rows.is_empty() || width == 0 {
+        return Err(format!("no samples for channels {} and {}", port, starboard).into());
+    }
+
+    let mut pixels = Vec::with_capacity(width * rows.len());
+    for (port_samples, starboard_samples) in &rows {
+        pixels.extend(std::iter::repeat_n(f32::NAN, port_width - port_samples.len()));
+        pixels.extend(port_samples.iter().rev());
+        pixels.extend(starboard_samples);
+        pixels.extend(std::iter::repeat_n(f32::NAN, starboard_width - starboard_samples.len()));
+    }
+
+    Ok(Waterfall { width, height: rows.len(), pixels })
+}
+
+
+impl Stretch {
+    // Sample values that map to black and white
+    pub fn limits(&self, pixels: &[f32]) -> (f64, f64) {
+        if let Stretch::Manual(low, high) = *self {
+            return (low, high);
+        }
+        let mut values: Vec<f32> = pixels.iter().copied().filter(|v| v.is_finite()).collect();
+        if values.is_empty() {
+            return (0.0, 1.0);
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+
+        let percentile = |p: f64| {
+            let rank = (p.clamp(0.0, 100.0) / 100.0 * (values.len() - 1) as f64).round() as usize;
+            values[rank] as f64
+        };
+
+        match *self {
+            Stretch::MinMax => (percentile(0.0), percentile(100.0)),
+            Stretch::Percentile(low, high) => (percentile(low), percentile(high)),
+            Stretch::Manual(low, high) => (low, high),
+        }
+    }
+}
+
+
+impl Waterfall {
+    // 0 - 1 with the stretch applied, missing samples come out black
+    fn scaled(&self, stretch: Stretch) -> impl Iterator<Item = f64> + '_ {
+        let (low, high) = stretch.limits(&self.pixels);
+        let span = if high > low { high - low } else { 1.0 };
+        self.pixels.iter().map(move |&v| {
+            if v.is_finite() {
+                ((v as f64 - low) / span).clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        })
+    }
+
+    pub fn to_u8(&self, stretch: Stretch) -> Vec<u8> {
+        self.scaled(stretch).map(|v| (v * 255.0).round() as u8).collect()
+    }
+
+    pub fn to_u16(&self, stretch: Stretch) -> Vec<u16> {
+        self.scaled(stretch).map(|v| (v * 65535.0).round() as u16).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{write_field, CHAN_INFO_LENGTH, FILE_HEADER_LENGTH, XTF_CHAN_INFO};
+    use crate::test_data::{file_header, ping_record, xtf_bytes, TestPing};
+
+    // Port is channel 0 with samples 0, 1, 2, ... and starboard channel 1 with 1, 2, 3, ...
+    fn rows(waterfall: &Waterfall) -> Vec<Vec<f32>> {
+        waterfall.pixels.chunks(waterfall.width).map(|row| row.to_vec()).collect()
+    }
+
+    #[test]
+    fn puts_port_on_the_left_reading_outwards_to_inwards() {
+        let xtf = XtfFile::from_bytes(xtf_bytes(2, 4, &[TestPing::numbered(0), TestPing::numbered(1)])).unwrap();
+        let waterfall = build_waterfall(&xtf, 0, 1, 1, &Processing::default()).unwrap();
+
+        assert_eq!((waterfall.width, waterfall.height), (8, 2));
+        assert_eq!(rows(&waterfall)[0], [3.0, 2.0, 1.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn pads_short_pings_at_far_range_and_steps_through_pings() {
+        let mut bytes = file_header(2);
+        bytes.extend(ping_record(2, 4, &TestPing::numbered(0)));
+        bytes.extend(ping_record(2, 2, &TestPing::numbered(1)));
+        bytes.extend(ping_record(2, 4, &TestPing::numbered(2)));
+        let xtf = XtfFile::from_bytes(bytes).unwrap();
+
+        let waterfall = build_waterfall(&xtf, 0, 1, 1, &Processing::default()).unwrap();
+        let short = &rows(&waterfall)[1];
+        assert!(short[0].is_nan() && short[1].is_nan() && short[6].is_nan() && short[7].is_nan());
+        assert_eq!(short[2..6], [1.0, 0.0, 1.0, 2.0]);
+
+        assert_eq!(build_waterfall(&xtf, 0, 1, 2, &Processing::default()).unwrap().height, 2);
+        assert!(build_waterfall(&xtf, 5, 6, 1, &Processing::default()).is_err());
+    }
+
+    #[test]
+    fn picks_port_and_starboard_by_channel_type() {
+        let mut bytes = xtf_bytes(2, 4, &[TestPing::numbered(0)]);
+        write_field(XTF_CHAN_INFO, &mut bytes, FILE_HEADER_LENGTH, "TypeOfChannel", 2.0).unwrap();
+        write_field(XTF_CHAN_INFO, &mut bytes, FILE_HEADER_LENGTH + CHAN_INFO_LENGTH, "TypeOfChannel", 1.0).unwrap();
+        let xtf = XtfFile::from_bytes(bytes).unwrap();
+
+        assert_eq!(default_pair(&xtf), (1, 0));
+        let (port, starboard) = default_pair(&xtf);
+        let waterfall = build_waterfall(&xtf, port, starboard, 1, &Processing::default()).unwrap();
+        assert_eq!(rows(&waterfall)[0], [4.0, 3.0, 2.0, 1.0, 0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn stretches_to_grey_levels() {
+        let pixels: Vec<f32> = (0..=100).map(|v| v as f32).chain([f32::NAN]).collect();
+        assert_eq!(Stretch::MinMax.limits(&pixels), (0.0, 100.0));
+        assert_eq!(Stretch::Percentile(10.0, 90.0).limits(&pixels), (10.0, 90.0));
+        assert_eq!(Stretch::Manual(5.0, 6.0).limits(&pixels), (5.0, 6.0));
+
+        let waterfall = Waterfall { width: 4, height: 1, pixels: vec![10.0, 50.0, 200.0, f32::NAN] };
+        assert_eq!(waterfall.to_u8(Stretch::Manual(10.0, 90.0)), [0, 128, 255, 0]);
+        assert_eq!(waterfall.to_u16(Stretch::MinMax), [0, 13797, 65535, 0]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::record::read_record_prefix;
+
+
+// Writes an XTF file a record at a time. The file header block goes in as raw bytes so
+// everything we don't parse (reserved areas, extra chan infos) survives untouched. Records go
+// to a temporary file next to the output that only replaces it on finish, so a failed write
+// never leaves half a file (or a truncated input) behind
+pub struct XtfWriter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    temporary: PathBuf,
+    finished: bool,
+    pub records_written: usize,
+}
+
+impl XtfWriter {
+    pub fn create<P: AsRef<Path>>(path: P, header_bytes: &[u8]) -> Result<XtfWriter, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let name = path.file_name().ok_or(format!("{} is not a file name", path.display()))?;
+        let temporary = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+
+        let mut writer = XtfWriter {
+            writer: BufWriter::new(File::create(&temporary)?),
+            path,
+            temporary,
+            finished: false,
+            records_written: 0,
+        };
+        writer.writer.write_all(header_bytes)?;
+        Ok(writer)
+    }
+
+    // bytes must be a whole record starting at its 0xFACE magic number
+    pub fn write_record(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let (_, length) = read_record_prefix(bytes, 0)?;
+        if length != bytes.len() {
+            return Err(format!("Record says {} bytes but {} were given", length, bytes.len()).into());
+        }
+
+        self.writer.write_all(bytes)?;
+        self.records_written += 1;
+        Ok(())
+    }
+
+    // Moves the finished file into place. Returns records written
+    pub fn finish(mut self) -> Result<usize, Box<dyn Error>> {
+        self.writer.flush()?;
+        fs::rename(&self.temporary, &self.path)?;
+        self.finished = true;
+        Ok(self.records_written)
+    }
+}
+
+impl Drop for XtfWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+
+// Errors if output is one of the inputs. Reading happens as the output is written (merge
+// opens each input in turn), so writing over an input would lose it
+pub fn check_not_input<P: AsRef<Path>>(output: P, inputs: &[&Path]) -> Result<(), Box<dyn Error>> {
+    let output = output.as_ref();
+    // an output that doesn't exist yet can't be an input
+    let Ok(canonical) = fs::canonicalize(output) else {
+        return Ok(());
+    };
+
+    for input in inputs {
+        if fs::canonicalize(input).is_ok_and(|input| input == canonical) {
+            return Err(format!("{} is an input, write the output somewhere else", output.display()).into());
+        }
+    }
+    Ok(())
+}
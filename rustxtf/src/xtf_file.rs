@@ -0,0 +1,222 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::headers::{
+    get_number, read_field, read_headers, HeaderMap, CHAN_INFO_LENGTH, FILE_HEADER_BLOCK_LENGTH,
+    FILE_HEADER_LENGTH, XTF_CHAN_INFO, XTF_FILE_HEADER, XTF_PING_HEADER,
+};
+use crate::index::{IndexEntry, RecordIndex};
+use crate::record::{read_record, read_record_prefix, Ping, Record};
+use crate::samples::{decode_samples, SAMPLE_FORMAT_LEGACY};
+
+
+#[derive(Debug)]
+pub struct XtfFile {
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    pub file_header: HeaderMap,
+    pub channel_infos: Vec<HeaderMap>,
+    pub header_length: usize, // first record starts here
+    pub index: RecordIndex,
+    pub index_error: Option<String>, // why the sidecar index couldn't be read, it was rebuilt instead
+}
+
+impl XtfFile {
+    // Uses the sidecar index if there is a valid one, otherwise indexes the file in memory
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<XtfFile, Box<dyn Error>> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+
+        let (index, index_error) = match RecordIndex::load(path) {
+            Ok(index) => (index, None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        let mut xtf = XtfFile::parse(data, index)?;
+        xtf.path = path.to_path_buf();
+        xtf.index_error = index_error;
+        Ok(xtf)
+    }
+
+    // Just the file header and chan infos, no records, for checking files before committing to reading them
+    pub fn open_header_only<P: AsRef<Path>>(path: P) -> Result<XtfFile, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+
+        let mut data = vec![0u8; FILE_HEADER_LENGTH];
+        file.read_exact(&mut data)?;
+
+        let (file_header, _) = read_headers(XTF_FILE_HEADER, &data, 0);
+        data.resize(file_header_length(number_of_channel_infos(&file_header)), 0);
+        file.read_exact(&mut data[FILE_HEADER_LENGTH..])?;
+
+        let mut xtf = XtfFile::parse(data, Some(RecordIndex::default()))?;
+        xtf.path = path.to_path_buf();
+        Ok(xtf)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<XtfFile, Box<dyn Error>> {
+        XtfFile::parse(data, None)
+    }
+
+    fn parse(data: Vec<u8>, index: Option<RecordIndex>) -> Result<XtfFile, Box<dyn Error>> {
+        if data.len() < FILE_HEADER_LENGTH {
+            return Err("File too short for an XTF file header".into());
+        }
+
+        let (file_header, _) = read_headers(XTF_FILE_HEADER, &data, 0);
+        let channels = number_of_channel_infos(&file_header);
+        let header_length = file_header_length(channels);
+
+        if data.len() < header_length {
+            return Err(format!("File too short for {} channel infos", channels).into());
+        }
+
+        let mut channel_infos = Vec::with_capacity(channels);
+        for i in 0..channels {
+            let (channel_info, _) = read_headers(XTF_CHAN_INFO, &data, FILE_HEADER_LENGTH + i * CHAN_INFO_LENGTH);
+            channel_infos.push(channel_info);
+        }
+
+        let index = index.unwrap_or_else(|| RecordIndex::build(&data, header_length));
+
+        Ok(XtfFile {
+            path: PathBuf::new(),
+            data,
+            file_header,
+            channel_infos,
+            header_length,
+            index,
+            index_error: None,
+        })
+    }
+
+    pub fn number_of_sonar_channels(&self) -> usize {
+        get_number(&self.file_header, "NumberOfSonarChannels").unwrap_or(0.0) as usize
+    }
+
+    // BytesPerSample for each channel info, indexed by ChannelNumber
+    pub fn bytes_per_sample(&self) -> Vec<usize> {
+        self.channel_infos
+            .iter()
+            .map(|info| get_number(info, "BytesPerSample").unwrap_or(1.0).max(1.0) as usize)
+            .collect()
+    }
+
+    // SampleFormat of a channel from its chan info, legacy if it's missing
+    pub fn sample_format(&self, channel_number: u16) -> u8 {
+        self.channel_infos
+            .get(channel_number as usize)
+            .and_then(|info| get_number(info, "SampleFormat"))
+            .map_or(SAMPLE_FORMAT_LEGACY, |format| format as u8)
+    }
+
+    // Decoded samples of one of the channels in a ping
+    pub fn samples(&self, ping: &Ping, channel_index: usize) -> Vec<f32> {
+        let channel = &ping.channels[channel_index];
+        decode_samples(ping.raw_samples(channel_index), channel.bytes_per_sample, self.sample_format(channel.channel_number))
+    }
+
+    // File header plus channel info blocks, exactly as stored on disk
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.data[..self.header_length]
+    }
+
+    // Persists the record index next to the file so later opens can skip indexing
+    pub fn write_index(&self) -> Result<PathBuf, Box<dyn Error>> {
+        if self.path.as_os_str().is_empty() {
+            return Err("Can only write an index for a file opened from disk".into());
+        }
+        self.index.save(&self.path)
+    }
+
+    // Raw bytes of the record an index entry points at, 0xFACE header included
+    pub fn record_bytes(&self, entry: &IndexEntry) -> Result<&[u8], Box<dyn Error>> {
+        let offset = entry.offset as usize;
+        let (_, length) = read_record_prefix(&self.data, offset)?;
+        Ok(&self.data[offset..offset + length])
+    }
+
+    // One numeric ping header field without parsing the whole record
+    pub fn ping_field(&self, entry: &IndexEntry, name: &str) -> Option<f64> {
+        read_field(XTF_PING_HEADER, &self.data, entry.offset as usize, name).and_then(|value| value.as_f64())
+    }
+
+    // Parses the record an index entry points at
+    pub fn record(&self, entry: &IndexEntry) -> Result<Record, Box<dyn Error>> {
+        read_record(&self.data, entry.offset as usize, &self.bytes_per_sample())
+    }
+
+    pub fn ping_by_number(&self, ping_number: u32) -> Result<Option<Ping>, Box<dyn Error>> {
+        match self.index.ping_by_number(ping_number) {
+            Some(entry) => Ok(Some(self.ping(entry)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Nearest ping to time (seconds since epoch)
+    pub fn ping_at_time(&self, time: f64) -> Result<Option<Ping>, Box<dyn Error>> {
+        match self.index.ping_at_time(time) {
+            Some(entry) => Ok(Some(self.ping(entry)?)),
+            None => Ok(None),
+        }
+    }
+
+    // All pings from start to end inclusive (seconds since epoch)
+    pub fn pings_between(&self, start: f64, end: f64) -> Result<Vec<Ping>, Box<dyn Error>> {
+        self.index
+            .pings_between(start, end)
+            .into_iter()
+            .map(|entry| self.ping(entry))
+            .collect()
+    }
+
+    // Parses a ping, erroring if the entry is some other record type
+    pub fn ping(&self, entry: &IndexEntry) -> Result<Ping, Box<dyn Error>> {
+        match self.record(entry)? {
+            Record::Sonar(ping) => Ok(ping),
+            other => Err(format!("Record at offset {} is type {}, not a ping", entry.offset, other.header_type()).into()),
+        }
+    }
+
+    pub fn records(&self) -> Records<'_> {
+        Records {
+            data: &self.data,
+            entries: self.index.entries.iter(),
+            bytes_per_sample: self.bytes_per_sample(),
+        }
+    }
+}
+
+
+// Chan infos follow the file header for every sonar and bathymetry channel
+pub fn number_of_channel_infos(file_header: &HeaderMap) -> usize {
+    let sonar = get_number(file_header, "NumberOfSonarChannels").unwrap_or(0.0) as usize;
+    let bathymetry = get_number(file_header, "NumberOfBathymetryChannels").unwrap_or(0.0) as usize;
+    sonar + bathymetry
+}
+
+
+// File header and chan infos are padded out to a whole number of 1024 byte blocks
+pub fn file_header_length(channels: usize) -> usize {
+    let used = FILE_HEADER_LENGTH + channels * CHAN_INFO_LENGTH;
+    used.div_ceil(FILE_HEADER_BLOCK_LENGTH).max(1) * FILE_HEADER_BLOCK_LENGTH
+}
+
+
+pub struct Records<'a> {
+    data: &'a [u8],
+    entries: std::slice::Iter<'a, IndexEntry>,
+    bytes_per_sample: Vec<usize>,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        Some(read_record(self.data, entry.offset as usize, &self.bytes_per_sample))
+    }
+}